@@ -1,7 +1,7 @@
 use clap::{App, Arg};
-use easy_fs::{BlockDevice, EasyFileSystem};
+use easy_fs::{BlockDevice, BlockMapping, EasyFileSystem, IoError, IoResult};
 use std::fs::{read_dir, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::sync::Arc;
 use std::sync::Mutex;
 
@@ -9,19 +9,32 @@ const BLOCK_SZ: usize = 512;
 
 struct BlockFile(Mutex<File>);
 
+fn block_error(error: Error) -> IoError {
+    match error.kind() {
+        ErrorKind::UnexpectedEof | ErrorKind::WriteZero => IoError::ShortTransfer,
+        _ => IoError::Device,
+    }
+}
+
+fn image_error(error: IoError) -> Error {
+    Error::new(ErrorKind::Other, format!("I/O error on the disk image: {:?}", error))
+}
+
 impl BlockDevice for BlockFile {
-    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> IoResult<()> {
+        assert_eq!(buf.len(), BLOCK_SZ, "Not a complete block!");
         let mut file = self.0.lock().unwrap();
         file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
-            .expect("Error when seeking!");
-        assert_eq!(file.read(buf).unwrap(), BLOCK_SZ, "Not a complete block!");
+            .map_err(block_error)?;
+        file.read_exact(buf).map_err(block_error)
     }
 
-    fn write_block(&self, block_id: usize, buf: &[u8]) {
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> IoResult<()> {
+        assert_eq!(buf.len(), BLOCK_SZ, "Not a complete block!");
         let mut file = self.0.lock().unwrap();
         file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
-            .expect("Error when seeking!");
-        assert_eq!(file.write(buf).unwrap(), BLOCK_SZ, "Not a complete block!");
+            .map_err(block_error)?;
+        file.write_all(buf).map_err(block_error)
     }
 }
 
@@ -59,7 +72,7 @@ fn easy_fs_pack() -> std::io::Result<()> {
         f
     })));
     // 4MiB, at most 4095 files
-    let efs = EasyFileSystem::create(block_file.clone(), 16384, 1);
+    let efs = EasyFileSystem::create(block_file.clone(), 16384, 1, BlockMapping::Indirect).map_err(image_error)?;
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
     for dir_entry in read_dir(src_path).unwrap() {
         let dir_entry = dir_entry.unwrap();
@@ -70,12 +83,12 @@ fn easy_fs_pack() -> std::io::Result<()> {
         host_file.read_to_end(&mut all_data).unwrap();
         // create a file in easy-fs
         let name = path.file_stem().unwrap().to_str().unwrap();
-        let inode = root_inode.create(name).unwrap();
+        let inode = root_inode.create(name).map_err(image_error)?.unwrap();
         // write data to easy-fs
-        inode.write_at(0, all_data.as_slice());
+        inode.write_at(0, all_data.as_slice()).map_err(image_error)?;
     }
     // list apps
-    for app in root_inode.ls() {
+    for app in root_inode.ls().map_err(image_error)? {
         println!("{}", app);
     }
     Ok(())
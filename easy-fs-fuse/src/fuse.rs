@@ -0,0 +1,874 @@
+//! Serve an easy-fs image to the host kernel through the FUSE protocol.
+//!
+//! Requests are read from `/dev/fuse` one at a time and answered by `FuseFs`,
+//! which only deals with bytes, so it can be driven without a mount as well.
+
+use easy_fs::{Inode, IoError, Metadata, SYMLINK_MAX_LEN, XATTR_NAME_MAX};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Result};
+use std::mem::size_of;
+use std::os::raw::c_int;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The version of the protocol implemented
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+/// Node id of the root directory, whose inode id is 0
+pub const FUSE_ROOT_ID: u64 = 1;
+/// The max size of the data of a write request
+const MAX_WRITE: usize = 128 * 1024;
+/// Seconds for which the kernel may cache entries and attributes
+const TTL: u64 = 1;
+/// Writes may be larger than a page
+const FUSE_BIG_WRITES: u32 = 1 << 5;
+
+pub const FUSE_LOOKUP: u32 = 1;
+pub const FUSE_FORGET: u32 = 2;
+pub const FUSE_GETATTR: u32 = 3;
+pub const FUSE_SETATTR: u32 = 4;
+pub const FUSE_READLINK: u32 = 5;
+pub const FUSE_SYMLINK: u32 = 6;
+pub const FUSE_MKDIR: u32 = 9;
+pub const FUSE_UNLINK: u32 = 10;
+pub const FUSE_RMDIR: u32 = 11;
+pub const FUSE_OPEN: u32 = 14;
+pub const FUSE_READ: u32 = 15;
+pub const FUSE_WRITE: u32 = 16;
+pub const FUSE_STATFS: u32 = 17;
+pub const FUSE_RELEASE: u32 = 18;
+pub const FUSE_FSYNC: u32 = 20;
+pub const FUSE_SETXATTR: u32 = 21;
+pub const FUSE_GETXATTR: u32 = 22;
+pub const FUSE_LISTXATTR: u32 = 23;
+pub const FUSE_REMOVEXATTR: u32 = 24;
+pub const FUSE_FLUSH: u32 = 25;
+pub const FUSE_INIT: u32 = 26;
+pub const FUSE_OPENDIR: u32 = 27;
+pub const FUSE_READDIR: u32 = 28;
+pub const FUSE_RELEASEDIR: u32 = 29;
+pub const FUSE_FSYNCDIR: u32 = 30;
+pub const FUSE_ACCESS: u32 = 34;
+pub const FUSE_CREATE: u32 = 35;
+pub const FUSE_DESTROY: u32 = 38;
+pub const FUSE_BATCH_FORGET: u32 = 42;
+
+/// Attributes to change in a setattr request
+const FATTR_MODE: u32 = 1 << 0;
+const FATTR_UID: u32 = 1 << 1;
+const FATTR_GID: u32 = 1 << 2;
+const FATTR_SIZE: u32 = 1 << 3;
+const FATTR_ATIME: u32 = 1 << 4;
+const FATTR_MTIME: u32 = 1 << 5;
+const FATTR_ATIME_NOW: u32 = 1 << 7;
+const FATTR_MTIME_NOW: u32 = 1 << 8;
+
+/// Flags of a setxattr request
+const XATTR_CREATE: u32 = 1;
+const XATTR_REPLACE: u32 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct InHeader {
+    pub len: u32,
+    pub opcode: u32,
+    pub unique: u64,
+    pub nodeid: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct OutHeader {
+    pub len: u32,
+    pub error: i32,
+    pub unique: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct InitIn {
+    pub major: u32,
+    pub minor: u32,
+    pub max_readahead: u32,
+    pub flags: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct InitOut {
+    pub major: u32,
+    pub minor: u32,
+    pub max_readahead: u32,
+    pub flags: u32,
+    pub max_background: u16,
+    pub congestion_threshold: u16,
+    pub max_write: u32,
+    pub time_gran: u32,
+    pub max_pages: u16,
+    pub map_alignment: u16,
+    pub flags2: u32,
+    pub unused: [u32; 7],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Attr {
+    pub ino: u64,
+    pub size: u64,
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    pub atimensec: u32,
+    pub mtimensec: u32,
+    pub ctimensec: u32,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u32,
+    pub blksize: u32,
+    pub flags: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct EntryOut {
+    pub nodeid: u64,
+    pub generation: u64,
+    pub entry_valid: u64,
+    pub attr_valid: u64,
+    pub entry_valid_nsec: u32,
+    pub attr_valid_nsec: u32,
+    pub attr: Attr,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct AttrOut {
+    pub attr_valid: u64,
+    pub attr_valid_nsec: u32,
+    pub dummy: u32,
+    pub attr: Attr,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SetattrIn {
+    pub valid: u32,
+    pub padding: u32,
+    pub fh: u64,
+    pub size: u64,
+    pub lock_owner: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    pub atimensec: u32,
+    pub mtimensec: u32,
+    pub ctimensec: u32,
+    pub mode: u32,
+    pub unused4: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub unused5: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct OpenOut {
+    pub fh: u64,
+    pub open_flags: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct CreateIn {
+    pub flags: u32,
+    pub mode: u32,
+    pub umask: u32,
+    pub open_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct MkdirIn {
+    pub mode: u32,
+    pub umask: u32,
+}
+
+/// The body of both read and readdir requests
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct ReadIn {
+    pub fh: u64,
+    pub offset: u64,
+    pub size: u32,
+    pub read_flags: u32,
+    pub lock_owner: u64,
+    pub flags: u32,
+    pub padding: u32,
+}
+
+/// The body of a write request, followed by the data
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct WriteIn {
+    pub fh: u64,
+    pub offset: u64,
+    pub size: u32,
+    pub write_flags: u32,
+    pub lock_owner: u64,
+    pub flags: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct WriteOut {
+    pub size: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct StatfsOut {
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub bsize: u32,
+    pub namelen: u32,
+    pub frsize: u32,
+    pub padding: u32,
+    pub spare: [u32; 6],
+}
+
+/// A directory entry in the reply to readdir, followed by the name padded to 8 bytes
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Dirent {
+    pub ino: u64,
+    pub off: u64,
+    pub namelen: u32,
+    pub type_: u32,
+}
+
+/// Followed by the name and the value
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SetxattrIn {
+    pub size: u32,
+    pub flags: u32,
+}
+
+/// Followed by the name, a size of 0 asks for the size of the value or the names only
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct GetxattrIn {
+    pub size: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct GetxattrOut {
+    pub size: u32,
+    pub padding: u32,
+}
+
+/// View a protocol struct as bytes
+pub fn as_bytes<T: Copy>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+/// Read a protocol struct from the front of `data`
+pub fn read_struct<T: Copy + Default>(data: &[u8]) -> core::result::Result<T, c_int> {
+    if data.len() < size_of::<T>() {
+        return Err(libc::EINVAL);
+    }
+    Ok(unsafe { core::ptr::read_unaligned(data.as_ptr() as *const T) })
+}
+
+/// Read a name terminated by 0 from the front of `data`
+fn read_name(data: &[u8]) -> core::result::Result<&str, c_int> {
+    let len = data.iter().position(|b| *b == 0).ok_or(libc::EINVAL)?;
+    core::str::from_utf8(&data[..len]).map_err(|_| libc::EINVAL)
+}
+
+/// Node id of an inode
+fn node_id(inode: &Inode) -> u64 {
+    inode.inode_id() as u64 + FUSE_ROOT_ID
+}
+
+/// Get the errno of an I/O error of the image
+fn errno(_: IoError) -> c_int {
+    libc::EIO
+}
+
+/// Attributes of an inode from its metadata
+fn attr(metadata: &Metadata) -> Attr {
+    let kind = if metadata.is_dir {
+        libc::S_IFDIR
+    } else if metadata.is_symlink {
+        libc::S_IFLNK
+    } else {
+        libc::S_IFREG
+    };
+    Attr {
+        ino: metadata.inode_id as u64 + FUSE_ROOT_ID,
+        size: metadata.size as u64,
+        // in units of 512 bytes
+        blocks: (metadata.size as u64 + 511) >> 9,
+        atime: metadata.atime,
+        mtime: metadata.mtime,
+        ctime: metadata.ctime,
+        mode: kind | metadata.mode,
+        nlink: metadata.nlink,
+        uid: metadata.uid,
+        gid: metadata.gid,
+        blksize: easy_fs::BLOCK_SZ as u32,
+        ..Default::default()
+    }
+}
+
+/// Build the reply to a request from the result of handling it
+fn reply(unique: u64, result: core::result::Result<Vec<u8>, c_int>) -> Vec<u8> {
+    let (error, data) = match result {
+        Ok(data) => (0, data),
+        Err(errno) => (-errno, Vec::new()),
+    };
+    let header = OutHeader {
+        len: (size_of::<OutHeader>() + data.len()) as u32,
+        error,
+        unique,
+    };
+    let mut bytes = as_bytes(&header).to_vec();
+    bytes.extend_from_slice(&data);
+    bytes
+}
+
+/// An easy-fs image served to the kernel
+pub struct FuseFs {
+    /// inodes the kernel knows of by node id
+    inodes: BTreeMap<u64, Arc<Inode>>,
+    /// source of the current time in seconds
+    clock: fn() -> u64,
+}
+
+impl FuseFs {
+    pub fn new(root_inode: Arc<Inode>, clock: fn() -> u64) -> Self {
+        let mut inodes = BTreeMap::new();
+        inodes.insert(FUSE_ROOT_ID, root_inode);
+        Self { inodes, clock }
+    }
+    /// Get an inode the kernel knows of
+    fn inode(&self, nodeid: u64) -> core::result::Result<Arc<Inode>, c_int> {
+        self.inodes.get(&nodeid).cloned().ok_or(libc::ENOENT)
+    }
+    /// Let the kernel know of an inode
+    fn entry(&mut self, inode: Arc<Inode>) -> core::result::Result<EntryOut, c_int> {
+        let metadata = inode.metadata().map_err(errno)?;
+        // inodes are looked up by id, so one of them is as good as another
+        self.inodes.entry(node_id(&inode)).or_insert(inode);
+        Ok(EntryOut {
+            nodeid: metadata.inode_id as u64 + FUSE_ROOT_ID,
+            entry_valid: TTL,
+            attr_valid: TTL,
+            attr: attr(&metadata),
+            ..Default::default()
+        })
+    }
+    /// Handle a request, return the reply or None if the request has none
+    pub fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let header: InHeader = match read_struct(request) {
+            Ok(header) => header,
+            Err(_) => return None,
+        };
+        let end = (header.len as usize).min(request.len());
+        let body = &request[size_of::<InHeader>().min(end)..end];
+        let result = match header.opcode {
+            FUSE_FORGET | FUSE_BATCH_FORGET => return None,
+            FUSE_INIT => self.init(body),
+            FUSE_LOOKUP => self.lookup(header.nodeid, body),
+            FUSE_GETATTR => self.getattr(header.nodeid),
+            FUSE_SETATTR => self.setattr(header.nodeid, body),
+            FUSE_READLINK => self.readlink(header.nodeid),
+            FUSE_SYMLINK => self.symlink(header.nodeid, body),
+            FUSE_MKDIR => self.mkdir(header.nodeid, body),
+            FUSE_UNLINK => self.unlink(header.nodeid, body),
+            FUSE_RMDIR => self.rmdir(header.nodeid, body),
+            FUSE_OPEN | FUSE_OPENDIR => self
+                .inode(header.nodeid)
+                .map(|_| as_bytes(&OpenOut::default()).to_vec()),
+            FUSE_READ => self.read(header.nodeid, body),
+            FUSE_WRITE => self.write(header.nodeid, body),
+            FUSE_STATFS => self.statfs(),
+            FUSE_RELEASE | FUSE_RELEASEDIR | FUSE_FLUSH | FUSE_FSYNC | FUSE_FSYNCDIR
+            | FUSE_ACCESS | FUSE_DESTROY => Ok(Vec::new()),
+            FUSE_READDIR => self.readdir(header.nodeid, body),
+            FUSE_CREATE => self.create(header.nodeid, body),
+            FUSE_SETXATTR => self.setxattr(header.nodeid, body),
+            FUSE_GETXATTR => self.getxattr(header.nodeid, body),
+            FUSE_LISTXATTR => self.listxattr(header.nodeid, body),
+            FUSE_REMOVEXATTR => self.removexattr(header.nodeid, body),
+            _ => Err(libc::ENOSYS),
+        };
+        Some(reply(header.unique, result))
+    }
+    fn init(&mut self, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let init_in: InitIn = read_struct(body)?;
+        if init_in.major < FUSE_KERNEL_VERSION {
+            return Err(libc::EPROTO);
+        }
+        let init_out = InitOut {
+            major: FUSE_KERNEL_VERSION,
+            minor: FUSE_KERNEL_MINOR_VERSION,
+            max_readahead: init_in.max_readahead,
+            flags: init_in.flags & FUSE_BIG_WRITES,
+            max_background: 16,
+            congestion_threshold: 12,
+            max_write: MAX_WRITE as u32,
+            // times are in seconds
+            time_gran: 1_000_000_000,
+            ..Default::default()
+        };
+        Ok(as_bytes(&init_out).to_vec())
+    }
+    fn lookup(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let dir = self.inode(nodeid)?;
+        let child = dir.find(read_name(body)?).map_err(errno)?.ok_or(libc::ENOENT)?;
+        Ok(as_bytes(&self.entry(child)?).to_vec())
+    }
+    fn getattr(&mut self, nodeid: u64) -> core::result::Result<Vec<u8>, c_int> {
+        let attr_out = AttrOut {
+            attr_valid: TTL,
+            attr: attr(&self.inode(nodeid)?.metadata().map_err(errno)?),
+            ..Default::default()
+        };
+        Ok(as_bytes(&attr_out).to_vec())
+    }
+    fn setattr(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let inode = self.inode(nodeid)?;
+        let setattr_in: SetattrIn = read_struct(body)?;
+        let valid = setattr_in.valid;
+        if valid & FATTR_SIZE != 0 {
+            if inode.is_dir().map_err(errno)? {
+                return Err(libc::EISDIR);
+            }
+            if setattr_in.size > u32::MAX as u64
+                || !inode.truncate(setattr_in.size as u32).map_err(errno)? {
+                return Err(libc::EFBIG);
+            }
+        }
+        if valid & FATTR_MODE != 0 {
+            inode.set_mode(setattr_in.mode).map_err(errno)?;
+        }
+        if valid & (FATTR_UID | FATTR_GID) != 0 {
+            let metadata = inode.metadata().map_err(errno)?;
+            let uid = if valid & FATTR_UID != 0 { setattr_in.uid } else { metadata.uid };
+            let gid = if valid & FATTR_GID != 0 { setattr_in.gid } else { metadata.gid };
+            inode.set_owner(uid, gid).map_err(errno)?;
+        }
+        if valid & (FATTR_ATIME | FATTR_MTIME | FATTR_ATIME_NOW | FATTR_MTIME_NOW) != 0 {
+            let metadata = inode.metadata().map_err(errno)?;
+            let now = (self.clock)();
+            let time = |set: u32, set_now: u32, time: u64, current: u64| {
+                if valid & set_now != 0 {
+                    now
+                } else if valid & set != 0 {
+                    time
+                } else {
+                    current
+                }
+            };
+            inode.set_times(
+                time(FATTR_ATIME, FATTR_ATIME_NOW, setattr_in.atime, metadata.atime),
+                time(FATTR_MTIME, FATTR_MTIME_NOW, setattr_in.mtime, metadata.mtime),
+            ).map_err(errno)?;
+        }
+        self.getattr(nodeid)
+    }
+    /// Find out why an inode named `name` cannot be created under a directory
+    fn create_error(dir: &Inode, name: &str) -> c_int {
+        let name_length_limit = match (dir.find(name), dir.fs_stat()) {
+            (Ok(None), Ok(stat)) => stat.name_length_limit as usize,
+            (Ok(Some(_)), _) => return libc::EEXIST,
+            _ => return libc::EIO,
+        };
+        if name.len() > name_length_limit {
+            libc::ENAMETOOLONG
+        } else {
+            libc::ENOSPC
+        }
+    }
+    fn mkdir(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let dir = self.inode(nodeid)?;
+        let mkdir_in: MkdirIn = read_struct(body)?;
+        let name = read_name(&body[size_of::<MkdirIn>()..])?;
+        let child = dir
+            .create_dir(name)
+            .map_err(errno)?
+            .ok_or_else(|| Self::create_error(&dir, name))?;
+        child.set_mode(mkdir_in.mode & !mkdir_in.umask).map_err(errno)?;
+        Ok(as_bytes(&self.entry(child)?).to_vec())
+    }
+    fn create(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let dir = self.inode(nodeid)?;
+        let create_in: CreateIn = read_struct(body)?;
+        let name = read_name(&body[size_of::<CreateIn>()..])?;
+        let child = dir
+            .create(name)
+            .map_err(errno)?
+            .ok_or_else(|| Self::create_error(&dir, name))?;
+        child.set_mode(create_in.mode & !create_in.umask).map_err(errno)?;
+        let mut bytes = as_bytes(&self.entry(child)?).to_vec();
+        bytes.extend_from_slice(as_bytes(&OpenOut::default()));
+        Ok(bytes)
+    }
+    fn readlink(&mut self, nodeid: u64) -> core::result::Result<Vec<u8>, c_int> {
+        let target = self.inode(nodeid)?.readlink().map_err(errno)?.ok_or(libc::EINVAL)?;
+        Ok(target.into_bytes())
+    }
+    fn symlink(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let dir = self.inode(nodeid)?;
+        // the name of the link is followed by its target
+        let name = read_name(body)?;
+        let target = read_name(&body[name.len() + 1..])?;
+        if target.is_empty() {
+            return Err(libc::ENOENT);
+        }
+        if target.len() > SYMLINK_MAX_LEN {
+            return Err(libc::ENAMETOOLONG);
+        }
+        let child = dir
+            .symlink(target, name)
+            .map_err(errno)?
+            .ok_or_else(|| Self::create_error(&dir, name))?;
+        Ok(as_bytes(&self.entry(child)?).to_vec())
+    }
+    fn unlink(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let dir = self.inode(nodeid)?;
+        let name = read_name(body)?;
+        if dir.find(name).map_err(errno)?.ok_or(libc::ENOENT)?.is_dir().map_err(errno)? {
+            return Err(libc::EISDIR);
+        }
+        if dir.unlink(name).map_err(errno)? { Ok(Vec::new()) } else { Err(libc::EIO) }
+    }
+    fn rmdir(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let dir = self.inode(nodeid)?;
+        let name = read_name(body)?;
+        if !dir.find(name).map_err(errno)?.ok_or(libc::ENOENT)?.is_dir().map_err(errno)? {
+            return Err(libc::ENOTDIR);
+        }
+        if dir.rmdir(name).map_err(errno)? { Ok(Vec::new()) } else { Err(libc::ENOTEMPTY) }
+    }
+    fn read(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let inode = self.inode(nodeid)?;
+        let read_in: ReadIn = read_struct(body)?;
+        let mut data = vec![0u8; read_in.size as usize];
+        let len = inode.read_at(read_in.offset as usize, &mut data).map_err(errno)?;
+        data.truncate(len);
+        Ok(data)
+    }
+    fn write(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let inode = self.inode(nodeid)?;
+        let write_in: WriteIn = read_struct(body)?;
+        let data = body
+            .get(size_of::<WriteIn>()..size_of::<WriteIn>() + write_in.size as usize)
+            .ok_or(libc::EINVAL)?;
+        let size = inode.write_at(write_in.offset as usize, data).map_err(errno)?;
+        if size == 0 && !data.is_empty() {
+            return Err(libc::ENOSPC);
+        }
+        let write_out = WriteOut { size: size as u32, padding: 0 };
+        Ok(as_bytes(&write_out).to_vec())
+    }
+    fn statfs(&mut self) -> core::result::Result<Vec<u8>, c_int> {
+        let stat = self.inode(FUSE_ROOT_ID)?.fs_stat().map_err(errno)?;
+        let statfs_out = StatfsOut {
+            blocks: stat.data_blocks as u64,
+            bfree: stat.free_blocks as u64,
+            bavail: stat.free_blocks as u64,
+            files: stat.inodes as u64,
+            ffree: stat.free_inodes as u64,
+            bsize: stat.block_size,
+            namelen: stat.name_length_limit,
+            frsize: stat.block_size,
+            ..Default::default()
+        };
+        Ok(as_bytes(&statfs_out).to_vec())
+    }
+    fn setxattr(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let inode = self.inode(nodeid)?;
+        let setxattr_in: SetxattrIn = read_struct(body)?;
+        let name = read_name(&body[size_of::<SetxattrIn>()..])?;
+        let value_start = size_of::<SetxattrIn>() + name.len() + 1;
+        let value = body
+            .get(value_start..value_start + setxattr_in.size as usize)
+            .ok_or(libc::EINVAL)?;
+        let set = inode.get_xattr(name).map_err(errno)?.is_some();
+        if setxattr_in.flags & XATTR_CREATE != 0 && set {
+            return Err(libc::EEXIST);
+        }
+        if setxattr_in.flags & XATTR_REPLACE != 0 && !set {
+            return Err(libc::ENODATA);
+        }
+        if name.len() > XATTR_NAME_MAX {
+            return Err(libc::ERANGE);
+        }
+        if inode.set_xattr(name, value).map_err(errno)? { Ok(Vec::new()) } else { Err(libc::ENOSPC) }
+    }
+    /// Reply with the size of `data` if the request asks for it,
+    /// or with `data` itself if it fits in the size requested
+    fn xattr_reply(size: u32, data: Vec<u8>) -> core::result::Result<Vec<u8>, c_int> {
+        if size == 0 {
+            let getxattr_out = GetxattrOut { size: data.len() as u32, padding: 0 };
+            Ok(as_bytes(&getxattr_out).to_vec())
+        } else if data.len() > size as usize {
+            Err(libc::ERANGE)
+        } else {
+            Ok(data)
+        }
+    }
+    fn getxattr(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let inode = self.inode(nodeid)?;
+        let getxattr_in: GetxattrIn = read_struct(body)?;
+        let name = read_name(&body[size_of::<GetxattrIn>()..])?;
+        let value = inode.get_xattr(name).map_err(errno)?.ok_or(libc::ENODATA)?;
+        Self::xattr_reply(getxattr_in.size, value)
+    }
+    fn listxattr(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let inode = self.inode(nodeid)?;
+        let getxattr_in: GetxattrIn = read_struct(body)?;
+        // each name is terminated by 0
+        let mut names = Vec::new();
+        for name in inode.list_xattr().map_err(errno)? {
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        Self::xattr_reply(getxattr_in.size, names)
+    }
+    fn removexattr(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let inode = self.inode(nodeid)?;
+        if inode.remove_xattr(read_name(body)?).map_err(errno)? { Ok(Vec::new()) } else { Err(libc::ENODATA) }
+    }
+    fn readdir(&mut self, nodeid: u64, body: &[u8]) -> core::result::Result<Vec<u8>, c_int> {
+        let dir = self.inode(nodeid)?;
+        let read_in: ReadIn = read_struct(body)?;
+        if !dir.is_dir().map_err(errno)? {
+            return Err(libc::ENOTDIR);
+        }
+        let mut names = vec![String::from("."), String::from("..")];
+        names.extend(dir.ls().map_err(errno)?);
+        let mut data = Vec::new();
+        // the offset of an entry is the index of the next one
+        for (i, name) in names.iter().enumerate().skip(read_in.offset as usize) {
+            let inode = match dir.find(name).map_err(errno)? {
+                Some(inode) => inode,
+                None => continue,
+            };
+            let metadata = inode.metadata().map_err(errno)?;
+            let type_ = if metadata.is_dir {
+                libc::DT_DIR
+            } else if metadata.is_symlink {
+                libc::DT_LNK
+            } else {
+                libc::DT_REG
+            };
+            let dirent = Dirent {
+                ino: node_id(&inode),
+                off: i as u64 + 1,
+                namelen: name.len() as u32,
+                type_: type_ as u32,
+            };
+            // entries are aligned to 8 bytes
+            let len = (size_of::<Dirent>() + name.len() + 7) & !7;
+            if data.len() + len > read_in.size as usize {
+                break;
+            }
+            let start = data.len();
+            data.extend_from_slice(as_bytes(&dirent));
+            data.extend_from_slice(name.as_bytes());
+            data.resize(start + len, 0);
+        }
+        Ok(data)
+    }
+}
+
+/// Set by SIGINT and SIGTERM to unmount the image
+static UNMOUNT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_unmount(_signal: c_int) {
+    UNMOUNT_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Convert a path into a C string
+fn c_path(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "path contains a nul byte"))
+}
+
+/// Mount the FUSE device at `mountpoint` by the mount syscall, which requires privilege
+fn mount_directly(mountpoint: &Path) -> Result<RawFd> {
+    let device = CString::new("/dev/fuse").unwrap();
+    let fd = unsafe { libc::open(device.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+    let options = CString::new(format!(
+        "fd={},rootmode=40000,user_id={},group_id={},default_permissions",
+        fd,
+        unsafe { libc::getuid() },
+        unsafe { libc::getgid() },
+    ))
+    .unwrap();
+    let source = CString::new("easy-fs").unwrap();
+    let fstype = CString::new("fuse.easy-fs").unwrap();
+    let target = c_path(mountpoint)?;
+    let ret = unsafe {
+        libc::mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            fstype.as_ptr(),
+            libc::MS_NOSUID | libc::MS_NODEV,
+            options.as_ptr() as *const libc::c_void,
+        )
+    };
+    if ret < 0 {
+        let error = Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(error);
+    }
+    Ok(fd)
+}
+
+/// Mount the FUSE device at `mountpoint` by the setuid helper `fusermount`,
+/// which passes the opened device back through a socket
+fn mount_with_fusermount(mountpoint: &Path) -> Result<RawFd> {
+    let mut sockets = [0 as c_int; 2];
+    if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, sockets.as_mut_ptr()) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    let status = ["fusermount3", "fusermount"].iter().find_map(|helper| {
+        Command::new(helper)
+            .args(["-o", "default_permissions,fsname=easy-fs,subtype=easy-fs", "--"])
+            .arg(mountpoint)
+            .env("_FUSE_COMMFD", sockets[0].to_string())
+            .status()
+            .ok()
+    });
+    unsafe { libc::close(sockets[0]) };
+    let fd = match status {
+        Some(status) if status.success() => receive_fd(sockets[1]),
+        Some(_) => Err(Error::new(ErrorKind::Other, "fusermount failed")),
+        None => Err(Error::new(ErrorKind::NotFound, "fusermount is not installed")),
+    };
+    unsafe { libc::close(sockets[1]) };
+    fd
+}
+
+/// Receive a file descriptor sent through a unix socket
+fn receive_fd(socket: RawFd) -> Result<RawFd> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+    // aligned for cmsghdr
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { core::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = core::mem::size_of_val(&control) as _;
+    if unsafe { libc::recvmsg(socket, &mut msg, 0) } <= 0 {
+        return Err(Error::last_os_error());
+    }
+    let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    if cmsg.is_null() || unsafe { (*cmsg).cmsg_type } != libc::SCM_RIGHTS {
+        return Err(Error::new(ErrorKind::Other, "no FUSE device received from fusermount"));
+    }
+    Ok(unsafe { core::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const c_int) })
+}
+
+/// Mount the FUSE device at `mountpoint`, return the file descriptor to serve it through
+pub fn mount(mountpoint: &Path) -> Result<RawFd> {
+    match mount_directly(mountpoint) {
+        Err(error) if error.raw_os_error() == Some(libc::EPERM) => {
+            mount_with_fusermount(mountpoint)
+        }
+        result => result,
+    }
+}
+
+/// Unmount `mountpoint`, lazily since files may still be open
+pub fn unmount(mountpoint: &Path) -> Result<()> {
+    let target = c_path(mountpoint)?;
+    if unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } == 0 {
+        return Ok(());
+    }
+    let status = ["fusermount3", "fusermount"].iter().find_map(|helper| {
+        Command::new(helper).arg("-u").arg("-z").arg(mountpoint).status().ok()
+    });
+    match status {
+        Some(status) if status.success() => Ok(()),
+        _ => Err(Error::new(ErrorKind::Other, "cannot unmount")),
+    }
+}
+
+/// Serve requests through the FUSE device until the image is unmounted,
+/// which is done on SIGINT or SIGTERM as well
+pub fn serve(fd: RawFd, fs: &mut FuseFs, mountpoint: &Path) -> Result<()> {
+    // no SA_RESTART, so that a blocked read returns once a signal arrives
+    let mut action: libc::sigaction = unsafe { core::mem::zeroed() };
+    action.sa_sigaction = request_unmount as extern "C" fn(c_int) as libc::sighandler_t;
+    unsafe {
+        libc::sigaction(libc::SIGINT, &action, core::ptr::null_mut());
+        libc::sigaction(libc::SIGTERM, &action, core::ptr::null_mut());
+    }
+    let mut buffer = vec![0u8; MAX_WRITE + 4096];
+    loop {
+        let len = unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+        if len < 0 {
+            let error = Error::last_os_error();
+            match error.raw_os_error() {
+                // unmounted
+                Some(libc::ENODEV) => break,
+                Some(libc::EINTR) => {
+                    if UNMOUNT_REQUESTED.swap(false, Ordering::SeqCst) {
+                        unmount(mountpoint)?;
+                    }
+                }
+                // the request was interrupted
+                Some(libc::ENOENT) | Some(libc::EAGAIN) => {}
+                _ => {
+                    unsafe { libc::close(fd) };
+                    return Err(error);
+                }
+            }
+            continue;
+        }
+        let request = &buffer[..len as usize];
+        let opcode = read_struct::<InHeader>(request).map_or(0, |header| header.opcode);
+        if let Some(reply) = fs.handle(request) {
+            // the reply to an interrupted request is refused, which is fine
+            unsafe { libc::write(fd, reply.as_ptr() as *const libc::c_void, reply.len()) };
+        }
+        if opcode == FUSE_DESTROY {
+            break;
+        }
+    }
+    unsafe { libc::close(fd) };
+    Ok(())
+}
@@ -1,41 +1,78 @@
-use clap::{App, Arg};
-use easy_fs::{BlockDevice, EasyFileSystem};
-use std::fs::{read_dir, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+mod fuse;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use easy_fs::{
+    BlockDevice, BlockMapping, DirEntry, EasyFileSystem, FsGeometry, Inode, IoError, IoResult, DIRENT_SZ,
+};
+use std::fs::{create_dir_all, read_dir, set_permissions, File, Metadata, OpenOptions, Permissions};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Use a block size of 512 bytes
 const BLOCK_SZ: usize = 512;
 const BLOCK_NUM: usize = 131072; //64*2048
+/// Number of inodes of an image by default
+const INODE_NUM: u32 = 4096;
 
 /// Wrapper for turning a File into a BlockDevice
 struct BlockFile(Mutex<File>);
 
+/// Get the error of a block request from the error of the file behind it
+fn block_error(error: Error) -> IoError {
+    match error.kind() {
+        ErrorKind::UnexpectedEof | ErrorKind::WriteZero => IoError::ShortTransfer,
+        _ => IoError::Device,
+    }
+}
+
+/// Get the error of an operation on a disk image from the error of its block device
+fn image_error(error: IoError) -> Error {
+    Error::new(ErrorKind::Other, format!("I/O error on the disk image: {:?}", error))
+}
+
 impl BlockDevice for BlockFile {
     /// Read a block from file
-    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> IoResult<()> {
+        assert_eq!(buf.len(), BLOCK_SZ, "Not a complete block!");
+        self.read_blocks(block_id, buf)
+    }
+    /// Write a block into file
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> IoResult<()> {
+        assert_eq!(buf.len(), BLOCK_SZ, "Not a complete block!");
+        self.write_blocks(block_id, buf)
+    }
+    /// Read consecutive blocks from file
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> IoResult<()> {
         let mut file = self.0.lock().unwrap();
         file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
-            .expect("Error when seeking!");
-        assert_eq!(file.read(buf).unwrap(), BLOCK_SZ, "Not a complete block!");
+            .map_err(block_error)?;
+        file.read_exact(buf).map_err(block_error)
     }
-    /// Write a block into file
-    fn write_block(&self, block_id: usize, buf: &[u8]) {
+    /// Write consecutive blocks into file
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> IoResult<()> {
         let mut file = self.0.lock().unwrap();
         file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
-            .expect("Error when seeking!");
-        assert_eq!(file.write(buf).unwrap(), BLOCK_SZ, "Not a complete block!");
+            .map_err(block_error)?;
+        file.write_all(buf).map_err(block_error)
     }
 }
 
-fn main() {
-    easy_fs_pack().expect("Error when packing easy-fs!");
+/// The argument of the disk image of a subcommand
+fn image_arg() -> Arg<'static, 'static> {
+    Arg::with_name("image")
+        .required(true)
+        .help("Path of the disk image")
 }
 
-/// Pack a directory into a easy-fs disk image
-fn easy_fs_pack() -> std::io::Result<()> {
-    let matches = App::new("EasyFileSystem packer")
+/// The command line of the packer and its subcommands
+fn app() -> App<'static, 'static> {
+    App::new("EasyFileSystem packer")
         .arg(
             Arg::with_name("source")
                 .short("s")
@@ -50,47 +87,611 @@ fn easy_fs_pack() -> std::io::Result<()> {
                 .takes_value(true)
                 .help("Executable target dir(with backslash)"),
         )
-        .get_matches();
-    let src_path = matches.value_of("source").unwrap();
-    let target_path = matches.value_of("target").unwrap();
-    println!("src_path = {}\ntarget_path = {}", src_path, target_path);
+        .arg(
+            Arg::with_name("dir")
+                .short("d")
+                .long("dir")
+                .takes_value(true)
+                .help("Host directory whose tree is mirrored into the root of the image"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("Path of the image, fs.img in the target dir by default"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .conflicts_with("auto-size")
+                .help("Size of the image in bytes, with an optional suffix K, M or G, 64M by default"),
+        )
+        .arg(
+            Arg::with_name("inodes")
+                .long("inodes")
+                .takes_value(true)
+                .help("Number of inodes of the image, 4096 by default or as few as needed with --auto-size"),
+        )
+        .arg(
+            Arg::with_name("auto-size")
+                .long("auto-size")
+                .help("Make the image as small as the executables allow"),
+        )
+        .arg(
+            Arg::with_name("extents")
+                .long("extents")
+                .help("Map the data of files by extents, which allows larger files stored consecutively"),
+        )
+        .arg(
+            Arg::with_name("xattr")
+                .long("xattr")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATH:NAME=VALUE")
+                .help("Set an extended attribute of a packed file, may be given more than once"),
+        )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("Check the consistency of an easy-fs disk image")
+                .arg(image_arg())
+                .arg(
+                    Arg::with_name("repair")
+                        .short("r")
+                        .long("repair")
+                        .help("Repair the problems found"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List the tree under a directory of an easy-fs disk image")
+                .arg(image_arg())
+                .arg(Arg::with_name("path").help("Path of the directory, the root by default")),
+        )
+        .subcommand(
+            SubCommand::with_name("cat")
+                .about("Print a file of an easy-fs disk image")
+                .arg(image_arg())
+                .arg(Arg::with_name("path").required(true).help("Path of the file")),
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Extract all files of an easy-fs disk image to a host directory")
+                .arg(image_arg())
+                .arg(Arg::with_name("dir").required(true).help("Path of the host directory")),
+        )
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Add a host file to an easy-fs disk image, replacing an existing one")
+                .arg(image_arg())
+                .arg(Arg::with_name("file").required(true).help("Path of the host file"))
+                .arg(Arg::with_name("path").help("Path in the image, the file name by default")),
+        )
+        .subcommand(
+            SubCommand::with_name("mount")
+                .about("Mount an easy-fs disk image on the host until it is unmounted")
+                .arg(image_arg())
+                .arg(Arg::with_name("mountpoint").required(true).help("Path of the mount point")),
+        )
+        .subcommand(
+            SubCommand::with_name("remove")
+                .about("Remove a file or an empty directory from an easy-fs disk image")
+                .arg(image_arg())
+                .arg(Arg::with_name("path").required(true).help("Path in the image")),
+        )
+}
+
+fn main() {
+    let matches = app().get_matches();
+    match matches.subcommand() {
+        ("fsck", Some(matches)) => {
+            if !easy_fs_fsck(matches).expect("Error when checking easy-fs!") {
+                std::process::exit(1);
+            }
+        }
+        ("list", Some(matches)) => easy_fs_list(matches).expect("Error when listing easy-fs!"),
+        ("cat", Some(matches)) => easy_fs_cat(matches).expect("Error when reading easy-fs!"),
+        ("extract", Some(matches)) => {
+            easy_fs_extract(matches).expect("Error when extracting easy-fs!")
+        }
+        ("add", Some(matches)) => easy_fs_add(matches).expect("Error when adding to easy-fs!"),
+        ("remove", Some(matches)) => {
+            easy_fs_remove(matches).expect("Error when removing from easy-fs!")
+        }
+        ("mount", Some(matches)) => easy_fs_mount(matches).expect("Error when mounting easy-fs!"),
+        _ => easy_fs_pack(&matches).expect("Error when packing easy-fs!"),
+    }
+}
+
+/// A file, directory or symbolic link to pack into an image
+struct PackEntry {
+    /// absolute path in the image
+    path: String,
+    host_path: PathBuf,
+    is_dir: bool,
+    /// target path of a symbolic link
+    symlink: Option<String>,
+}
+
+/// Collect the entries of the tree under a host directory, parents before children
+fn collect_tree(host_dir: &Path, dir_path: &str, entries: &mut Vec<PackEntry>) -> std::io::Result<()> {
+    let mut host_entries = read_dir(host_dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    host_entries.sort_by_key(|host_entry| host_entry.file_name());
+    for host_entry in host_entries {
+        let name = host_entry.file_name().into_string().map_err(|name| Error::new(
+            ErrorKind::InvalidInput,
+            format!("{:?}: the name is not valid UTF-8", name),
+        ))?;
+        let path = format!("{}/{}", dir_path, name);
+        let host_path = host_entry.path();
+        // symbolic links are packed as they are, so that aliases keep referring to their targets
+        let metadata = std::fs::symlink_metadata(&host_path)?;
+        if metadata.file_type().is_symlink() {
+            let target = std::fs::read_link(&host_path)?.into_os_string().into_string().map_err(|target| {
+                Error::new(ErrorKind::InvalidInput, format!("{:?}: the target is not valid UTF-8", target))
+            })?;
+            entries.push(PackEntry { path, host_path, is_dir: false, symlink: Some(target) });
+        } else if metadata.is_dir() {
+            entries.push(PackEntry { path: path.clone(), host_path: host_path.clone(), is_dir: true, symlink: None });
+            collect_tree(&host_path, &path, entries)?;
+        } else if metadata.is_file() {
+            entries.push(PackEntry { path, host_path, is_dir: false, symlink: None });
+        } else {
+            println!("skipping {}: not a regular file or directory", host_path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Pack a directory into a easy-fs disk image
+fn easy_fs_pack(matches: &ArgMatches) -> std::io::Result<()> {
+    let mut entries = Vec::new();
+    if let Some(src_path) = matches.value_of("source") {
+        let target_path = matches.value_of("target").ok_or_else(|| Error::new(
+            ErrorKind::InvalidInput,
+            "the executable target dir is required along with the source dir",
+        ))?;
+        println!("src_path = {}\ntarget_path = {}", src_path, target_path);
+        for dir_entry in read_dir(src_path)? {
+            // strip the extension only, so that names may contain '.'
+            let path = dir_entry?.path();
+            let app = path.file_stem().unwrap().to_str().unwrap().to_string();
+            entries.push(PackEntry {
+                path: format!("/{}", app),
+                // load app data (elf) from host file system
+                host_path: Path::new(target_path).join(&app),
+                is_dir: false,
+                symlink: None,
+            });
+        }
+    }
+    if let Some(host_dir) = matches.value_of("dir") {
+        println!("dir = {}", host_dir);
+        collect_tree(Path::new(host_dir), "", &mut entries)?;
+    }
+    let image_path = match (matches.value_of("output"), matches.value_of("target")) {
+        (Some(output), _) => PathBuf::from(output),
+        (None, Some(target_path)) => PathBuf::from(format!("{}{}", target_path, "fs.img")),
+        (None, None) => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "either the executable target dir or the output image is required",
+            ))
+        }
+    };
+    let mapping = if matches.is_present("extents") {
+        BlockMapping::Extents
+    } else {
+        BlockMapping::Indirect
+    };
+    let geometry = pack_geometry(matches, &entries, mapping)?;
     let block_file = Arc::new(BlockFile(Mutex::new({
         let f = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len((BLOCK_NUM * BLOCK_SZ) as u64).unwrap();
+            .open(image_path)?;
+        f.set_len(geometry.total_blocks as u64 * BLOCK_SZ as u64)?;
         f
     })));
-    let efs = EasyFileSystem::create(block_file.clone(), BLOCK_NUM as u32, 1);
+    let efs = EasyFileSystem::create_with_geometry(block_file.clone(), geometry, mapping)
+        .map_err(image_error)?;
+    efs.set_clock(host_time);
+    efs.set_relax(std::thread::yield_now);
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
-    let apps: Vec<_> = read_dir(src_path)
-        .unwrap()
-        .into_iter()
-        .map(|dir_entry| {
-            let mut name_with_ext = dir_entry.unwrap().file_name().into_string().unwrap();
-            name_with_ext.drain(name_with_ext.find('.').unwrap()..name_with_ext.len());
-            name_with_ext
-        })
-        .collect();
-    for app in apps {
-        // load app data (elf) from host file system
-        let mut host_file = File::open(format!("{}{}", target_path, app)).unwrap();
-        let mut all_data: Vec<u8> = Vec::new();
-        host_file.read_to_end(&mut all_data).unwrap();
-        // create a file in easy-fs
-        let inode = root_inode.create(app.as_str()).unwrap();
-        // write data to easy-fs
-        inode.write_at(0, all_data.as_slice());
+    for entry in entries.iter() {
+        if let Some(target) = &entry.symlink {
+            if root_inode.symlink(target, &entry.path).map_err(image_error)?.is_none() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("cannot create symbolic link {}: the name or target is invalid", entry.path),
+                ));
+            }
+        } else if !entry.is_dir {
+            add_file(&root_inode, &entry.host_path, &entry.path)?;
+        } else if root_inode.create_dir(&entry.path).map_err(image_error)?.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot create directory {}: the name is invalid or taken", entry.path),
+            ));
+        }
+    }
+    for (path, name, value) in xattr_args(matches)? {
+        let inode = find_inode(&root_inode, path)?;
+        if !inode.set_xattr(name, value.as_bytes()).map_err(image_error)? {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot set attribute {} of {}: the name is invalid or the attributes do not fit in a block", name, path),
+            ));
+        }
+    }
+    // the times of a directory change as entries are added to it
+    for entry in entries.iter().rev().filter(|entry| entry.is_dir) {
+        let inode = find_inode(&root_inode, &entry.path)?;
+        copy_metadata(&std::fs::metadata(&entry.host_path)?, &inode)?;
     }
     // list apps
-    for app in root_inode.ls() {
+    for app in root_inode.ls().map_err(image_error)? {
         println!("{}", app);
     }
     Ok(())
 }
 
+/// Parse the extended attributes to set when packing into (path, name, value)
+fn xattr_args<'a>(matches: &'a ArgMatches) -> std::io::Result<Vec<(&'a str, &'a str, &'a str)>> {
+    matches
+        .values_of("xattr")
+        .into_iter()
+        .flatten()
+        .map(|arg| {
+            arg.split_once(':')
+                .and_then(|(path, xattr)| xattr.split_once('=').map(|(name, value)| (path, name, value)))
+                .ok_or_else(|| Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid attribute {}: not in the form PATH:NAME=VALUE", arg),
+                ))
+        })
+        .collect()
+}
+
+/// Parse a size in bytes with an optional binary suffix K, M or G into a number of blocks
+fn parse_size(size: &str) -> std::io::Result<u32> {
+    let invalid = |reason: &str| Error::new(
+        ErrorKind::InvalidInput,
+        format!("invalid size {}: {}", size, reason),
+    );
+    let (digits, unit) = match size.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&size[..i], 1u64 << 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&size[..i], 1 << 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    let bytes = digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| invalid("not a number of bytes"))?;
+    if bytes % BLOCK_SZ as u64 != 0 {
+        return Err(invalid(&format!("not a multiple of the block size {}", BLOCK_SZ)));
+    }
+    u32::try_from(bytes / BLOCK_SZ as u64).map_err(|_| invalid("too many blocks"))
+}
+
+/// Lay out the image to pack the entries into, whose data is mapped as `mapping`,
+/// as the command line requests, checking that the areas fit in the image
+fn pack_geometry(
+    matches: &ArgMatches,
+    entries: &[PackEntry],
+    mapping: BlockMapping,
+) -> std::io::Result<FsGeometry> {
+    let inodes = matches
+        .value_of("inodes")
+        .map(|inodes| inodes.parse::<u32>().map_err(|_| Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid number of inodes {}", inodes),
+        )))
+        .transpose()?;
+    // the root directory takes an inode as well
+    let inodes_needed = entries.len() as u32 + 1;
+    if matches!(inodes, Some(inodes) if inodes < inodes_needed) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} inodes are needed to pack {} files and directories", inodes_needed, entries.len()),
+        ));
+    }
+    let geometry = if matches.is_present("auto-size") {
+        // every directory holds '.', '..' and an entry for each child
+        let mut dir_sizes: BTreeMap<&str, usize> = BTreeMap::new();
+        dir_sizes.insert("", 2 * DIRENT_SZ);
+        let mut data_blocks = 0;
+        for entry in entries {
+            let (parent, name) = entry.path.rsplit_once('/').unwrap();
+            let dirent = DirEntry::new(name, 0).map_err(|error| Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot create {}: {:?}", entry.path, error),
+            ))?;
+            *dir_sizes.entry(parent).or_insert(2 * DIRENT_SZ) += dirent.slots() * DIRENT_SZ;
+            if let Some(target) = &entry.symlink {
+                data_blocks += EasyFileSystem::blocks_of_size(target.len() as u32, mapping);
+            } else if !entry.is_dir {
+                let size = std::fs::metadata(&entry.host_path)?.len();
+                let size = size.min(mapping.max_file_size() as u64) as u32;
+                data_blocks += EasyFileSystem::blocks_of_size(size, mapping);
+            }
+        }
+        // each file with extended attributes holds them in a block of their own
+        let xattr_paths: BTreeSet<&str> = xattr_args(matches)?.iter().map(|(path, _, _)| *path).collect();
+        data_blocks += xattr_paths.len() as u32;
+        // an empty directory holds '.' and '..' only
+        for entry in entries.iter().filter(|entry| entry.is_dir) {
+            dir_sizes.entry(&entry.path).or_insert(2 * DIRENT_SZ);
+        }
+        for dir_size in dir_sizes.values() {
+            data_blocks += EasyFileSystem::blocks_of_size(*dir_size as u32, mapping);
+        }
+        FsGeometry::with_data_blocks(inodes.unwrap_or(inodes_needed), data_blocks)
+    } else {
+        let total_blocks = match matches.value_of("size") {
+            Some(size) => parse_size(size)?,
+            None => BLOCK_NUM as u32,
+        };
+        FsGeometry::new(total_blocks, inodes.unwrap_or(INODE_NUM))
+    };
+    let geometry = geometry.ok_or_else(|| Error::new(
+        ErrorKind::InvalidInput,
+        "the super block, log, inode and data areas do not fit in the image",
+    ))?;
+    println!("{:?}", geometry);
+    Ok(geometry)
+}
+
+/// Get the current time of the host in seconds
+fn host_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Copy the permission bits, ownership and times of a host file to an inode
+fn copy_metadata(metadata: &Metadata, inode: &Inode) -> std::io::Result<()> {
+    inode.set_mode(metadata.mode()).map_err(image_error)?;
+    inode.set_owner(metadata.uid(), metadata.gid()).map_err(image_error)?;
+    inode
+        .set_times(metadata.atime().max(0) as u64, metadata.mtime().max(0) as u64)
+        .map_err(image_error)
+}
+
+/// Check an easy-fs disk image, return whether it is consistent
+/// after the repair if one is requested
+fn easy_fs_fsck(matches: &ArgMatches) -> std::io::Result<bool> {
+    let image_path = matches.value_of("image").unwrap();
+    let repair = matches.is_present("repair");
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(image_path)?,
+    )));
+    let efs = EasyFileSystem::open(block_file).map_err(image_error)?;
+    let report = EasyFileSystem::fsck(&efs, repair).map_err(image_error)?;
+    for problem in report.problems.iter() {
+        println!("{}", problem);
+    }
+    let unrepaired = report
+        .problems
+        .iter()
+        .filter(|problem| !report.repaired || !problem.is_repairable())
+        .count();
+    println!(
+        "{}: {} problems found, {} repaired",
+        image_path,
+        report.problems.len(),
+        report.problems.len() - unrepaired,
+    );
+    Ok(unrepaired == 0)
+}
+
+/// Open an existing easy-fs disk image and get its root inode
+fn open_image(image_path: &str) -> std::io::Result<Inode> {
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(image_path)?,
+    )));
+    let efs = EasyFileSystem::open(block_file).map_err(image_error)?;
+    efs.set_clock(host_time);
+    efs.set_relax(std::thread::yield_now);
+    Ok(EasyFileSystem::root_inode(&efs))
+}
+
+/// Find an inode of an image by path
+fn find_inode(root_inode: &Inode, path: &str) -> std::io::Result<Arc<Inode>> {
+    root_inode.find(path).map_err(image_error)?.ok_or_else(|| Error::new(
+        ErrorKind::NotFound,
+        format!("{}: no such file or directory", path),
+    ))
+}
+
+/// Collect the inodes under a directory inode with their paths, parents before children
+fn walk(dir: &Inode, dir_path: &str, entries: &mut Vec<(String, Arc<Inode>)>) -> std::io::Result<()> {
+    for name in dir.ls().map_err(image_error)? {
+        let inode = find_inode(dir, &name)?;
+        let path = format!("{}/{}", dir_path, name);
+        let is_dir = inode.is_dir().map_err(image_error)?;
+        entries.push((path.clone(), Arc::clone(&inode)));
+        if is_dir {
+            walk(&inode, &path, entries)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read all data of a file inode
+fn read_file(inode: &Inode) -> std::io::Result<Vec<u8>> {
+    let mut data = vec![0u8; inode.metadata().map_err(image_error)?.size as usize];
+    inode.read_at(0, &mut data).map_err(image_error)?;
+    Ok(data)
+}
+
+/// Extract the tree under the root of an image to a host directory,
+/// return the number of files and directories extracted
+fn extract(root_inode: &Inode, host_dir: &Path) -> std::io::Result<usize> {
+    create_dir_all(host_dir)?;
+    let mut entries = Vec::new();
+    walk(root_inode, "", &mut entries)?;
+    for (path, inode) in entries.iter() {
+        let host_path = host_dir.join(path.trim_start_matches('/'));
+        if let Some(target) = inode.readlink().map_err(image_error)? {
+            std::os::unix::fs::symlink(target, &host_path)?;
+        } else if inode.is_dir().map_err(image_error)? {
+            create_dir_all(&host_path)?;
+        } else {
+            std::fs::write(&host_path, read_file(inode)?)?;
+        }
+    }
+    // children first, so that read-only directories are filled before
+    for (path, inode) in entries.iter().rev() {
+        let host_path = host_dir.join(path.trim_start_matches('/'));
+        let metadata = inode.metadata().map_err(image_error)?;
+        // the permissions of a symbolic link are those of its target
+        if !metadata.is_symlink {
+            set_permissions(&host_path, Permissions::from_mode(metadata.mode))?;
+        }
+    }
+    Ok(entries.len())
+}
+
+/// Add a host file to an image at `path`, creating the missing parent directories
+/// and replacing the data of an existing file
+fn add_file(root_inode: &Inode, host_path: &Path, path: &str) -> std::io::Result<()> {
+    let data = std::fs::read(host_path)?;
+    let names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+    let mut dir_path = String::new();
+    for name in names.iter().take(names.len().saturating_sub(1)) {
+        dir_path = format!("{}/{}", dir_path, name);
+        if root_inode.find(&dir_path).map_err(image_error)?.is_none()
+            && root_inode.create_dir(&dir_path).map_err(image_error)?.is_none() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("cannot create directory {}", dir_path),
+            ));
+        }
+    }
+    let inode = match root_inode.find(path).map_err(image_error)? {
+        Some(inode) if inode.is_dir().map_err(image_error)? => {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{}: is a directory", path),
+            ));
+        }
+        Some(inode) => {
+            inode.clear().map_err(image_error)?;
+            inode
+        }
+        None => root_inode.create(path).map_err(image_error)?.ok_or_else(|| Error::new(
+            ErrorKind::InvalidInput,
+            format!("cannot create {}: the name is invalid or the disk image is full", path),
+        ))?,
+    };
+    if inode.write_at(0, &data).map_err(image_error)? < data.len() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("cannot write {}: the disk image is full", path),
+        ));
+    }
+    copy_metadata(&std::fs::metadata(host_path)?, &inode)
+}
+
+/// Remove a file or an empty directory from an image
+fn remove(root_inode: &Inode, path: &str) -> std::io::Result<()> {
+    let removed = if find_inode(root_inode, path)?.is_dir().map_err(image_error)? {
+        root_inode.rmdir(path)
+    } else {
+        root_inode.unlink(path)
+    }
+    .map_err(image_error)?;
+    if removed {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::Other,
+            format!("cannot remove {}: the directory is not empty", path),
+        ))
+    }
+}
+
+/// List the tree under a directory of an easy-fs disk image
+fn easy_fs_list(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap())?;
+    let dir_path = matches.value_of("path").unwrap_or("").trim_end_matches('/');
+    let dir = find_inode(&root_inode, dir_path)?;
+    let mut entries = Vec::new();
+    walk(&dir, dir_path, &mut entries)?;
+    for (path, inode) in entries {
+        let metadata = inode.metadata().map_err(image_error)?;
+        let suffix = if metadata.is_dir { "/" } else { "" };
+        println!("{:04o} {:>10} {}{}", metadata.mode, metadata.size, path, suffix);
+    }
+    Ok(())
+}
+
+/// Print a file of an easy-fs disk image
+fn easy_fs_cat(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap())?;
+    let path = matches.value_of("path").unwrap();
+    let inode = find_inode(&root_inode, path)?;
+    if inode.is_dir().map_err(image_error)? {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{}: is a directory", path),
+        ));
+    }
+    std::io::stdout().write_all(&read_file(&inode)?)
+}
+
+/// Extract all files of an easy-fs disk image to a host directory
+fn easy_fs_extract(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap())?;
+    let host_dir = matches.value_of("dir").unwrap();
+    let count = extract(&root_inode, Path::new(host_dir))?;
+    println!("{} files and directories extracted to {}", count, host_dir);
+    Ok(())
+}
+
+/// Add a host file to an easy-fs disk image
+fn easy_fs_add(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap())?;
+    let host_path = Path::new(matches.value_of("file").unwrap());
+    let path = match matches.value_of("path") {
+        Some(path) => path.to_string(),
+        None => host_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no file name given"))?
+            .to_string(),
+    };
+    add_file(&root_inode, host_path, &path)
+}
+
+/// Remove a file or an empty directory from an easy-fs disk image
+fn easy_fs_remove(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap())?;
+    remove(&root_inode, matches.value_of("path").unwrap())
+}
+
+/// Mount an easy-fs disk image on the host and serve it until it is unmounted
+fn easy_fs_mount(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap())?;
+    let mountpoint = Path::new(matches.value_of("mountpoint").unwrap());
+    let mut fs = fuse::FuseFs::new(Arc::new(root_inode), host_time);
+    let fd = fuse::mount(mountpoint)?;
+    println!("mounted at {}, unmount with umount or Ctrl-C", mountpoint.display());
+    fuse::serve(fd, &mut fs, mountpoint)
+}
+
 #[test]
 fn efs_test() -> std::io::Result<()> {
     let block_file = Arc::new(BlockFile(Mutex::new({
@@ -99,40 +700,37 @@ fn efs_test() -> std::io::Result<()> {
             .write(true)
             .create(true)
             .open("target/fs.img")?;
-        f.set_len(BLOCK_NUM * BLOCK_SZ).unwrap();
+        f.set_len((BLOCK_NUM * BLOCK_SZ) as u64).unwrap();
         f
     })));
-    EasyFileSystem::create(block_file.clone(), 4096, 1);
-    let efs = EasyFileSystem::open(block_file.clone());
+    EasyFileSystem::create(block_file.clone(), 4096, 1, BlockMapping::Indirect).unwrap();
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
     let root_inode = EasyFileSystem::root_inode(&efs);
-    root_inode.create("filea");
-    root_inode.create("fileb");
-    for name in root_inode.ls() {
-        println!("{}", name);
-    }
-    let filea = root_inode.find("filea").unwrap();
+    root_inode.create("filea").unwrap();
+    root_inode.create("fileb").unwrap();
+    assert_eq!(root_inode.ls().unwrap(), vec!["filea", "fileb"]);
+    let filea = root_inode.find("filea").unwrap().unwrap();
     let greet_str = "Hello, world!";
-    filea.write_at(0, greet_str.as_bytes());
-    //let mut buffer = [0u8; BLOCK_SZ];
+    filea.write_at(0, greet_str.as_bytes()).unwrap();
     let mut buffer = [0u8; 233];
-    let len = filea.read_at(0, &mut buffer);
+    let len = filea.read_at(0, &mut buffer).unwrap();
     assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap(),);
 
     let mut random_str_test = |len: usize| {
-        filea.clear();
-        assert_eq!(filea.read_at(0, &mut buffer), 0,);
+        filea.clear().unwrap();
+        assert_eq!(filea.read_at(0, &mut buffer).unwrap(), 0,);
         let mut str = String::new();
         use rand;
         // random digit
         for _ in 0..len {
             str.push(char::from('0' as u8 + rand::random::<u8>() % 10));
         }
-        filea.write_at(0, str.as_bytes());
+        filea.write_at(0, str.as_bytes()).unwrap();
         let mut read_buffer = [0u8; 127];
         let mut offset = 0usize;
         let mut read_str = String::new();
         loop {
-            let len = filea.read_at(offset, &mut read_buffer);
+            let len = filea.read_at(offset, &mut read_buffer).unwrap();
             if len == 0 {
                 break;
             }
@@ -153,3 +751,351 @@ fn efs_test() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn efs_copy_metadata_test() -> std::io::Result<()> {
+    let block_file = Arc::new(BlockFile(Mutex::new({
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("target/fs_metadata.img")?;
+        f.set_len((4096 * BLOCK_SZ) as u64).unwrap();
+        f
+    })));
+    let efs = EasyFileSystem::create(block_file, 4096, 1, BlockMapping::Indirect).unwrap();
+    efs.set_clock(|| 3000);
+    let file = EasyFileSystem::root_inode(&efs).create("file").unwrap().unwrap();
+    // metadata of a host file is copied when packing
+    let host_file = File::open("Cargo.toml")?;
+    let host_metadata = host_file.metadata()?;
+    copy_metadata(&host_metadata, &file)?;
+    let metadata = file.metadata().unwrap();
+    assert_eq!(metadata.mode, host_metadata.mode() & 0o7777);
+    assert_eq!((metadata.uid, metadata.gid), (host_metadata.uid(), host_metadata.gid()));
+    assert_eq!(metadata.mtime, host_metadata.mtime() as u64);
+    assert_eq!(metadata.ctime, 3000);
+    Ok(())
+}
+
+#[test]
+fn efs_image_test() -> std::io::Result<()> {
+    let image = "target/fs_image.img";
+    {
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(image)?;
+        f.set_len((4096 * BLOCK_SZ) as u64).unwrap();
+        let block_file = Arc::new(BlockFile(Mutex::new(f)));
+        EasyFileSystem::create(block_file, 4096, 1, BlockMapping::Indirect).unwrap();
+    }
+    let run = |args: &[&str]| {
+        let matches = app().get_matches_from([&["easy-fs-fuse"], args].concat());
+        match matches.subcommand() {
+            ("add", Some(matches)) => easy_fs_add(matches),
+            ("remove", Some(matches)) => easy_fs_remove(matches),
+            ("list", Some(matches)) => easy_fs_list(matches),
+            _ => unreachable!(),
+        }
+    };
+    // files are added to an existing image, along with their parent directories
+    run(&["add", image, "Cargo.toml"])?;
+    run(&["add", image, "src/main.rs", "dir/sub/main.rs"])?;
+    run(&["list", image])?;
+    run(&["list", image, "dir/sub"])?;
+    let root_inode = open_image(image)?;
+    let mut entries = Vec::new();
+    walk(&root_inode, "", &mut entries)?;
+    let paths: Vec<&str> = entries.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(paths, vec!["/Cargo.toml", "/dir", "/dir/sub", "/dir/sub/main.rs"]);
+    let main_rs = std::fs::read("src/main.rs")?;
+    let inode = find_inode(&root_inode, "dir/sub/main.rs")?;
+    assert_eq!(read_file(&inode)?, main_rs);
+    // adding again replaces the data
+    add_file(&root_inode, Path::new("Cargo.toml"), "dir/sub/main.rs")?;
+    assert_eq!(read_file(&inode)?, std::fs::read("Cargo.toml")?);
+    assert!(add_file(&root_inode, Path::new("Cargo.toml"), "dir").is_err());
+    // everything is extracted with its permission bits
+    let host_dir = Path::new("target/fs_image_extract");
+    if host_dir.exists() {
+        std::fs::remove_dir_all(host_dir)?;
+    }
+    assert_eq!(extract(&root_inode, host_dir)?, 4);
+    assert_eq!(std::fs::read(host_dir.join("Cargo.toml"))?, std::fs::read("Cargo.toml")?);
+    let host_metadata = std::fs::metadata(host_dir.join("dir/sub/main.rs"))?;
+    assert_eq!(host_metadata.mode() & 0o7777, std::fs::metadata("Cargo.toml")?.mode() & 0o7777);
+    // only files and empty directories are removed
+    drop((entries, inode, root_inode));
+    assert!(run(&["remove", image, "dir"]).is_err());
+    assert!(run(&["remove", image, "missing"]).is_err());
+    run(&["remove", image, "dir/sub/main.rs"])?;
+    run(&["remove", image, "dir/sub"])?;
+    let root_inode = open_image(image)?;
+    assert_eq!(root_inode.ls().unwrap(), vec!["Cargo.toml", "dir"]);
+    assert!(find_inode(&root_inode, "dir")?.ls().unwrap().is_empty());
+    Ok(())
+}
+
+#[test]
+fn efs_fuse_test() {
+    use fuse::*;
+    use std::mem::size_of;
+    let block_file = Arc::new(BlockFile(Mutex::new({
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("target/fs_fuse.img")
+            .unwrap();
+        f.set_len(4096 * 512).unwrap();
+        f
+    })));
+    let efs = EasyFileSystem::create(block_file, 4096, 1, BlockMapping::Indirect).unwrap();
+    let mut fs = FuseFs::new(Arc::new(EasyFileSystem::root_inode(&efs)), host_time);
+    let mut unique = 0;
+    // send a request, return the error and the data of the reply
+    let mut request = |opcode: u32, nodeid: u64, body: &[u8]| {
+        unique += 1;
+        let header = InHeader {
+            len: (size_of::<InHeader>() + body.len()) as u32,
+            opcode,
+            unique,
+            nodeid,
+            ..Default::default()
+        };
+        let bytes = [as_bytes(&header), body].concat();
+        let reply = fs.handle(&bytes).unwrap();
+        let out: OutHeader = read_struct(&reply).unwrap();
+        assert_eq!((out.len as usize, out.unique), (reply.len(), unique));
+        (out.error, reply[size_of::<OutHeader>()..].to_vec())
+    };
+    let init_in = InitIn { major: 7, minor: 31, max_readahead: 4096, flags: 0 };
+    let (error, data) = request(FUSE_INIT, 0, as_bytes(&init_in));
+    assert_eq!(error, 0);
+    assert_eq!(read_struct::<InitOut>(&data).unwrap().major, 7);
+    // create a file and write to it
+    let create_in = CreateIn { mode: 0o644, ..Default::default() };
+    let (error, data) = request(FUSE_CREATE, FUSE_ROOT_ID, &[as_bytes(&create_in), b"hello\0"].concat());
+    assert_eq!(error, 0);
+    let file_id = read_struct::<EntryOut>(&data).unwrap().nodeid;
+    let (error, _) = request(FUSE_CREATE, FUSE_ROOT_ID, &[as_bytes(&create_in), b"hello\0"].concat());
+    assert_eq!(error, -libc::EEXIST);
+    let write_in = WriteIn { offset: 3, size: 5, ..Default::default() };
+    let (error, data) = request(FUSE_WRITE, file_id, &[as_bytes(&write_in), b"world"].concat());
+    assert_eq!(error, 0);
+    assert_eq!(read_struct::<WriteOut>(&data).unwrap().size, 5);
+    // the file can be looked up and read back
+    let (error, data) = request(FUSE_LOOKUP, FUSE_ROOT_ID, b"hello\0");
+    assert_eq!(error, 0);
+    let entry = read_struct::<EntryOut>(&data).unwrap();
+    assert_eq!((entry.nodeid, entry.attr.size, entry.attr.mode), (file_id, 8, libc::S_IFREG | 0o644));
+    let read_in = ReadIn { offset: 0, size: 100, ..Default::default() };
+    let (error, data) = request(FUSE_READ, file_id, as_bytes(&read_in));
+    assert_eq!(error, 0);
+    assert_eq!(data, b"\0\0\0world");
+    let (error, data) = request(FUSE_GETATTR, file_id, &[]);
+    assert_eq!(error, 0);
+    assert_eq!(read_struct::<AttrOut>(&data).unwrap().attr.nlink, 1);
+    assert_eq!(request(FUSE_LOOKUP, FUSE_ROOT_ID, b"missing\0").0, -libc::ENOENT);
+    // directory entries, each padded to 8 bytes
+    let (error, data) = request(FUSE_READDIR, FUSE_ROOT_ID, as_bytes(&read_in));
+    assert_eq!(error, 0);
+    let mut names = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let dirent: Dirent = read_struct(&data[offset..]).unwrap();
+        let name = &data[offset + size_of::<Dirent>()..][..dirent.namelen as usize];
+        names.push(String::from_utf8(name.to_vec()).unwrap());
+        offset += (size_of::<Dirent>() + name.len() + 7) & !7;
+    }
+    assert_eq!(names, vec![".", "..", "hello"]);
+    let (error, data) = request(FUSE_STATFS, FUSE_ROOT_ID, &[]);
+    assert_eq!(error, 0);
+    let stat = read_struct::<StatfsOut>(&data).unwrap();
+    assert_eq!(stat.files - stat.ffree, 2);
+    // a symbolic link reads back its target
+    let (error, data) = request(FUSE_SYMLINK, FUSE_ROOT_ID, b"alias\0hello\0");
+    assert_eq!(error, 0);
+    let entry = read_struct::<EntryOut>(&data).unwrap();
+    assert_eq!((entry.attr.size, entry.attr.mode), (5, libc::S_IFLNK | 0o777));
+    assert_eq!(request(FUSE_READLINK, entry.nodeid, &[]), (0, b"hello".to_vec()));
+    assert_eq!(request(FUSE_READLINK, file_id, &[]).0, -libc::EINVAL);
+    assert_eq!(request(FUSE_UNLINK, FUSE_ROOT_ID, b"alias\0").0, 0);
+    // extended attributes, whose size is told by a request of size 0
+    let setxattr_in = SetxattrIn { size: 2, flags: 0 };
+    assert_eq!(request(FUSE_SETXATTR, file_id, &[as_bytes(&setxattr_in), b"user.tag\0ok"].concat()).0, 0);
+    let setxattr_in = SetxattrIn { size: 2, flags: 1 };
+    let (error, _) = request(FUSE_SETXATTR, file_id, &[as_bytes(&setxattr_in), b"user.tag\0no"].concat());
+    assert_eq!(error, -libc::EEXIST);
+    let getxattr_in = GetxattrIn { size: 0, padding: 0 };
+    let (error, data) = request(FUSE_GETXATTR, file_id, &[as_bytes(&getxattr_in), b"user.tag\0"].concat());
+    assert_eq!((error, read_struct::<GetxattrOut>(&data).unwrap().size), (0, 2));
+    let getxattr_in = GetxattrIn { size: 16, padding: 0 };
+    let (error, data) = request(FUSE_GETXATTR, file_id, &[as_bytes(&getxattr_in), b"user.tag\0"].concat());
+    assert_eq!((error, data), (0, b"ok".to_vec()));
+    assert_eq!(request(FUSE_LISTXATTR, file_id, as_bytes(&getxattr_in)), (0, b"user.tag\0".to_vec()));
+    assert_eq!(request(FUSE_REMOVEXATTR, file_id, b"user.tag\0").0, 0);
+    let (error, _) = request(FUSE_GETXATTR, file_id, &[as_bytes(&getxattr_in), b"user.tag\0"].concat());
+    assert_eq!(error, -libc::ENODATA);
+    // remove the file
+    assert_eq!(request(FUSE_RMDIR, FUSE_ROOT_ID, b"hello\0").0, -libc::ENOTDIR);
+    assert_eq!(request(FUSE_UNLINK, FUSE_ROOT_ID, b"hello\0").0, 0);
+    assert_eq!(request(FUSE_LOOKUP, FUSE_ROOT_ID, b"hello\0").0, -libc::ENOENT);
+    assert_eq!(request(0xffff, FUSE_ROOT_ID, &[]).0, -libc::ENOSYS);
+}
+
+#[test]
+fn efs_geometry_test() -> std::io::Result<()> {
+    // the default geometry is the one of an inode bitmap block
+    let geometry = FsGeometry::new(BLOCK_NUM as u32, INODE_NUM).unwrap();
+    assert_eq!((geometry.inode_bitmap_blocks, geometry.inodes()), (1, INODE_NUM));
+    assert_eq!(
+        1 + geometry.log_blocks + geometry.inode_bitmap_blocks + geometry.inode_area_blocks
+            + geometry.data_bitmap_blocks + geometry.data_area_blocks,
+        geometry.total_blocks,
+    );
+    assert!(FsGeometry::new(40, INODE_NUM).is_none());
+    assert!(FsGeometry::new(BLOCK_NUM as u32, 0).is_none());
+    // the smallest geometry holding the data blocks
+    for data_blocks in [1, 4096, 4097, 100000] {
+        let geometry = FsGeometry::with_data_blocks(10, data_blocks).unwrap();
+        assert!(geometry.data_area_blocks >= data_blocks);
+        let smaller = FsGeometry::new(geometry.total_blocks - 1, 10);
+        assert!(!matches!(smaller, Some(smaller) if smaller.data_area_blocks >= data_blocks));
+    }
+    // pack as few blocks and inodes as needed
+    let source = Path::new("target/fs_geometry_src");
+    let target = Path::new("target/fs_geometry/");
+    for dir in [source, target] {
+        if dir.exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+        create_dir_all(dir)?;
+    }
+    let apps = [("small", 100), ("large", 20000), ("a_rather_long_name_of_an_app_to_pack", 9000)];
+    for (name, size) in apps {
+        File::create(source.join(format!("{}.rs", name)))?;
+        std::fs::write(target.join(name), vec![7u8; size])?;
+    }
+    let pack = |args: &[&str]| {
+        let command = ["easy-fs-fuse", "-s", "target/fs_geometry_src", "-t", "target/fs_geometry/"];
+        easy_fs_pack(&app().get_matches_from([&command, args].concat()))
+    };
+    pack(&["--auto-size"])?;
+    let root_inode = open_image("target/fs_geometry/fs.img")?;
+    let stat = root_inode.fs_stat().unwrap();
+    assert_eq!((stat.free_blocks, stat.inodes - stat.free_inodes), (0, 4));
+    let inode = find_inode(&root_inode, "large")?;
+    assert_eq!(read_file(&inode)?, vec![7u8; 20000]);
+    drop((inode, root_inode));
+    pack(&["--size", "1M", "--inodes", "10"])?;
+    let stat = open_image("target/fs_geometry/fs.img")?.fs_stat().unwrap();
+    assert_eq!((stat.total_blocks, stat.inodes), (2048, 12));
+    // the areas have to fit in the image
+    assert!(pack(&["--size", "20K"]).is_err());
+    assert!(pack(&["--inodes", "3"]).is_err());
+    assert!(parse_size("1000").is_err());
+    assert_eq!(parse_size("4K")?, 8);
+    Ok(())
+}
+
+#[test]
+fn efs_tree_test() -> std::io::Result<()> {
+    let host_dir = Path::new("target/fs_tree_src");
+    if host_dir.exists() {
+        std::fs::remove_dir_all(host_dir)?;
+    }
+    create_dir_all(host_dir.join("etc/init.d"))?;
+    create_dir_all(host_dir.join("empty"))?;
+    std::fs::write(host_dir.join("etc/config.toml"), b"answer = 42\n")?;
+    std::fs::write(host_dir.join("etc/init.d/rc"), vec![1u8; 30000])?;
+    std::fs::write(host_dir.join("data.bin"), (0..=255u8).collect::<Vec<u8>>())?;
+    set_permissions(host_dir.join("etc"), Permissions::from_mode(0o750))?;
+    // the tree is mirrored as it is, keeping the extensions
+    let image = "target/fs_tree.img";
+    let matches = app().get_matches_from(["easy-fs-fuse", "-d", "target/fs_tree_src", "-o", image, "--auto-size"]);
+    easy_fs_pack(&matches)?;
+    let root_inode = open_image(image)?;
+    let mut entries = Vec::new();
+    walk(&root_inode, "", &mut entries)?;
+    let paths: Vec<&str> = entries.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(
+        paths,
+        vec!["/data.bin", "/empty", "/etc", "/etc/config.toml", "/etc/init.d", "/etc/init.d/rc"],
+    );
+    let etc = find_inode(&root_inode, "etc")?;
+    assert_eq!(etc.metadata().unwrap().mode, 0o750);
+    assert_eq!(etc.metadata().unwrap().mtime, std::fs::metadata(host_dir.join("etc"))?.mtime() as u64);
+    let rc = find_inode(&root_inode, "etc/init.d/rc")?;
+    assert_eq!(read_file(&rc)?, vec![1u8; 30000]);
+    // the image is just large enough
+    assert_eq!(root_inode.fs_stat().unwrap().free_blocks, 0);
+    drop((entries, etc, rc, root_inode));
+    // apps are packed alongside the tree
+    let target = Path::new("target/fs_tree_apps/");
+    create_dir_all(target.join("src"))?;
+    File::create(target.join("src/hello.rs"))?;
+    std::fs::write(target.join("hello"), b"\x7fELF")?;
+    let matches = app().get_matches_from([
+        "easy-fs-fuse", "-s", "target/fs_tree_apps/src/", "-t", "target/fs_tree_apps/",
+        "-d", "target/fs_tree_src",
+    ]);
+    easy_fs_pack(&matches)?;
+    let root_inode = open_image("target/fs_tree_apps/fs.img")?;
+    assert_eq!(root_inode.ls().unwrap(), vec!["hello", "data.bin", "empty", "etc"]);
+    drop(root_inode);
+    let matches = app().get_matches_from(["easy-fs-fuse", "fsck", "target/fs_tree_apps/fs.img"]);
+    assert!(easy_fs_fsck(matches.subcommand_matches("fsck").unwrap())?);
+    Ok(())
+}
+
+#[test]
+fn efs_pack_symlink_test() -> std::io::Result<()> {
+    // host symbolic links are packed and extracted as they are
+    let host_dir = Path::new("target/fs_symlink_src");
+    let _ = std::fs::remove_dir_all(host_dir);
+    create_dir_all(host_dir.join("bin"))?;
+    std::fs::write(host_dir.join("bin/app-1.2"), b"\x7fELF")?;
+    std::os::unix::fs::symlink("app-1.2", host_dir.join("bin/app"))?;
+    std::os::unix::fs::symlink("/bin/app", host_dir.join("app"))?;
+    let image = "target/fs_symlink.img";
+    let matches = app().get_matches_from(["easy-fs-fuse", "-d", "target/fs_symlink_src", "-o", image, "--auto-size"]);
+    easy_fs_pack(&matches)?;
+    let root_inode = open_image(image)?;
+    assert_eq!(find_inode(&root_inode, "app")?.readlink().unwrap(), Some(String::from("/bin/app")));
+    assert_eq!(find_inode(&root_inode, "bin/app")?.readlink().unwrap(), Some(String::from("app-1.2")));
+    let extracted = Path::new("target/fs_symlink_dst");
+    let _ = std::fs::remove_dir_all(extracted);
+    assert_eq!(extract(&root_inode, extracted)?, 4);
+    assert_eq!(std::fs::read_link(extracted.join("bin/app"))?, Path::new("app-1.2"));
+    assert_eq!(std::fs::read(extracted.join("bin/app"))?, b"\x7fELF");
+    Ok(())
+}
+
+#[test]
+fn efs_pack_xattr_test() -> std::io::Result<()> {
+    // attributes are set when packing
+    let host_dir = Path::new("target/fs_xattr_src");
+    let _ = std::fs::remove_dir_all(host_dir);
+    create_dir_all(host_dir.join("tests"))?;
+    std::fs::write(host_dir.join("tests/exit"), b"\x7fELF")?;
+    let image = "target/fs_xattr.img";
+    let matches = app().get_matches_from([
+        "easy-fs-fuse", "-d", "target/fs_xattr_src", "-o", image, "--auto-size",
+        "--xattr", "/tests/exit:exit_code=3", "--xattr", "/tests/exit:category=process:exit",
+    ]);
+    easy_fs_pack(&matches)?;
+    let root_inode = open_image(image)?;
+    let exit = find_inode(&root_inode, "tests/exit")?;
+    assert_eq!(exit.get_xattr("exit_code").unwrap(), Some(b"3".to_vec()));
+    assert_eq!(exit.get_xattr("category").unwrap(), Some(b"process:exit".to_vec()));
+    assert_eq!(root_inode.fs_stat().unwrap().free_blocks, 0);
+    let matches = app().get_matches_from(["easy-fs-fuse", "-d", "target/fs_xattr_src", "-o", image, "--xattr", "exit"]);
+    assert!(easy_fs_pack(&matches).is_err());
+    Ok(())
+}
@@ -2,6 +2,8 @@ use alloc::sync::Arc;
 use super::{
     BlockDevice,
     BLOCK_SZ,
+    IoResult,
+    ceil_div,
     get_block_cache,
 };
 
@@ -15,6 +17,8 @@ const BLOCK_BITS: usize = BLOCK_SZ * 8;
 pub struct Bitmap {
     start_block_id: usize,
     blocks: usize,
+    /// number of bits in use, bits past them in the last block are never allocated
+    bits: usize,
 }
 
 /// Decompose bits into (block_pos, bits64_pos, inner_pos)
@@ -24,54 +28,122 @@ fn decomposition(mut bit: usize) -> (usize, usize, usize) {
     (block_pos, bit / 64, bit % 64)
 }
 
+/// Find the first bit not set in [from, to) of a bitmap block
+fn find_free(bitmap_block: &BitmapBlock, from: usize, to: usize) -> Option<usize> {
+    (from / 64..ceil_div(to, 64)).find_map(|bits64_pos| {
+        let mut bits64 = bitmap_block[bits64_pos];
+        // take the bits before `from` as set
+        if bits64_pos == from / 64 {
+            bits64 |= (1u64 << (from % 64)) - 1;
+        }
+        let pos = bits64_pos * 64 + bits64.trailing_ones() as usize;
+        if bits64 != u64::MAX && pos < to {
+            Some(pos)
+        } else {
+            None
+        }
+    })
+}
+
 impl Bitmap {
-    /// A new bitmap from start block id and number of blocks
-    pub fn new(start_block_id: usize, blocks: usize) -> Self {
+    /// A new bitmap from start block id, number of blocks and number of bits in use
+    pub fn new(start_block_id: usize, blocks: usize, bits: usize) -> Self {
+        assert!(bits <= blocks * BLOCK_BITS);
         Self {
             start_block_id,
             blocks,
+            bits,
         }
     }
-    /// Allocate a new block from a block device
-    pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
-        for block_id in 0..self.blocks {
-            let pos = get_block_cache(
+    /// Allocate a new block from a block device, which is the bit of `hint` if that is free,
+    /// or the first free bit after it, wrapping around to the start of the bitmap.
+    /// Blocks allocated with the last one as the hint are consecutive as long as possible.
+    pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>, hint: usize) -> IoResult<Option<usize>> {
+        if self.bits == 0 {
+            return Ok(None);
+        }
+        let hint = if hint < self.bits { hint } else { 0 };
+        let (hint_block, hint_pos) = (hint / BLOCK_BITS, hint % BLOCK_BITS);
+        // the block of the hint is searched from the hint first and up to it last
+        for i in 0..=self.blocks {
+            let block_id = (hint_block + i) % self.blocks;
+            let from = if i == 0 { hint_pos } else { 0 };
+            let to = if i == self.blocks { hint_pos } else { BLOCK_BITS };
+            // bits past those in use are never allocated
+            let to = to.min(self.bits.saturating_sub(block_id * BLOCK_BITS));
+            if from >= to {
+                continue;
+            }
+            let block_cache = get_block_cache(
                 block_id + self.start_block_id as usize,
                 Arc::clone(block_device),
-            ).lock().modify(0, |bitmap_block: &mut BitmapBlock| {
-                if let Some((bits64_pos, inner_pos)) = bitmap_block
-                    .iter()
-                    .enumerate()
-                    .find(|(_, bits64)| **bits64 != u64::MAX)
-                    .map(|(bits64_pos, bits64)| {
-                        (bits64_pos, bits64.trailing_ones() as usize)
-                    }) {
-                    // modify cache
-                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
-                    Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos as usize)
-                } else {
-                    None
-                }
+            )?;
+            let mut block_cache = block_cache.lock();
+            // only modify the block if a bit is found, so that a full block
+            // is left out of the transaction
+            let pos = block_cache.read(0, |bitmap_block: &BitmapBlock| {
+                find_free(bitmap_block, from, to)
             });
-            if pos.is_some() {
-                return pos;
+            if let Some(pos) = pos {
+                block_cache.modify(0, |bitmap_block: &mut BitmapBlock| {
+                    bitmap_block[pos / 64] |= 1u64 << (pos % 64);
+                });
+                return Ok(Some(block_id * BLOCK_BITS + pos));
             }
         }
-        None
+        Ok(None)
     }
     /// Deallocate a block
-    pub fn dealloc(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) {
+    pub fn dealloc(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> IoResult<()> {
         let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
         get_block_cache(
             block_pos + self.start_block_id,
             Arc::clone(block_device)
-        ).lock().modify(0, |bitmap_block: &mut BitmapBlock| {
+        )?.lock().modify(0, |bitmap_block: &mut BitmapBlock| {
             assert!(bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0);
             bitmap_block[bits64_pos] -= 1u64 << inner_pos;
         });
+        Ok(())
+    }
+    /// Check whether a block is allocated
+    pub fn is_allocated(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> IoResult<bool> {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        Ok(get_block_cache(
+            block_pos + self.start_block_id,
+            Arc::clone(block_device)
+        )?.lock().read(0, |bitmap_block: &BitmapBlock| {
+            bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0
+        }))
+    }
+    /// Mark a given block as allocated
+    pub fn set_allocated(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> IoResult<()> {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(
+            block_pos + self.start_block_id,
+            Arc::clone(block_device)
+        )?.lock().modify(0, |bitmap_block: &mut BitmapBlock| {
+            bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+        });
+        Ok(())
+    }
+    /// Count the bits which are not allocated
+    pub fn count_free(&self, block_device: &Arc<dyn BlockDevice>) -> IoResult<usize> {
+        let mut allocated = 0;
+        for block_id in 0..self.blocks {
+            allocated += get_block_cache(
+                block_id + self.start_block_id,
+                Arc::clone(block_device),
+            )?.lock().read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block
+                    .iter()
+                    .map(|bits64| bits64.count_ones() as usize)
+                    .sum::<usize>()
+            });
+        }
+        Ok(self.bits - allocated)
     }
     /// Get the max number of allocatable blocks
     pub fn maximum(&self) -> usize {
-        self.blocks * BLOCK_BITS
+        self.bits
     }
 }
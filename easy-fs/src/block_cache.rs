@@ -1,11 +1,17 @@
 use super::{
     BLOCK_SZ,
     BlockDevice,
+    CachePolicy,
+    IoResult,
+    LruPolicy,
 };
-use alloc::collections::VecDeque;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 use lazy_static::*;
-use spin::Mutex;
+use spin::{Mutex, MutexGuard};
 
 /// Cached block inside memory
 pub struct BlockCache {
@@ -17,23 +23,50 @@ pub struct BlockCache {
     block_device: Arc<dyn BlockDevice>,
     /// whether the block is dirty
     modified: bool,
+    /// whether the block has to be loaded before its next use,
+    /// since it is not loaded yet or its modifications have been dropped
+    stale: bool,
 }
 
 impl BlockCache {
-    /// Load a new BlockCache from disk.
+    /// Create a BlockCache which is loaded from disk before its first use,
+    /// so that the block is read without the block cache manager locked.
     pub fn new(
         block_id: usize,
-        block_device: Arc<dyn BlockDevice>
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Self {
+        Self {
+            cache: [0u8; BLOCK_SZ],
+            block_id,
+            block_device,
+            modified: false,
+            stale: true,
+        }
+    }
+    /// Create a BlockCache from block data already read from disk.
+    pub fn with_data(
+        block_id: usize,
+        block_device: Arc<dyn BlockDevice>,
+        data: &[u8],
     ) -> Self {
         let mut cache = [0u8; BLOCK_SZ];
-        block_device.read_block(block_id, &mut cache);
+        cache.copy_from_slice(data);
         Self {
             cache,
             block_id,
             block_device,
             modified: false,
+            stale: false,
         }
     }
+    /// Get the underlying block id
+    pub fn block_id(&self) -> usize {
+        self.block_id
+    }
+    /// Whether the cached block has been modified since the last write back
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
     /// Get the address of an offset inside the cached block data
     fn addr_of_offset(&self, offset: usize) -> usize {
         &self.cache[offset] as *const _ as usize
@@ -62,61 +95,229 @@ impl BlockCache {
         f(self.get_mut(offset))
     }
 
-    pub fn sync(&mut self) {
+    pub fn sync(&mut self) -> IoResult<()> {
         if self.modified {
+            self.block_device.write_block(self.block_id, &self.cache)?;
             self.modified = false;
-            self.block_device.write_block(self.block_id, &self.cache);
         }
+        Ok(())
+    }
+    /// Drop the modifications, loading the block again before its next use
+    pub fn discard(&mut self) {
+        self.modified = false;
+        self.stale = true;
+    }
+    /// Load the block if it is not loaded yet or its modifications have been dropped
+    fn reload(&mut self) -> IoResult<()> {
+        if self.stale {
+            self.block_device.read_block(self.block_id, &mut self.cache)?;
+            self.stale = false;
+        }
+        Ok(())
     }
 }
 
 impl Drop for BlockCache {
     fn drop(&mut self) {
-        self.sync()
+        // an error cannot be reported here, and blocks are written back by commits anyway
+        let _ = self.sync();
     }
 }
 
-/// Use a block cache of 16 blocks
-const BLOCK_CACHE_SIZE: usize = 16;
+/// Use a block cache of 16 blocks unless another capacity is given
+pub const BLOCK_CACHE_SIZE: usize = 16;
+
+/// Statistics of the block cache of a block device
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    /// accesses to blocks already cached
+    pub hits: usize,
+    /// accesses loading blocks from the block device
+    pub misses: usize,
+    /// cached blocks substituted
+    pub evictions: usize,
+    /// blocks cached beyond the capacity because no cached block could be substituted
+    pub overflows: usize,
+    /// blocks loaded ahead of being accessed
+    pub read_ahead: usize,
+    /// blocks cached at the moment, exceeding the capacity only after overflows
+    pub cached: usize,
+}
+
+/// Cached blocks of a single block device
+struct DeviceBlockCache {
+    capacity: usize,
+    policy: Box<dyn CachePolicy>,
+    /// (block id, cached block) by slot
+    slots: Vec<(usize, Arc<Mutex<BlockCache>>)>,
+    stats: BlockCacheStats,
+}
+
+impl DeviceBlockCache {
+    fn new(capacity: usize, policy: Box<dyn CachePolicy>) -> Self {
+        assert!(capacity > 0, "The block cache needs a capacity of at least one block!");
+        Self {
+            capacity,
+            policy,
+            slots: Vec::new(),
+            stats: BlockCacheStats::default(),
+        }
+    }
+
+    fn get_block_cache(
+        &mut self,
+        block_id: usize,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> IoResult<Arc<Mutex<BlockCache>>> {
+        if let Some(slot) = self.slots.iter().position(|(id, _)| *id == block_id) {
+            self.stats.hits += 1;
+            self.policy.access(slot);
+            return Ok(Arc::clone(&self.slots[slot].1));
+        }
+        self.stats.misses += 1;
+        // the block is loaded into mem by the caller
+        let block_cache = Arc::new(Mutex::new(BlockCache::new(block_id, block_device)));
+        self.insert(block_id, Arc::clone(&block_cache), true);
+        Ok(block_cache)
+    }
+
+    /// Cache a newly loaded block, substituting a cached block if the cache is full.
+    /// Only if `overflow` is set will the capacity be exceeded when nothing can be substituted.
+    /// A cache exceeding its capacity shrinks back by substituting more blocks on later inserts.
+    fn insert(&mut self, block_id: usize, block_cache: Arc<Mutex<BlockCache>>, overflow: bool) -> bool {
+        let entry = (block_id, block_cache);
+        if self.slots.len() < self.capacity {
+            self.slots.push(entry);
+            self.policy.insert(self.slots.len() - 1);
+            return true;
+        }
+        match self.evict() {
+            Some(slot) => {
+                self.stats.evictions += 1;
+                self.slots[slot] = entry;
+                self.policy.insert(slot);
+            }
+            None if overflow => {
+                // every cached block is in use, exceed the capacity rather than fail
+                self.stats.overflows += 1;
+                self.slots.push(entry);
+                self.policy.insert(self.slots.len() - 1);
+                return true;
+            }
+            None => return false,
+        }
+        // drop the blocks cached beyond the capacity which are no longer in use
+        while self.slots.len() > self.capacity {
+            let slot = match self.evict() {
+                Some(slot) => slot,
+                None => break,
+            };
+            self.stats.evictions += 1;
+            self.slots.swap_remove(slot);
+            self.policy.relocate(self.slots.len(), slot);
+        }
+        true
+    }
+
+    /// Choose a cached block to substitute, modified blocks are only written back
+    /// by a journal commit so they can never be substituted
+    fn evict(&mut self) -> Option<usize> {
+        let slots = &self.slots;
+        self.policy.evict(&mut |slot| {
+            let cache = &slots[slot].1;
+            Arc::strong_count(cache) == 1 && !cache.lock().is_modified()
+        })
+    }
+
+    /// Load the blocks of the given runs of consecutive blocks which are not cached yet,
+    /// each run of uncached blocks in a single request. At most half of the cache is used,
+    /// and nothing in use is substituted for them.
+    /// Return the number of leading blocks of the runs which are cached afterwards.
+    fn prefetch(&mut self, runs: &[(usize, usize)], block_device: &Arc<dyn BlockDevice>) -> usize {
+        let slot_of = |slots: &Vec<(usize, Arc<Mutex<BlockCache>>)>, id: usize| {
+            slots.iter().position(|(cached_id, _)| *cached_id == id)
+        };
+        let mut budget = self.capacity / 2;
+        let mut cached = 0;
+        for &(block_id, count) in runs {
+            let end = block_id + count.min(budget);
+            budget -= end - block_id;
+            let mut start = block_id;
+            while start < end {
+                // blocks about to be used are kept from being substituted for the others
+                if let Some(slot) = slot_of(&self.slots, start) {
+                    self.policy.access(slot);
+                    cached += 1;
+                    start += 1;
+                    continue;
+                }
+                let mut run_end = start + 1;
+                while run_end < end && slot_of(&self.slots, run_end).is_none() {
+                    run_end += 1;
+                }
+                let mut buf = vec![0u8; (run_end - start) * BLOCK_SZ];
+                // reading ahead is only a hint, the error shows up once the block is used
+                if block_device.read_blocks(start, &mut buf).is_err() {
+                    return cached;
+                }
+                for (i, data) in buf.chunks(BLOCK_SZ).enumerate() {
+                    let block_cache = Arc::new(Mutex::new(
+                        BlockCache::with_data(start + i, Arc::clone(block_device), data)
+                    ));
+                    if !self.insert(start + i, block_cache, false) {
+                        return cached;
+                    }
+                    self.stats.read_ahead += 1;
+                    cached += 1;
+                }
+                start = run_end;
+            }
+            if end - block_id < count {
+                break;
+            }
+        }
+        cached
+    }
+}
 
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    /// block caches by the address of the block device,
+    /// so that blocks of different devices never share a cache entry
+    devices: BTreeMap<usize, DeviceBlockCache>,
+}
+
+/// Get the key of a block device in the block cache manager
+fn device_key(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const () as usize
 }
 
 impl BlockCacheManager {
     pub fn new() -> Self {
-        Self { queue: VecDeque::new() }
+        Self { devices: BTreeMap::new() }
     }
 
     pub fn get_block_cache(
         &mut self,
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
-    ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue
-            .iter()
-            .find(|pair| pair.0 == block_id) {
-                Arc::clone(&pair.1)
-        } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self.queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1) {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of BlockCache!");
-                }
-            }
-            // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(
-                BlockCache::new(block_id, Arc::clone(&block_device))
-            ));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
-            block_cache
-        }
+    ) -> IoResult<Arc<Mutex<BlockCache>>> {
+        self.device(&block_device).get_block_cache(block_id, block_device)
+    }
+
+    /// Get the block cache of a block device, set up with the default capacity and policy
+    /// if the device is not known yet
+    fn device(&mut self, block_device: &Arc<dyn BlockDevice>) -> &mut DeviceBlockCache {
+        self.devices
+            .entry(device_key(block_device))
+            .or_insert_with(|| DeviceBlockCache::new(BLOCK_CACHE_SIZE, Box::new(LruPolicy::new())))
+    }
+
+    /// Get all cached blocks of a block device
+    fn device_blocks(&self, block_device: &Arc<dyn BlockDevice>) -> Vec<Arc<Mutex<BlockCache>>> {
+        self.devices
+            .get(&device_key(block_device))
+            .map(|device| device.slots.iter().map(|(_, cache)| Arc::clone(cache)).collect())
+            .unwrap_or_default()
     }
 }
 
@@ -127,18 +328,107 @@ lazy_static! {
     );
 }
 
-/// Get the block cache corresponding to the given block id and block device
+/// Get the block cache corresponding to the given block id and block device,
+/// loading the block if it is not cached
 pub fn get_block_cache(
     block_id: usize,
     block_device: Arc<dyn BlockDevice>
-) -> Arc<Mutex<BlockCache>> {
-    BLOCK_CACHE_MANAGER.lock().get_block_cache(block_id, block_device)
+) -> IoResult<Arc<Mutex<BlockCache>>> {
+    let block_cache = BLOCK_CACHE_MANAGER.lock().get_block_cache(block_id, block_device)?;
+    // a block is loaded without the manager locked, so that blocks are read in parallel,
+    // and since the block may be locked by a thread waiting for the manager.
+    // Others getting the block meanwhile wait for it to be loaded.
+    block_cache.lock().reload()?;
+    Ok(block_cache)
+}
+
+/// Load the given runs of consecutive blocks, as (first block id, number of blocks),
+/// into the block cache ahead of their use, return the number of leading blocks cached
+pub fn block_cache_prefetch(runs: &[(usize, usize)], block_device: Arc<dyn BlockDevice>) -> usize {
+    BLOCK_CACHE_MANAGER.lock()
+        .device(&block_device)
+        .prefetch(runs, &block_device)
+}
+
+/// Set up the block cache of a block device with the given capacity and policy,
+/// dropping the blocks cached so far
+pub fn block_cache_init(
+    block_device: &Arc<dyn BlockDevice>,
+    capacity: usize,
+    policy: Box<dyn CachePolicy>,
+) {
+    let old = BLOCK_CACHE_MANAGER.lock()
+        .devices
+        .insert(device_key(block_device), DeviceBlockCache::new(capacity, policy));
+    // write back without holding the manager
+    drop(old);
+}
+
+/// Drop the block cache of a block device
+pub fn block_cache_release(block_device: &Arc<dyn BlockDevice>) {
+    let old = BLOCK_CACHE_MANAGER.lock()
+        .devices
+        .remove(&device_key(block_device));
+    drop(old);
+}
+
+/// Get the statistics of the block cache of a block device
+pub fn block_cache_stats(block_device: &Arc<dyn BlockDevice>) -> BlockCacheStats {
+    BLOCK_CACHE_MANAGER.lock()
+        .devices
+        .get(&device_key(block_device))
+        .map(|device| BlockCacheStats { cached: device.slots.len(), ..device.stats })
+        .unwrap_or_default()
 }
 
 /// Sync all block cache to block device
-pub fn block_cache_sync_all() {
-    let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
-        cache.lock().sync();
+pub fn block_cache_sync_all() -> IoResult<()> {
+    // do not hold the manager while waiting for a block cache in use
+    let caches: Vec<_> = BLOCK_CACHE_MANAGER.lock()
+        .devices
+        .values()
+        .flat_map(|device| device.slots.iter().map(|(_, cache)| Arc::clone(cache)))
+        .collect();
+    for cache in caches {
+        cache.lock().sync()?;
+    }
+    Ok(())
+}
+
+/// Get the cached blocks of a block device that are modified but not written back
+pub fn block_cache_dirty(block_device: &Arc<dyn BlockDevice>) -> Vec<Arc<Mutex<BlockCache>>> {
+    let mut caches = BLOCK_CACHE_MANAGER.lock().device_blocks(block_device);
+    // lock the caches only after the manager is released
+    caches.retain(|cache| cache.lock().is_modified());
+    caches
+}
+
+/// Drop the modifications of the cached blocks of a block device
+pub fn block_cache_discard(block_device: &Arc<dyn BlockDevice>) {
+    for cache in block_cache_dirty(block_device) {
+        cache.lock().discard();
+    }
+}
+
+/// Write back the given modified blocks, consecutive ones in a single request.
+/// If a request fails, the blocks are all left modified.
+pub fn block_cache_sync_batch(
+    block_device: &Arc<dyn BlockDevice>,
+    caches: &mut [MutexGuard<'_, BlockCache>],
+) -> IoResult<()> {
+    caches.sort_by_key(|cache| cache.block_id);
+    let mut buf: Vec<u8> = Vec::new();
+    let mut start = 0;
+    for i in 0..caches.len() {
+        buf.extend_from_slice(&caches[i].cache);
+        if i + 1 == caches.len() || caches[i + 1].block_id != caches[i].block_id + 1 {
+            block_device.write_blocks(caches[start].block_id, &buf)?;
+            buf.clear();
+            start = i + 1;
+        }
+    }
+    for cache in caches.iter_mut() {
+        cache.modified = false;
     }
+    Ok(())
 }
@@ -1,8 +1,41 @@
 use core::any::Any;
+use super::BLOCK_SZ;
+
+/// Error of a request to a block device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoError {
+    /// The block is beyond the end of the device
+    OutOfRange,
+    /// Less than the requested data was transferred
+    ShortTransfer,
+    /// The device failed the request
+    Device,
+    /// The data read from the device is inconsistent
+    Corrupt,
+}
+
+/// Result of a request to a block device
+pub type IoResult<T> = core::result::Result<T, IoError>;
 
 /// Trait for block devices
 /// which reads and writes data in the unit of blocks
 pub trait BlockDevice : Send + Sync + Any {
-    fn read_block(&self, block_id: usize, buf: &mut [u8]);
-    fn write_block(&self, block_id: usize, buf: &[u8]);
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> IoResult<()>;
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> IoResult<()>;
+    /// Read consecutive blocks starting from `block_id` into `buf`,
+    /// devices able to do it in a single request should override this
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> IoResult<()> {
+        for (i, block) in buf.chunks_mut(BLOCK_SZ).enumerate() {
+            self.read_block(block_id + i, block)?;
+        }
+        Ok(())
+    }
+    /// Write `buf` into consecutive blocks starting from `block_id`,
+    /// devices able to do it in a single request should override this
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) -> IoResult<()> {
+        for (i, block) in buf.chunks(BLOCK_SZ).enumerate() {
+            self.write_block(block_id + i, block)?;
+        }
+        Ok(())
+    }
 }
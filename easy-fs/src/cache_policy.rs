@@ -0,0 +1,117 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// A replacement policy of the block cache.
+///
+/// Cached blocks are identified by their slots in the cache, and a slot
+/// is reused by the new block once the block in it is substituted.
+pub trait CachePolicy: Send {
+    /// A new block is loaded into `slot`
+    fn insert(&mut self, slot: usize);
+    /// The block in `slot` is accessed again
+    fn access(&mut self, slot: usize);
+    /// Choose a slot to substitute among those for which `evictable` holds
+    fn evict(&mut self, evictable: &mut dyn FnMut(usize) -> bool) -> Option<usize>;
+    /// The block in `last`, the last slot, moves into `slot` chosen by `evict`,
+    /// and `last` is no longer used. Both are the same if the last slot was chosen.
+    fn relocate(&mut self, last: usize, slot: usize);
+}
+
+/// Substitute the least recently used block
+pub struct LruPolicy {
+    /// slots from the least recently used to the most recently used
+    order: VecDeque<usize>,
+}
+
+impl LruPolicy {
+    pub fn new() -> Self {
+        Self { order: VecDeque::new() }
+    }
+}
+
+impl Default for LruPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CachePolicy for LruPolicy {
+    fn insert(&mut self, slot: usize) {
+        self.access(slot);
+    }
+    fn access(&mut self, slot: usize) {
+        if let Some(pos) = self.order.iter().position(|s| *s == slot) {
+            self.order.remove(pos);
+        }
+        self.order.push_back(slot);
+    }
+    fn evict(&mut self, evictable: &mut dyn FnMut(usize) -> bool) -> Option<usize> {
+        let pos = self.order.iter().position(|s| evictable(*s))?;
+        self.order.remove(pos)
+    }
+    fn relocate(&mut self, last: usize, slot: usize) {
+        if let Some(pos) = self.order.iter().position(|s| *s == last) {
+            self.order[pos] = slot;
+        }
+    }
+}
+
+/// Substitute the first block not referenced since the clock hand passed it last time
+pub struct ClockPolicy {
+    /// reference bit of each slot
+    referenced: Vec<bool>,
+    /// the slot to be examined next
+    hand: usize,
+}
+
+impl ClockPolicy {
+    pub fn new() -> Self {
+        Self {
+            referenced: Vec::new(),
+            hand: 0,
+        }
+    }
+}
+
+impl Default for ClockPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CachePolicy for ClockPolicy {
+    fn insert(&mut self, slot: usize) {
+        if slot >= self.referenced.len() {
+            self.referenced.resize(slot + 1, false);
+        }
+        self.referenced[slot] = true;
+    }
+    fn access(&mut self, slot: usize) {
+        self.referenced[slot] = true;
+    }
+    fn evict(&mut self, evictable: &mut dyn FnMut(usize) -> bool) -> Option<usize> {
+        let slots = self.referenced.len();
+        // all reference bits are cleared in the first round,
+        // so a victim is found in the second one if there is any
+        for _ in 0..2 * slots {
+            let slot = self.hand;
+            self.hand = (self.hand + 1) % slots;
+            if !evictable(slot) {
+                continue;
+            }
+            if self.referenced[slot] {
+                self.referenced[slot] = false;
+            } else {
+                return Some(slot);
+            }
+        }
+        None
+    }
+    fn relocate(&mut self, last: usize, slot: usize) {
+        self.referenced[slot] = self.referenced[last];
+        self.referenced.truncate(last);
+        if self.hand >= last {
+            self.hand = 0;
+        }
+    }
+}
@@ -1,69 +1,214 @@
-use alloc::sync::Arc;
-use spin::Mutex;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
 use super::{
     BlockDevice,
+    IoError,
+    IoResult,
     Bitmap,
     SuperBlock,
     DiskInode,
     DiskInodeType,
+    BlockMapping,
+    DirEntry,
+    DEFAULT_DIR_MODE,
+    FEATURE_EXTENTS,
+    FEATURE_LONG_NAMES,
+    NAME_LENGTH_LIMIT,
+    SHORT_NAME_LENGTH_LIMIT,
     Inode,
+    Journal,
+    BlockCacheStats,
+    CachePolicy,
+    LruPolicy,
+    BLOCK_CACHE_SIZE,
+    ceil_div,
     get_block_cache,
     block_cache_sync_all,
+    block_cache_init,
+    block_cache_release,
+    block_cache_stats,
 };
-use crate::BLOCK_SZ;
+use crate::{BLOCK_SZ, DIRENT_SZ};
 
-/// An easy fs over a block device
+/// An easy fs over a block device.
+///
+/// Operations on different inodes run concurrently, each inode being guarded by a
+/// read/write lock of its own. Operations modifying the filesystem are transactions,
+/// which join a group committed as a whole once every operation of it has ended,
+/// since a commit writes back every modified block.
 pub struct EasyFileSystem {
     pub block_device: Arc<dyn BlockDevice>,
     pub inode_bitmap: Bitmap,
     pub data_bitmap: Bitmap,
+    journal: Journal,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    /// source of the current time in seconds
+    clock: Mutex<fn() -> u64>,
+    /// called while waiting for other transactions
+    relax: Mutex<fn()>,
+    /// format features of the super block
+    features: u32,
+    /// the group of transactions in progress
+    group: Mutex<TransactionGroup>,
+    /// held while allocating or deallocating inodes and data blocks
+    allocator: Mutex<()>,
+    /// locks of the inodes in use, by inode id
+    inode_locks: Mutex<BTreeMap<u32, Weak<RwLock<()>>>>,
 }
 
 /// A data block of block size
 type DataBlock = [u8; BLOCK_SZ];
 
+/// Number of bits in a bitmap block
+const BLOCK_BITS: usize = BLOCK_SZ * 8;
+
+/// Use a write-ahead log of 128 blocks, so that a group holds several transactions
+const LOG_BLOCKS: u32 = 128;
+
+/// The max number of blocks modified by a transaction, which is reserved in the log
+/// for each transaction of a group
+const TRANSACTION_BLOCKS: usize = 24;
+
+/// Number of inodes in an inode area block
+const INODES_PER_BLOCK: usize = BLOCK_SZ / core::mem::size_of::<DiskInode>();
+
+/// The clock of a filesystem before one is set, which stays at 0
+fn no_clock() -> u64 {
+    0
+}
+
+/// The group of transactions in progress, which is committed as a whole
+struct TransactionGroup {
+    /// transactions of the group not ended yet
+    outstanding: usize,
+    /// ended transactions of the group waiting for its result
+    waiting: usize,
+    /// whether the group is being committed, or its result not taken by all of it yet
+    committing: bool,
+    /// whether the group is committed, its result being left for the waiting transactions
+    committed: bool,
+    /// the first error failing a transaction of the group
+    error: Option<IoError>,
+    /// the result of the last group
+    result: IoResult<()>,
+    /// whether transactions are kept from beginning by `begin_exclusive`
+    exclusive: bool,
+}
+
+impl TransactionGroup {
+    fn new() -> Self {
+        Self {
+            outstanding: 0,
+            waiting: 0,
+            committing: false,
+            committed: false,
+            error: None,
+            result: Ok(()),
+            exclusive: false,
+        }
+    }
+}
+
+/// A transaction in progress, see `EasyFileSystem::begin_transaction`.
+/// Dropping it before it ends ends it as an operation which succeeded.
+pub(crate) struct Transaction<'a> {
+    fs: &'a EasyFileSystem,
+    ended: bool,
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _ = self.fs.end_group_member(None);
+        }
+    }
+}
+
+/// Keeps transactions from beginning, see `EasyFileSystem::begin_exclusive`
+pub(crate) struct Exclusive<'a> {
+    fs: &'a EasyFileSystem,
+}
+
+impl Drop for Exclusive<'_> {
+    fn drop(&mut self) {
+        self.fs.group.lock().exclusive = false;
+    }
+}
+
 impl EasyFileSystem {
-    /// Create a filesystem from a block device
+    /// Create a filesystem from a block device, whose inodes map their data as `mapping`
     pub fn create(
         block_device: Arc<dyn BlockDevice>,
         total_blocks: u32,
         inode_bitmap_blocks: u32,
-    ) -> Arc<Mutex<Self>> {
-        // calculate block size of areas & create bitmaps
-        let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
-        let inode_num = inode_bitmap.maximum();
-        let inode_area_blocks =
-            ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
+        mapping: BlockMapping,
+    ) -> IoResult<Arc<Self>> {
+        let geometry = FsGeometry::new(total_blocks, inode_bitmap_blocks * BLOCK_BITS as u32)
+            .expect("The areas of easy-fs do not fit in the blocks!");
+        Self::create_with_geometry(block_device, geometry, mapping)
+    }
+    /// Create a filesystem from a block device with the areas laid out by `geometry`,
+    /// whose inodes map their data as `mapping`
+    pub fn create_with_geometry(
+        block_device: Arc<dyn BlockDevice>,
+        geometry: FsGeometry,
+        mapping: BlockMapping,
+    ) -> IoResult<Arc<Self>> {
+        let FsGeometry {
+            total_blocks,
+            log_blocks,
+            inode_bitmap_blocks,
+            inode_area_blocks,
+            data_bitmap_blocks,
+            data_area_blocks,
+        } = geometry;
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
-        let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
-        let data_area_blocks = data_total_blocks - data_bitmap_blocks;
+        let inode_bitmap = Bitmap::new(
+            (1 + log_blocks) as usize,
+            inode_bitmap_blocks as usize,
+            geometry.inodes() as usize,
+        );
         let data_bitmap = Bitmap::new(
-            (1 + inode_bitmap_blocks + inode_area_blocks) as usize,
+            (1 + log_blocks + inode_total_blocks) as usize,
             data_bitmap_blocks as usize,
+            data_area_blocks as usize,
         );
-        let mut efs = Self {
+        let efs = Self {
             block_device: Arc::clone(&block_device),
             inode_bitmap,
             data_bitmap,
-            inode_area_start_block: 1 + inode_bitmap_blocks,
-            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            journal: Journal::new(1, log_blocks as usize),
+            inode_area_start_block: 1 + log_blocks + inode_bitmap_blocks,
+            data_area_start_block: 1 + log_blocks + inode_total_blocks + data_bitmap_blocks,
+            clock: Mutex::new(no_clock),
+            relax: Mutex::new(core::hint::spin_loop),
+            features: match mapping {
+                BlockMapping::Indirect => FEATURE_LONG_NAMES,
+                BlockMapping::Extents => FEATURE_LONG_NAMES | FEATURE_EXTENTS,
+            },
+            group: Mutex::new(TransactionGroup::new()),
+            allocator: Mutex::new(()),
+            inode_locks: Mutex::new(BTreeMap::new()),
         };
-        // clear all blocks
+        // clear all blocks, writing each back at once
+        // since modified blocks stay in the cache until they are synced
         for i in 0..total_blocks {
-            get_block_cache(
+            let block_cache = get_block_cache(
                 i as usize,
                 Arc::clone(&block_device)
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
+            )?;
+            let mut block_cache = block_cache.lock();
+            block_cache.modify(0, |data_block: &mut DataBlock| {
                 for byte in data_block.iter_mut() { *byte = 0; }
             });
+            block_cache.sync()?;
         }
         // initialize SuperBlock
-        get_block_cache(0, Arc::clone(&block_device))
+        get_block_cache(0, Arc::clone(&block_device))?
         .lock()
         .modify(0, |super_block: &mut SuperBlock| {
             super_block.initialize(
@@ -72,61 +217,282 @@ impl EasyFileSystem {
                 inode_area_blocks,
                 data_bitmap_blocks,
                 data_area_blocks,
+                log_blocks,
+                efs.features,
             );
         });
         // write back immediately
         // create a inode for root node "/"
-        assert_eq!(efs.alloc_inode(), 0);
+        assert_eq!(efs.alloc_inode()?, Some(0));
         let (root_inode_block_id, root_inode_offset) = efs.get_disk_inode_pos(0);
+        // both '.' and '..' of the root refer to the root itself
+        let root_size = 2 * DIRENT_SZ;
         get_block_cache(
             root_inode_block_id as usize,
             Arc::clone(&block_device)
-        )
+        )?
         .lock()
-        .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
-            disk_inode.initialize(DiskInodeType::Directory);
-        });
-        block_cache_sync_all();
-        Arc::new(Mutex::new(efs))
+        .modify(root_inode_offset, |disk_inode: &mut DiskInode| -> IoResult<()> {
+            disk_inode.initialize(DiskInodeType::Directory, DEFAULT_DIR_MODE, 0, mapping);
+            disk_inode.size = root_size as u32;
+            let blocks_needed = disk_inode.blocks_num_needed(0, &block_device)?.unwrap();
+            let mut new_blocks = Vec::new();
+            for _ in 0..blocks_needed {
+                new_blocks.push(efs.alloc_data(0)?.unwrap());
+            }
+            disk_inode.map_block(0, new_blocks, &block_device)?;
+            disk_inode.write_at(0, &DirEntry::new(".", 0).unwrap().to_bytes(), &block_device)?;
+            disk_inode.write_at(DIRENT_SZ, &DirEntry::new("..", 0).unwrap().to_bytes(), &block_device)?;
+            Ok(())
+        })?;
+        block_cache_sync_all()?;
+        Ok(Arc::new(efs))
+    }
+    /// Open a block device as a filesystem,
+    /// installing the transaction left committed by a crash first
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> IoResult<Arc<Self>> {
+        Self::open_with_cache(block_device, BLOCK_CACHE_SIZE, Box::new(LruPolicy::new()))
     }
-    /// Open a block device as a filesystem
-    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
+    /// Open a block device as a filesystem,
+    /// caching at most `cache_capacity` blocks substituted by the given policy
+    pub fn open_with_cache(
+        block_device: Arc<dyn BlockDevice>,
+        cache_capacity: usize,
+        cache_policy: Box<dyn CachePolicy>,
+    ) -> IoResult<Arc<Self>> {
+        block_cache_init(&block_device, cache_capacity, cache_policy);
         // read SuperBlock
-        get_block_cache(0, Arc::clone(&block_device))
+        let efs = get_block_cache(0, Arc::clone(&block_device))?
             .lock()
             .read(0, |super_block: &SuperBlock| {
                 assert!(super_block.is_valid(), "Error loading EFS!");
+                let log_blocks = super_block.log_blocks;
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
-                let efs = Self {
+                // the inode area may hold fewer inodes than the bitmap does
+                let inodes = (super_block.inode_bitmap_blocks as usize * BLOCK_BITS)
+                    .min(super_block.inode_area_blocks as usize * INODES_PER_BLOCK);
+                Self {
                     block_device,
                     inode_bitmap: Bitmap::new(
-                        1,
-                        super_block.inode_bitmap_blocks as usize
+                        (1 + log_blocks) as usize,
+                        super_block.inode_bitmap_blocks as usize,
+                        inodes,
                     ),
                     data_bitmap: Bitmap::new(
-                        (1 + inode_total_blocks) as usize,
+                        (1 + log_blocks + inode_total_blocks) as usize,
                         super_block.data_bitmap_blocks as usize,
+                        super_block.data_area_blocks as usize,
                     ),
-                    inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
-                    data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
-                };
-                Arc::new(Mutex::new(efs))
-            })
+                    journal: Journal::new(1, log_blocks as usize),
+                    inode_area_start_block: 1 + log_blocks + super_block.inode_bitmap_blocks,
+                    data_area_start_block: 1 + log_blocks + inode_total_blocks
+                        + super_block.data_bitmap_blocks,
+                    clock: Mutex::new(no_clock),
+                    relax: Mutex::new(core::hint::spin_loop),
+                    features: super_block.features,
+                    group: Mutex::new(TransactionGroup::new()),
+                    allocator: Mutex::new(()),
+                    inode_locks: Mutex::new(BTreeMap::new()),
+                }
+            });
+        efs.journal.recover(&efs.block_device)?;
+        Ok(Arc::new(efs))
     }
     /// Get the root inode of the filesystem
-    pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
-        let block_device = Arc::clone(&efs.lock().block_device);
-        // acquire efs lock temporarily
-        let (block_id, block_offset) = efs.lock().get_disk_inode_pos(0);
-        // release efs lock
+    pub fn root_inode(efs: &Arc<Self>) -> Inode {
+        let block_device = Arc::clone(&efs.block_device);
+        let (block_id, block_offset) = efs.get_disk_inode_pos(0);
         Inode::new(
+            0,
             block_id,
             block_offset,
             Arc::clone(efs),
             block_device,
         )
     }
+    /// Get the lock of an inode, shared by all vfs inodes of it
+    pub(crate) fn inode_lock(&self, inode_id: u32) -> Arc<RwLock<()>> {
+        let mut inode_locks = self.inode_locks.lock();
+        if let Some(lock) = inode_locks.get(&inode_id).and_then(Weak::upgrade) {
+            return lock;
+        }
+        // forget the locks of the inodes no longer in use
+        inode_locks.retain(|_, lock| lock.strong_count() > 0);
+        let lock = Arc::new(RwLock::new(()));
+        inode_locks.insert(inode_id, Arc::downgrade(&lock));
+        lock
+    }
+    /// Whether vfs inodes other than the one holding `lock` refer to its inode
+    pub(crate) fn inode_shared(&self, lock: &Arc<RwLock<()>>) -> bool {
+        let _inode_locks = self.inode_locks.lock();
+        Arc::strong_count(lock) > 1
+    }
+    /// Whether any vfs inode refers to an inode
+    pub(crate) fn inode_in_use(&self, inode_id: u32) -> bool {
+        matches!(self.inode_locks.lock().get(&inode_id), Some(lock) if lock.strong_count() > 0)
+    }
+    /// Drop the lock of a vfs inode being dropped,
+    /// return whether it was the last vfs inode of its inode
+    pub(crate) fn put_inode_lock(&self, lock: Arc<RwLock<()>>) -> bool {
+        let _inode_locks = self.inode_locks.lock();
+        let last = Arc::strong_count(&lock) == 1;
+        drop(lock);
+        last
+    }
+    /// Begin a transaction, joining the group in progress unless it is being committed
+    /// or its room in the log is taken, and end it by `end_transaction`.
+    /// The locks of inodes are only acquired after this, and released before it ends.
+    pub(crate) fn begin_transaction(&self) -> Transaction<'_> {
+        loop {
+            let mut group = self.group.lock();
+            if !group.committing
+                && !group.exclusive
+                && (group.outstanding == 0
+                    || (group.outstanding + 1) * TRANSACTION_BLOCKS <= self.journal.capacity()) {
+                group.outstanding += 1;
+                break;
+            }
+            drop(group);
+            self.relax();
+        }
+        Transaction { fs: self, ended: false }
+    }
+    /// End a transaction with the result of its operation, then wait for its group:
+    /// the last transaction of the group to end commits the modifications of all of them
+    /// if they all succeeded, otherwise drops them, so that an operation failed by
+    /// an I/O error leaves the filesystem as it was.
+    /// Return the result of the operation, or the error failing its group.
+    pub(crate) fn end_transaction<T>(&self, mut transaction: Transaction, result: IoResult<T>) -> IoResult<T> {
+        transaction.ended = true;
+        let group_result = self.end_group_member(result.as_ref().err().copied());
+        result.and_then(|value| group_result.map(|_| value))
+    }
+    /// Leave the group of transactions in progress, failing it with `error` if there is one,
+    /// and return the result of the group
+    fn end_group_member(&self, error: Option<IoError>) -> IoResult<()> {
+        let mut group = self.group.lock();
+        group.outstanding -= 1;
+        if group.error.is_none() {
+            group.error = error;
+        }
+        if group.outstanding == 0 {
+            group.committing = true;
+            let error = group.error.take();
+            drop(group);
+            let result = match error {
+                None => self.commit(),
+                Some(err) => {
+                    self.abort();
+                    Err(err)
+                }
+            };
+            let mut group = self.group.lock();
+            // the next group begins once the others have taken the result
+            group.result = result;
+            group.committed = group.waiting > 0;
+            group.committing = group.committed;
+            return result;
+        }
+        group.waiting += 1;
+        drop(group);
+        loop {
+            let mut group = self.group.lock();
+            if group.committed {
+                group.waiting -= 1;
+                group.committed = group.waiting > 0;
+                group.committing = group.committed;
+                return group.result;
+            }
+            drop(group);
+            self.relax();
+        }
+    }
+    /// Wait for the transactions in progress and keep others from beginning
+    /// until the returned guard is dropped, modifications being committed by `commit_or_abort`
+    pub(crate) fn begin_exclusive(&self) -> Exclusive<'_> {
+        loop {
+            let mut group = self.group.lock();
+            if !group.exclusive {
+                group.exclusive = true;
+                break;
+            }
+            drop(group);
+            self.relax();
+        }
+        loop {
+            let group = self.group.lock();
+            if group.outstanding == 0 && !group.committing {
+                break;
+            }
+            drop(group);
+            self.relax();
+        }
+        Exclusive { fs: self }
+    }
+    /// Write all modifications made since the last commit back as one transaction,
+    /// or drop them all if they cannot be committed
+    pub(crate) fn commit(&self) -> IoResult<()> {
+        let result = self.journal.commit(&self.block_device);
+        if result.is_err() {
+            self.abort();
+        }
+        result
+    }
+    /// Drop all modifications made since the last commit
+    pub(crate) fn abort(&self) {
+        self.journal.abort(&self.block_device);
+    }
+    /// Commit the modifications made since the last commit if `result` is Ok,
+    /// otherwise drop them
+    pub(crate) fn commit_or_abort<T>(&self, result: IoResult<T>) -> IoResult<T> {
+        match result {
+            Ok(value) => self.commit().map(|_| value),
+            Err(err) => {
+                self.abort();
+                Err(err)
+            }
+        }
+    }
+    /// Set the source of the current time in seconds, used to stamp inodes
+    pub fn set_clock(&self, clock: fn() -> u64) {
+        *self.clock.lock() = clock;
+    }
+    /// Get the current time in seconds
+    pub fn now(&self) -> u64 {
+        let clock = *self.clock.lock();
+        clock()
+    }
+    /// Set the function called while waiting for other transactions,
+    /// such as one yielding to other threads, instead of spinning
+    pub fn set_relax(&self, relax: fn()) {
+        *self.relax.lock() = relax;
+    }
+    /// Wait a moment for other transactions
+    fn relax(&self) {
+        let relax = *self.relax.lock();
+        relax()
+    }
+    /// Get how the new inodes of the filesystem map their data to blocks
+    pub fn block_mapping(&self) -> BlockMapping {
+        if self.features & FEATURE_EXTENTS != 0 {
+            BlockMapping::Extents
+        } else {
+            BlockMapping::Indirect
+        }
+    }
+    /// Get the max length of inode name allowed by the format of the filesystem
+    pub fn name_length_limit(&self) -> usize {
+        if self.features & FEATURE_LONG_NAMES != 0 {
+            NAME_LENGTH_LIMIT
+        } else {
+            SHORT_NAME_LENGTH_LIMIT
+        }
+    }
+    /// Get the statistics of the block cache of the filesystem
+    pub fn cache_stats(&self) -> BlockCacheStats {
+        block_cache_stats(&self.block_device)
+    }
     /// Get inode by id
     pub fn get_disk_inode_pos(&self, inode_id: u32) -> (u32, usize) {
         let inode_size = core::mem::size_of::<DiskInode>();
@@ -138,27 +504,154 @@ impl EasyFileSystem {
     pub fn get_data_block_id(&self, data_block_id: u32) -> u32 {
         self.data_area_start_block + data_block_id
     }
-    /// Allocate a new inode
-    pub fn alloc_inode(&mut self) -> u32 {
-        self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
+    /// Get the data bitmap block, counted from the first one, which has the bit of a data block
+    pub(crate) fn data_bitmap_block(&self, block_id: u32) -> usize {
+        (block_id - self.data_area_start_block) as usize / BLOCK_BITS
+    }
+    /// Get the number of data blocks taken by a file of `size` bytes without holes
+    /// mapped as `mapping`, including the indirect or extent blocks
+    pub fn blocks_of_size(size: u32, mapping: BlockMapping) -> u32 {
+        DiskInode::total_blocks(size, mapping)
+    }
+    /// Get the usage statistics of the filesystem
+    pub fn stat(&self) -> IoResult<FsStat> {
+        let _allocator = self.allocator.lock();
+        let total_blocks = get_block_cache(0, Arc::clone(&self.block_device))?
+            .lock()
+            .read(0, |super_block: &SuperBlock| super_block.total_blocks);
+        Ok(FsStat {
+            block_size: BLOCK_SZ as u32,
+            total_blocks,
+            data_blocks: self.data_bitmap.maximum() as u32,
+            free_blocks: self.data_bitmap.count_free(&self.block_device)? as u32,
+            inodes: self.inode_bitmap.maximum() as u32,
+            free_inodes: self.inode_bitmap.count_free(&self.block_device)? as u32,
+            name_length_limit: self.name_length_limit() as u32,
+        })
+    }
+    /// Allocate a new inode, or return None if there is no free inode
+    pub fn alloc_inode(&self) -> IoResult<Option<u32>> {
+        let _allocator = self.allocator.lock();
+        Ok(self.inode_bitmap.alloc(&self.block_device, 0)?.map(|inode_id| inode_id as u32))
     }
-    /// Allocate a data block
-    pub fn alloc_data(&mut self) -> u32 {
-        self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
+    /// Deallocate an inode
+    pub fn dealloc_inode(&self, inode_id: u32) -> IoResult<()> {
+        let _allocator = self.allocator.lock();
+        self.inode_bitmap.dealloc(&self.block_device, inode_id as usize)
     }
-    /// Deallocate a data block
-    pub fn dealloc_data(&mut self, block_id: u32) {
+    /// Allocate a data block, whose contents are cleared to zero,
+    /// or return None if the disk is full.
+    /// It is `hint` if that is free, or the first free block after it, 0 for no hint.
+    pub fn alloc_data(&self, hint: u32) -> IoResult<Option<u32>> {
+        let allocator = self.allocator.lock();
+        let hint = hint.saturating_sub(self.data_area_start_block) as usize;
+        let block_id = match self.data_bitmap.alloc(&self.block_device, hint)? {
+            Some(bit) => bit as u32 + self.data_area_start_block,
+            None => return Ok(None),
+        };
+        // the block is cleared without the allocator locked, since nobody else refers to it
+        drop(allocator);
         get_block_cache(
             block_id as usize,
             Arc::clone(&self.block_device)
-        )
+        )?
         .lock()
         .modify(0, |data_block: &mut DataBlock| {
             data_block.iter_mut().for_each(|p| { *p = 0; })
         });
+        Ok(Some(block_id))
+    }
+    /// Deallocate a data block, whose contents are left as they are
+    pub fn dealloc_data(&self, block_id: u32) -> IoResult<()> {
+        let _allocator = self.allocator.lock();
         self.data_bitmap.dealloc(
             &self.block_device,
             (block_id - self.data_area_start_block) as usize
         )
     }
 }
+
+/// Usage statistics of a filesystem
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FsStat {
+    /// size of a block in bytes
+    pub block_size: u32,
+    /// number of blocks of the device
+    pub total_blocks: u32,
+    /// number of blocks in the data area
+    pub data_blocks: u32,
+    /// number of free blocks in the data area
+    pub free_blocks: u32,
+    pub inodes: u32,
+    pub free_inodes: u32,
+    /// max length of inode name
+    pub name_length_limit: u32,
+}
+
+/// Block counts of the areas of a filesystem, which follow the super block in this order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FsGeometry {
+    pub total_blocks: u32,
+    pub log_blocks: u32,
+    pub inode_bitmap_blocks: u32,
+    pub inode_area_blocks: u32,
+    pub data_bitmap_blocks: u32,
+    pub data_area_blocks: u32,
+}
+
+impl FsGeometry {
+    /// Lay out a filesystem of `total_blocks` blocks with room for at least `inodes` inodes,
+    /// or return None if the areas leave no room for the root directory
+    pub fn new(total_blocks: u32, inodes: u32) -> Option<Self> {
+        let (inode_bitmap_blocks, inode_area_blocks) = Self::inode_blocks(inodes)?;
+        let data_total_blocks = total_blocks
+            .checked_sub(1 + LOG_BLOCKS)?
+            .checked_sub(inode_bitmap_blocks + inode_area_blocks)?;
+        // a data bitmap block covers itself and BLOCK_BITS data blocks
+        let data_bitmap_blocks = (data_total_blocks + BLOCK_BITS as u32) / (BLOCK_BITS as u32 + 1);
+        let data_area_blocks = data_total_blocks - data_bitmap_blocks;
+        if data_area_blocks < DiskInode::total_blocks(2 * DIRENT_SZ as u32, BlockMapping::Indirect) {
+            return None;
+        }
+        Some(Self {
+            total_blocks,
+            log_blocks: LOG_BLOCKS,
+            inode_bitmap_blocks,
+            inode_area_blocks,
+            data_bitmap_blocks,
+            data_area_blocks,
+        })
+    }
+    /// Lay out the smallest filesystem with room for at least `inodes` inodes
+    /// and `data_blocks` data blocks, or return None if it has more than `u32::MAX` blocks
+    pub fn with_data_blocks(inodes: u32, data_blocks: u32) -> Option<Self> {
+        let (inode_bitmap_blocks, inode_area_blocks) = Self::inode_blocks(inodes)?;
+        let data_blocks = data_blocks.max(DiskInode::total_blocks(2 * DIRENT_SZ as u32, BlockMapping::Indirect));
+        let data_bitmap_blocks = ceil_div(data_blocks as usize, BLOCK_BITS) as u32;
+        let total_blocks = (1 + LOG_BLOCKS + inode_bitmap_blocks + inode_area_blocks)
+            .checked_add(data_bitmap_blocks)?
+            .checked_add(data_blocks)?;
+        Self::new(total_blocks, inodes)
+    }
+    /// Get the number of inodes of the filesystem
+    pub fn inodes(&self) -> u32 {
+        self.inode_area_blocks * INODES_PER_BLOCK as u32
+    }
+    /// Get the blocks of the inode bitmap and the inode area to hold `inodes` inodes
+    fn inode_blocks(inodes: u32) -> Option<(u32, u32)> {
+        if inodes == 0 {
+            return None;
+        }
+        let inodes = inodes as usize;
+        let inode_bitmap_blocks = ceil_div(inodes, BLOCK_BITS);
+        let inode_area_blocks = ceil_div(inodes, INODES_PER_BLOCK);
+        Some((inode_bitmap_blocks as u32, inode_area_blocks as u32))
+    }
+}
+
+impl Drop for EasyFileSystem {
+    /// Release the cached blocks of the filesystem
+    fn drop(&mut self) {
+        block_cache_release(&self.block_device);
+    }
+}
@@ -0,0 +1,258 @@
+use core::fmt::{Display, Formatter, Result};
+use super::{
+    DiskInode,
+    DirEntry,
+    EasyFileSystem,
+    SuperBlock,
+    IoResult,
+    DIRENT_SZ,
+    get_block_cache,
+};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// An inconsistency found by `EasyFileSystem::fsck`
+#[derive(Debug, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// A directory entry refers to an inode which is not allocated
+    DanglingEntry { dir_inode_id: u32, name: String, inode_id: u32 },
+    /// A directory entry has a name too long or not in UTF-8, or misses extension slots
+    CorruptEntry { dir_inode_id: u32, slot: usize },
+    /// An allocated inode is not reachable from the root directory
+    OrphanInode { inode_id: u32 },
+    /// The link count of an inode differs from the number of entries referring to it
+    WrongLinkCount { inode_id: u32, nlink: u32, links: u32 },
+    /// A block referred to by an inode lies outside of the data area
+    BadBlock { inode_id: u32, block_id: u32 },
+    /// A block is referred to more than once
+    DuplicateBlock { inode_id: u32, block_id: u32 },
+    /// A block in use is free in the data bitmap
+    UnmarkedBlock { block_id: u32 },
+    /// A block allocated in the data bitmap is not in use
+    LeakedBlock { block_id: u32 },
+}
+
+impl FsckProblem {
+    /// Whether `fsck` is able to repair the problem
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, Self::BadBlock { .. } | Self::DuplicateBlock { .. })
+    }
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::DanglingEntry { dir_inode_id, name, inode_id } => write!(
+                f, "entry {} in directory inode {} refers to free inode {}",
+                name, dir_inode_id, inode_id,
+            ),
+            Self::CorruptEntry { dir_inode_id, slot } => write!(
+                f, "entry at slot {} in directory inode {} is corrupt", slot, dir_inode_id,
+            ),
+            Self::OrphanInode { inode_id } => write!(
+                f, "inode {} is allocated but unreachable", inode_id,
+            ),
+            Self::WrongLinkCount { inode_id, nlink, links } => write!(
+                f, "inode {} has link count {} but {} entries refer to it",
+                inode_id, nlink, links,
+            ),
+            Self::BadBlock { inode_id, block_id } => write!(
+                f, "inode {} refers to block {} outside of the data area", inode_id, block_id,
+            ),
+            Self::DuplicateBlock { inode_id, block_id } => write!(
+                f, "inode {} refers to block {} which is already in use", inode_id, block_id,
+            ),
+            Self::UnmarkedBlock { block_id } => write!(
+                f, "block {} is in use but free in the bitmap", block_id,
+            ),
+            Self::LeakedBlock { block_id } => write!(
+                f, "block {} is allocated but not in use", block_id,
+            ),
+        }
+    }
+}
+
+/// Result of a consistency check
+pub struct FsckReport {
+    /// problems found, in the order they were found
+    pub problems: Vec<FsckProblem>,
+    /// whether the repairable problems have been repaired
+    pub repaired: bool,
+}
+
+impl FsckReport {
+    /// Whether no problem was found
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl EasyFileSystem {
+    /// Check the consistency of the filesystem.
+    ///
+    /// Every inode reachable from the root directory is walked, the blocks it refers to
+    /// are cross-checked against the data bitmap, and allocated inodes are cross-checked
+    /// against the directory entries. If `repair` is set, dangling and corrupt entries are
+    /// removed, orphaned inodes are released, link counts are corrected and the data bitmap
+    /// is rebuilt from the blocks in use. Bad and doubly referenced blocks are only reported.
+    pub fn fsck(efs: &Arc<Self>, repair: bool) -> IoResult<FsckReport> {
+        let fs = efs.as_ref();
+        // nothing else is modified while the filesystem is checked and repaired
+        let _exclusive = fs.begin_exclusive();
+        let block_device = Arc::clone(&fs.block_device);
+        let data_area_blocks = get_block_cache(0, Arc::clone(&block_device))?
+            .lock()
+            .read(0, |super_block: &SuperBlock| super_block.data_area_blocks);
+        let data_area_start = fs.get_data_block_id(0);
+        let data_area_end = data_area_start + data_area_blocks;
+        let inode_num = fs.inode_bitmap.maximum() as u32;
+        let read_disk_inode = |fs: &Self, inode_id: u32| -> IoResult<(bool, u32, Vec<u32>)> {
+            let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+            get_block_cache(block_id as usize, Arc::clone(&block_device))?
+                .lock()
+                .read(block_offset, |disk_inode: &DiskInode| {
+                    Ok((disk_inode.is_dir(), disk_inode.nlink, disk_inode.all_blocks(&block_device)?))
+                })
+        };
+        let mut problems = Vec::new();
+        // walk the directory tree, counting the entries referring to each inode
+        let mut links: BTreeMap<u32, u32> = BTreeMap::new();
+        links.insert(0, 0);
+        let mut dangling: Vec<(u32, usize, usize)> = Vec::new();
+        let mut dirs = vec![0u32];
+        while let Some(dir_inode_id) = dirs.pop() {
+            let (block_id, block_offset) = fs.get_disk_inode_pos(dir_inode_id);
+            let entries: Vec<(usize, usize, Option<DirEntry>)> = get_block_cache(
+                block_id as usize,
+                Arc::clone(&block_device),
+            )?
+            .lock()
+            .read(block_offset, |disk_inode: &DiskInode| disk_inode.dirent_slots(&block_device))?;
+            for (slot, slots, dirent) in entries {
+                let dirent = match dirent {
+                    Some(dirent) => dirent,
+                    None => {
+                        problems.push(FsckProblem::CorruptEntry { dir_inode_id, slot });
+                        dangling.push((dir_inode_id, slot, slots));
+                        continue;
+                    }
+                };
+                let inode_id = dirent.inode_number();
+                if dirent.name() == "." || dirent.name() == ".." {
+                    continue;
+                }
+                if inode_id >= inode_num || !fs.inode_bitmap.is_allocated(&block_device, inode_id as usize)? {
+                    let name = dirent.name().to_string();
+                    problems.push(FsckProblem::DanglingEntry { dir_inode_id, name, inode_id });
+                    dangling.push((dir_inode_id, slot, slots));
+                    continue;
+                }
+                let count = links.entry(inode_id).or_insert(0);
+                *count += 1;
+                // a directory reached twice is walked only once
+                if *count == 1 && read_disk_inode(fs, inode_id)?.0 {
+                    dirs.push(inode_id);
+                }
+            }
+        }
+        // an inode removed while in use is released once it is no longer in use,
+        // until then it is unreachable and its blocks are in use
+        let mut removed: Vec<u32> = Vec::new();
+        for inode_id in 0..inode_num {
+            if !links.contains_key(&inode_id)
+                && fs.inode_in_use(inode_id)
+                && fs.inode_bitmap.is_allocated(&block_device, inode_id as usize)?
+                && read_disk_inode(fs, inode_id)?.1 == 0 {
+                removed.push(inode_id);
+            }
+        }
+        // check link counts and blocks of reachable and removed inodes
+        let mut wrong_links: Vec<(u32, u32)> = Vec::new();
+        let mut used_blocks: BTreeSet<u32> = BTreeSet::new();
+        let inodes = links.iter().map(|(&inode_id, &count)| (inode_id, count));
+        for (inode_id, count) in inodes.chain(removed.iter().map(|&inode_id| (inode_id, 0))) {
+            let (_, nlink, blocks) = read_disk_inode(fs, inode_id)?;
+            // the root directory has no entry referring to it
+            if inode_id != 0 && nlink != count {
+                problems.push(FsckProblem::WrongLinkCount { inode_id, nlink, links: count });
+                wrong_links.push((inode_id, count));
+            }
+            for block_id in blocks {
+                if block_id < data_area_start || block_id >= data_area_end {
+                    problems.push(FsckProblem::BadBlock { inode_id, block_id });
+                } else if !used_blocks.insert(block_id) {
+                    problems.push(FsckProblem::DuplicateBlock { inode_id, block_id });
+                }
+            }
+        }
+        // check allocated inodes against the directory tree
+        let mut orphans: Vec<u32> = Vec::new();
+        for inode_id in 0..inode_num {
+            if fs.inode_bitmap.is_allocated(&block_device, inode_id as usize)?
+                && !links.contains_key(&inode_id)
+                && !removed.contains(&inode_id) {
+                problems.push(FsckProblem::OrphanInode { inode_id });
+                orphans.push(inode_id);
+            }
+        }
+        // check the data bitmap against the blocks in use
+        let mut unmarked: Vec<u32> = Vec::new();
+        let mut leaked: Vec<u32> = Vec::new();
+        for block_id in data_area_start..data_area_end {
+            let allocated = fs.data_bitmap.is_allocated(
+                &block_device,
+                (block_id - data_area_start) as usize,
+            )?;
+            match (allocated, used_blocks.contains(&block_id)) {
+                (false, true) => {
+                    problems.push(FsckProblem::UnmarkedBlock { block_id });
+                    unmarked.push(block_id);
+                }
+                (true, false) => {
+                    problems.push(FsckProblem::LeakedBlock { block_id });
+                    leaked.push(block_id);
+                }
+                _ => {}
+            }
+        }
+        if !repair || problems.is_empty() {
+            return Ok(FsckReport { problems, repaired: false });
+        }
+        // repair, each fix being a transaction of its own
+        for (dir_inode_id, slot, slots) in dangling {
+            let (block_id, block_offset) = fs.get_disk_inode_pos(dir_inode_id);
+            let result = get_block_cache(block_id as usize, Arc::clone(&block_device))?
+                .lock()
+                .modify(block_offset, |disk_inode: &mut DiskInode| {
+                    disk_inode.write_at(slot * DIRENT_SZ, &vec![0u8; slots * DIRENT_SZ], &block_device)
+                });
+            fs.commit_or_abort(result)?;
+        }
+        for (inode_id, count) in wrong_links {
+            let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+            get_block_cache(block_id as usize, Arc::clone(&block_device))?
+                .lock()
+                .modify(block_offset, |disk_inode: &mut DiskInode| {
+                    disk_inode.nlink = count;
+                });
+            fs.commit()?;
+        }
+        // blocks of orphaned inodes are not in use, so they are released as leaked blocks
+        for inode_id in orphans {
+            let result = fs.dealloc_inode(inode_id);
+            fs.commit_or_abort(result)?;
+        }
+        for block_id in unmarked {
+            let result = fs.data_bitmap.set_allocated(&block_device, (block_id - data_area_start) as usize);
+            fs.commit_or_abort(result)?;
+        }
+        for block_id in leaked {
+            let result = fs.dealloc_data(block_id);
+            fs.commit_or_abort(result)?;
+        }
+        Ok(FsckReport { problems, repaired: true })
+    }
+}
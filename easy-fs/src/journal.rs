@@ -0,0 +1,173 @@
+use super::{
+    BlockDevice,
+    BLOCK_SZ,
+    IoError,
+    IoResult,
+    block_cache_dirty,
+    block_cache_discard,
+    block_cache_sync_batch,
+};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The max number of blocks a log header can describe
+const LOG_HEADER_CAPACITY: usize = BLOCK_SZ / 4 - 1;
+
+/// Header block of the log, written in a single block write
+/// so that a transaction is either wholly committed or not at all
+#[repr(C)]
+struct LogHeader {
+    /// number of blocks in the committed transaction, 0 if there is none
+    count: u32,
+    /// home block ids of the logged blocks
+    block_ids: [u32; LOG_HEADER_CAPACITY],
+}
+
+/// A log block
+type LogBlock = [u8; BLOCK_SZ];
+
+/// A write-ahead log over a region of a block device.
+///
+/// The region starts with a header block followed by log blocks.
+/// Blocks modified by a transaction are first copied into the log blocks,
+/// then the header is written as the commit point, then the blocks are
+/// installed to their home locations and the header is cleared. If a crash
+/// happens after the commit point, the transaction is installed again by
+/// `recover`, otherwise it is discarded as a whole.
+///
+/// If a write fails after the commit point, the transaction stays in the log
+/// and is installed again before the next one overwrites it.
+pub struct Journal {
+    header_block_id: usize,
+    log_blocks: usize,
+    /// whether the header may describe a transaction not completely installed
+    pending: AtomicBool,
+}
+
+impl Journal {
+    /// A journal over `blocks` blocks starting from `start_block_id`
+    pub fn new(start_block_id: usize, blocks: usize) -> Self {
+        assert!(blocks >= 2, "The log needs a header and at least one log block!");
+        Self {
+            header_block_id: start_block_id,
+            log_blocks: (blocks - 1).min(LOG_HEADER_CAPACITY),
+            pending: AtomicBool::new(false),
+        }
+    }
+    /// Get the number of blocks a transaction can modify
+    pub fn capacity(&self) -> usize {
+        self.log_blocks
+    }
+    /// Read the header directly from the block device
+    fn read_header(&self, block_device: &Arc<dyn BlockDevice>) -> IoResult<LogHeader> {
+        let mut header = LogHeader {
+            count: 0,
+            block_ids: [0; LOG_HEADER_CAPACITY],
+        };
+        block_device.read_block(self.header_block_id, header.as_bytes_mut())?;
+        Ok(header)
+    }
+    /// Write the header directly to the block device
+    fn write_header(&self, header: &LogHeader, block_device: &Arc<dyn BlockDevice>) -> IoResult<()> {
+        block_device.write_block(self.header_block_id, header.as_bytes())
+    }
+    /// Write all modified cached blocks of the block device back as one transaction.
+    ///
+    /// An error means that the transaction is not committed, the modified blocks are
+    /// then left as they are. Once the commit point is written the transaction is
+    /// durable, so a failure to install it only defers the installation.
+    pub fn commit(&self, block_device: &Arc<dyn BlockDevice>) -> IoResult<()> {
+        if self.pending.load(Ordering::Acquire) {
+            self.recover(block_device)?;
+        }
+        let dirty = block_cache_dirty(block_device);
+        if dirty.is_empty() {
+            return Ok(());
+        }
+        assert!(dirty.len() <= self.log_blocks, "Transaction too large for the log!");
+        let mut dirty: Vec<_> = dirty.iter().map(|block_cache| block_cache.lock()).collect();
+        let mut header = LogHeader {
+            count: dirty.len() as u32,
+            block_ids: [0; LOG_HEADER_CAPACITY],
+        };
+        // copy modified blocks into the log in a single request
+        let mut log: Vec<u8> = Vec::with_capacity(dirty.len() * BLOCK_SZ);
+        for (i, block_cache) in dirty.iter().enumerate() {
+            header.block_ids[i] = block_cache.block_id() as u32;
+            block_cache.read(0, |log_block: &LogBlock| {
+                log.extend_from_slice(log_block);
+            });
+        }
+        block_device.write_blocks(self.header_block_id + 1, &log)?;
+        // commit point
+        if let Err(err) = self.write_header(&header, block_device) {
+            // the header may have been written anyway
+            self.pending.store(true, Ordering::Release);
+            return Err(err);
+        }
+        // install the transaction, blocks which fail to be written stay modified
+        // and are written again by the next transaction
+        header.count = 0;
+        if block_cache_sync_batch(block_device, &mut dirty).is_err()
+            || self.write_header(&header, block_device).is_err()
+        {
+            self.pending.store(true, Ordering::Release);
+        }
+        Ok(())
+    }
+    /// Drop all modified cached blocks of the block device instead of committing them
+    pub fn abort(&self, block_device: &Arc<dyn BlockDevice>) {
+        // blocks of a transaction not completely installed stay modified, and would be
+        // read back from their home locations once dropped, so it is installed first
+        if self.pending.load(Ordering::Acquire) {
+            let _ = self.recover(block_device);
+        }
+        block_cache_discard(block_device);
+    }
+    /// Install the transaction left committed by a crash or a failed write, if there is one.
+    /// A header describing more blocks than the log holds, or blocks of the log itself,
+    /// is reported as corrupt.
+    pub fn recover(&self, block_device: &Arc<dyn BlockDevice>) -> IoResult<()> {
+        let mut header = self.read_header(block_device)?;
+        let count = header.count as usize;
+        let log_region = self.header_block_id..self.header_block_id + 1 + self.log_blocks;
+        if count > self.log_blocks
+            || header.block_ids[..count].iter().any(|&block_id| log_region.contains(&(block_id as usize))) {
+            return Err(IoError::Corrupt);
+        }
+        if count != 0 {
+            let mut log = vec![0u8; count * BLOCK_SZ];
+            block_device.read_blocks(self.header_block_id + 1, &mut log)?;
+            for (i, log_block) in log.chunks(BLOCK_SZ).enumerate() {
+                block_device.write_block(header.block_ids[i] as usize, log_block)?;
+            }
+            header.count = 0;
+            self.write_header(&header, block_device)?;
+        }
+        self.pending.store(false, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl LogHeader {
+    /// Serialize into bytes
+    fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self as *const _ as usize as *const u8,
+                BLOCK_SZ,
+            )
+        }
+    }
+    /// Serialize into mutable bytes
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self as *mut _ as usize as *mut u8,
+                BLOCK_SZ,
+            )
+        }
+    }
+}
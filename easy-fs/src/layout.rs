@@ -2,17 +2,47 @@ use core::fmt::{Debug, Formatter, Result};
 use super::{
     BLOCK_SZ,
     BlockDevice,
+    IoResult,
     get_block_cache,
+    block_cache_prefetch,
 };
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 
-/// Magic number for sanity check
-const EFS_MAGIC: u32 = 0x3b800001;
+/// Magic number for sanity check, changed along with the on-disk format
+const EFS_MAGIC: u32 = 0x3b800004;
 /// The max number of direct inodes
-const INODE_DIRECT_COUNT: usize = 28;
+const INODE_DIRECT_COUNT: usize = 16;
 /// The max length of inode name
-const NAME_LENGTH_LIMIT: usize = 27;
+pub const NAME_LENGTH_LIMIT: usize = 255;
+/// The max length of a name held in a single directory entry slot,
+/// which is also the max length of inode name without the long name format
+pub const SHORT_NAME_LENGTH_LIMIT: usize = 27;
+/// The number of name bytes held by an extension slot of a long name
+const EXT_NAME_LENGTH: usize = DIRENT_SZ - 1;
+/// The first byte of an extension slot, which never starts a UTF-8 name
+const EXT_SLOT_MARK: u8 = 0xff;
+/// Feature flag of the super block for long names in directory entries
+pub const FEATURE_LONG_NAMES: u32 = 1;
+/// Feature flag of the super block for new inodes mapping their data by extents
+pub const FEATURE_EXTENTS: u32 = 2;
+/// Flag of a disk inode mapping its data by extents
+const INODE_EXTENTS: u32 = 1;
+/// The permission bits of a new file
+pub const DEFAULT_FILE_MODE: u32 = 0o644;
+/// The permission bits of a new directory
+pub const DEFAULT_DIR_MODE: u32 = 0o755;
+/// The permission bits of a new symbolic link, which are never checked
+pub const DEFAULT_SYMLINK_MODE: u32 = 0o777;
+/// The max length of the target path of a symbolic link
+pub const SYMLINK_MAX_LEN: usize = BLOCK_SZ;
+/// The max length of the name of an extended attribute
+pub const XATTR_NAME_MAX: usize = 255;
+/// Size of the header of an extended attribute in an attribute block:
+/// the length of the name, 0 for the end of the attributes, and the length of the value
+const XATTR_HEADER_SZ: usize = 3;
 /// The max number of indirect1 inodes
 const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
 /// The max number of indirect2 inodes
@@ -22,8 +52,23 @@ const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
 /// The upper bound of indirect1 inode index
 const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
 /// The upper bound of indirect2 inode index
-#[allow(unused)]
 const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
+/// The max size of a file
+pub const MAX_FILE_SIZE: usize = INDIRECT2_BOUND * BLOCK_SZ;
+/// The max number of extents held by a disk inode itself, in its direct inodes
+const INODE_EXTENT_COUNT: usize = INODE_DIRECT_COUNT / 2;
+/// The number of extents in an extent block
+const EXTENT_BLOCK_COUNT: usize = BLOCK_SZ / core::mem::size_of::<Extent>();
+/// The max number of extent blocks under the extent index block
+const EXTENT_INDEX_COUNT: usize = 16;
+/// The upper bound of the extent index in the extent block of indirect1
+const EXTENT1_BOUND: usize = INODE_EXTENT_COUNT + EXTENT_BLOCK_COUNT;
+/// The upper bound of the extent index in the extent blocks under indirect2
+const EXTENT2_BOUND: usize = EXTENT1_BOUND + EXTENT_INDEX_COUNT * EXTENT_BLOCK_COUNT;
+/// The max size of a file mapped by extents, the largest number of whole blocks the size
+/// of a disk inode can hold, as an extent maps any number of consecutive blocks.
+/// A file too fragmented for the extent table cannot grow anymore before that.
+pub const MAX_EXTENT_FILE_SIZE: usize = u32::MAX as usize / BLOCK_SZ * BLOCK_SZ;
 
 /// Super block of a filesystem
 #[repr(C)]
@@ -34,6 +79,10 @@ pub struct SuperBlock {
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
     pub data_area_blocks: u32,
+    /// blocks of the write-ahead log right after the super block
+    pub log_blocks: u32,
+    /// flags of format features, 0 for the formats before them
+    pub features: u32,
 }
 
 impl Debug for SuperBlock {
@@ -44,12 +93,15 @@ impl Debug for SuperBlock {
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("log_blocks", &self.log_blocks)
+            .field("features", &self.features)
             .finish()
     }
 }
 
 impl SuperBlock {
     /// Initialize a super block
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize(
         &mut self,
         total_blocks: u32,
@@ -57,6 +109,8 @@ impl SuperBlock {
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
         data_area_blocks: u32,
+        log_blocks: u32,
+        features: u32,
     ) {
         *self = Self {
             magic: EFS_MAGIC,
@@ -65,6 +119,8 @@ impl SuperBlock {
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            log_blocks,
+            features,
         }
     }
     /// Check if a super block is valid using efs magic
@@ -74,36 +130,104 @@ impl SuperBlock {
 }
 
 /// Type of a disk inode
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum DiskInodeType {
     File,
     Directory,
+    /// a symbolic link, whose data is the path it refers to
+    SymLink,
+}
+
+/// How a disk inode maps its data to blocks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockMapping {
+    /// Direct blocks followed by the blocks under an indirect1 and an indirect2 block
+    Indirect,
+    /// Extents held by the inode followed by those in extent blocks
+    Extents,
+}
+
+impl BlockMapping {
+    /// Get the max size of a file mapped this way
+    pub fn max_file_size(&self) -> usize {
+        match self {
+            BlockMapping::Indirect => MAX_FILE_SIZE,
+            BlockMapping::Extents => MAX_EXTENT_FILE_SIZE,
+        }
+    }
+}
+
+/// An extent, which maps `len` blocks of a file to the consecutive blocks
+/// from `start`, or to a hole if `start` is 0. An extent of length 0 ends the list.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Extent {
+    start: u32,
+    len: u32,
 }
 
 /// A indirect block
 type IndirectBlock = [u32; BLOCK_SZ / 4];
+/// A block of extents
+type ExtentBlock = [Extent; EXTENT_BLOCK_COUNT];
 /// A data block
 type DataBlock = [u8; BLOCK_SZ];
 
-/// A disk inode
+/// A disk inode.
+///
+/// With extents, the direct inodes hold the first `INODE_EXTENT_COUNT` extents,
+/// indirect1 is an extent block holding the next ones and indirect2 is an index
+/// of the extent blocks holding the rest.
 #[repr(C)]
+#[derive(Clone)]
 pub struct DiskInode {
     pub size: u32,
     pub direct: [u32; INODE_DIRECT_COUNT],
     pub indirect1: u32,
     pub indirect2: u32,
+    /// number of directory entries referring to this inode,
+    /// '.' and '..' are not counted
+    pub nlink: u32,
+    /// permission bits
+    pub mode: u32,
+    /// id of the owner
+    pub uid: u32,
+    /// id of the owner group
+    pub gid: u32,
+    /// block holding the extended attributes, 0 if there are none
+    xattr: u32,
+    /// time of the last access, in seconds
+    pub atime: u64,
+    /// time of the last modification of the data, in seconds
+    pub mtime: u64,
+    /// time of the last change of the data or metadata, in seconds
+    pub ctime: u64,
     type_: DiskInodeType,
+    /// flags of the inode, 0 for the inodes before them
+    flags: u32,
 }
 
 impl DiskInode {
     /// Initialize a disk inode, as well as all direct inodes under it
     /// indirect1 and indirect2 block are allocated only when they are needed
-    pub fn initialize(&mut self, type_: DiskInodeType) {
+    pub fn initialize(&mut self, type_: DiskInodeType, mode: u32, time: u64, mapping: BlockMapping) {
         self.size = 0;
         self.direct.iter_mut().for_each(|v| *v = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
+        self.nlink = 1;
+        self.mode = mode;
+        self.uid = 0;
+        self.gid = 0;
+        self.xattr = 0;
+        self.atime = time;
+        self.mtime = time;
+        self.ctime = time;
         self.type_ = type_;
+        self.flags = match mapping {
+            BlockMapping::Indirect => 0,
+            BlockMapping::Extents => INODE_EXTENTS,
+        };
     }
     /// Whether this inode is a directory
     pub fn is_dir(&self) -> bool {
@@ -114,6 +238,18 @@ impl DiskInode {
     pub fn is_file(&self) -> bool {
         self.type_ == DiskInodeType::File
     }
+    /// Whether this inode is a symbolic link
+    pub fn is_symlink(&self) -> bool {
+        self.type_ == DiskInodeType::SymLink
+    }
+    /// Get how this inode maps its data to blocks
+    pub fn mapping(&self) -> BlockMapping {
+        if self.flags & INODE_EXTENTS != 0 {
+            BlockMapping::Extents
+        } else {
+            BlockMapping::Indirect
+        }
+    }
     /// Get the number of data blocks corresponding to size
     pub fn data_blocks(&self) -> u32 {
         Self::_data_blocks(self.size)
@@ -121,10 +257,15 @@ impl DiskInode {
     fn _data_blocks(size: u32) -> u32 {
         (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32
     }
-    /// Get the number of data blocks required for the given size of data
-    pub fn total_blocks(size: u32) -> u32 {
+    /// Get the number of blocks taken by data of the given size without holes,
+    /// including the indirect blocks, or the extent blocks in the worst case
+    /// of an extent for each block
+    pub fn total_blocks(size: u32, mapping: BlockMapping) -> u32 {
         let data_blocks = Self::_data_blocks(size) as usize;
-        let mut total = data_blocks as usize;
+        if mapping == BlockMapping::Extents {
+            return (data_blocks + extent_blocks(data_blocks.min(EXTENT2_BOUND))) as u32;
+        }
+        let mut total = data_blocks;
         // indirect1
         if data_blocks > INODE_DIRECT_COUNT {
             total += 1;
@@ -133,215 +274,572 @@ impl DiskInode {
         if data_blocks > INDIRECT1_BOUND {
             total += 1;
             // sub indirect1
-            total += (data_blocks - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+            total += ceil_div(data_blocks - INDIRECT1_BOUND, INODE_INDIRECT1_COUNT);
         }
         total as u32
     }
-    /// Get the number of data blocks that have to be allocated given the new size of data
-    pub fn blocks_num_needed(&self, new_size: u32) -> u32 {
-        assert!(new_size >= self.size);
-        Self::total_blocks(new_size) - Self::total_blocks(self.size)
-    }
-    /// Get id of block given inner id
-    pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
+    /// Get id of block given inner id, which is 0 for a hole
+    pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> IoResult<u32> {
+        if self.mapping() == BlockMapping::Extents {
+            let mut first = 0;
+            for extent in self.extents(block_device)? {
+                if inner_id < first + extent.len {
+                    return Ok(match extent.start {
+                        0 => 0,
+                        start => start + inner_id - first,
+                    });
+                }
+                first += extent.len;
+            }
+            // past the last extent
+            return Ok(0);
+        }
         let inner_id = inner_id as usize;
         if inner_id < INODE_DIRECT_COUNT {
-            self.direct[inner_id]
+            Ok(self.direct[inner_id])
         } else if inner_id < INDIRECT1_BOUND {
-            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
-                    indirect_block[inner_id - INODE_DIRECT_COUNT]
-                })
+            read_indirect(self.indirect1, inner_id - INODE_DIRECT_COUNT, block_device)
         } else {
             let last = inner_id - INDIRECT1_BOUND;
-            let indirect1 = get_block_cache(
-                self.indirect2 as usize,
-                Arc::clone(block_device)
-            )
-            .lock()
-            .read(0, |indirect2: &IndirectBlock| {
-                indirect2[last / INODE_INDIRECT1_COUNT]
-            });
-            get_block_cache(
-                indirect1 as usize,
-                Arc::clone(block_device)
-            )
-            .lock()
-            .read(0, |indirect1: &IndirectBlock| {
-                indirect1[last % INODE_INDIRECT1_COUNT]
-            })
+            let indirect1 = read_indirect(
+                self.indirect2,
+                last / INODE_INDIRECT1_COUNT,
+                block_device,
+            )?;
+            read_indirect(indirect1, last % INODE_INDIRECT1_COUNT, block_device)
         }
     }
-    /// Inncrease the size of current disk inode
-    pub fn increase_size(
+    /// Get the number of blocks that have to be allocated to map the block of the given
+    /// inner id, including the indirect or extent blocks leading to it,
+    /// or None if the block cannot be mapped since the file is at its max size
+    /// or has run out of extents
+    pub fn blocks_num_needed(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> IoResult<Option<u32>> {
+        let inner_id = inner_id as usize;
+        if inner_id >= self.mapping().max_file_size() / BLOCK_SZ {
+            return Ok(None);
+        }
+        if self.get_block_id(inner_id as u32, block_device)? != 0 {
+            return Ok(Some(0));
+        }
+        if self.mapping() == BlockMapping::Extents {
+            let extents = self.extents(block_device)?;
+            // a block which does not follow the extent before it, in the worst case
+            let count = map_extent(&extents, inner_id as u32, u32::MAX).len();
+            if count > EXTENT2_BOUND {
+                return Ok(None);
+            }
+            return Ok(Some((1 + extent_blocks(count).saturating_sub(extent_blocks(extents.len()))) as u32));
+        }
+        Ok(Some(if inner_id < INODE_DIRECT_COUNT {
+            1
+        } else if inner_id < INDIRECT1_BOUND {
+            if self.indirect1 == 0 { 2 } else { 1 }
+        } else if self.indirect2 == 0 {
+            3
+        } else {
+            let last = inner_id - INDIRECT1_BOUND;
+            match read_indirect(self.indirect2, last / INODE_INDIRECT1_COUNT, block_device)? {
+                0 => 2,
+                _ => 1,
+            }
+        }))
+    }
+    /// Map the block of the given inner id if it is a hole, as well as the indirect or
+    /// extent blocks leading to it, with `blocks_num_needed` new blocks, the first of which
+    /// is for the data. Return the blocks left unused, which should be deallocated.
+    pub fn map_block(
         &mut self,
-        new_size: u32,
+        inner_id: u32,
         new_blocks: Vec<u32>,
         block_device: &Arc<dyn BlockDevice>,
-    ) {
-        let mut current_blocks = self.data_blocks();
-        self.size = new_size;
-        let mut total_blocks = self.data_blocks();
-        let mut new_blocks = new_blocks.into_iter();
-        // fill direct
-        while current_blocks < total_blocks.min(INODE_DIRECT_COUNT as u32) {
-            self.direct[current_blocks as usize] = new_blocks.next().unwrap();
-            current_blocks += 1;
-        }
-        // alloc indirect1
-        if total_blocks > INODE_DIRECT_COUNT as u32{
-            if current_blocks == INODE_DIRECT_COUNT as u32 {
+    ) -> IoResult<Vec<u32>> {
+        if new_blocks.is_empty() {
+            // mapped already
+            return Ok(new_blocks);
+        }
+        if self.mapping() == BlockMapping::Extents {
+            let mut new_blocks = new_blocks.into_iter();
+            let extents = self.extents(block_device)?;
+            let mapped = map_extent(&extents, inner_id, new_blocks.next().unwrap());
+            self.store_extents(&extents, &mapped, &mut new_blocks, block_device)?;
+            // the data block may follow or fill the gap between the extents around it,
+            // leaving some of the extent blocks unnecessary
+            let mut unused: Vec<u32> = new_blocks.collect();
+            unused.extend(self.release_extent_blocks(mapped.len(), block_device)?);
+            return Ok(unused);
+        }
+        let inner_id = inner_id as usize;
+        // the indirect blocks are mapped before the data block
+        let mut new_blocks = new_blocks[1..].iter().copied().chain(core::iter::once(new_blocks[0]));
+        if inner_id < INODE_DIRECT_COUNT {
+            if self.direct[inner_id] == 0 {
+                self.direct[inner_id] = new_blocks.next().unwrap();
+            }
+        } else if inner_id < INDIRECT1_BOUND {
+            if self.indirect1 == 0 {
                 self.indirect1 = new_blocks.next().unwrap();
             }
-            current_blocks -= INODE_DIRECT_COUNT as u32;
-            total_blocks -= INODE_DIRECT_COUNT as u32;
+            map_indirect(self.indirect1, inner_id - INODE_DIRECT_COUNT, &mut new_blocks, block_device)?;
         } else {
-            return;
-        }
-        // fill indirect1
-        get_block_cache(
-            self.indirect1 as usize,
-            Arc::clone(block_device)
-        )
-        .lock()
-        .modify(0, |indirect1: &mut IndirectBlock| {
-            while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT as u32) {
-                indirect1[current_blocks as usize] = new_blocks.next().unwrap();
-                current_blocks += 1;
-            }
-        });
-        // alloc indirect2
-        if total_blocks > INODE_INDIRECT1_COUNT as u32 {
-            if current_blocks == INODE_INDIRECT1_COUNT as u32 {
+            let last = inner_id - INDIRECT1_BOUND;
+            if self.indirect2 == 0 {
                 self.indirect2 = new_blocks.next().unwrap();
             }
-            current_blocks -= INODE_INDIRECT1_COUNT as u32;
-            total_blocks -= INODE_INDIRECT1_COUNT as u32;
-        } else {
-            return;
+            let indirect1 = map_indirect(
+                self.indirect2,
+                last / INODE_INDIRECT1_COUNT,
+                &mut new_blocks,
+                block_device,
+            )?;
+            map_indirect(indirect1, last % INODE_INDIRECT1_COUNT, &mut new_blocks, block_device)?;
         }
-        // fill indirect2 from (a0, b0) -> (a1, b1)
-        let mut a0 = current_blocks as usize / INODE_INDIRECT1_COUNT;
-        let mut b0 = current_blocks as usize % INODE_INDIRECT1_COUNT;
-        let a1 = total_blocks as usize / INODE_INDIRECT1_COUNT;
-        let b1 = total_blocks as usize % INODE_INDIRECT1_COUNT;
-        // alloc low-level indirect1
-        get_block_cache(
-            self.indirect2 as usize,
-            Arc::clone(block_device)
-        )
-        .lock()
-        .modify(0, |indirect2: &mut IndirectBlock| {
-            while (a0 < a1) || (a0 == a1 && b0 < b1) {
-                if b0 == 0 {
-                    indirect2[a0] = new_blocks.next().unwrap();
+        assert!(new_blocks.next().is_none());
+        Ok(Vec::new())
+    }
+    /// Get the size between `new_size` and the current size to shrink current disk inode to,
+    /// so that the blocks it deallocates fall in at most `max_groups` groups as given by `group`.
+    /// Shrinking a file in such steps bounds the bitmap blocks modified by each of them.
+    pub fn shrink_step(
+        &self,
+        new_size: u32,
+        max_groups: usize,
+        group: impl Fn(u32) -> usize,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> IoResult<u32> {
+        let kept_blocks = Self::_data_blocks(new_size) as usize;
+        let mut groups: Vec<usize> = Vec::new();
+        // whether the groups of the blocks deallocated so far and `block_id` are within bound
+        let mut fits = |block_id: u32| {
+            if block_id != 0 && !groups.contains(&group(block_id)) {
+                groups.push(group(block_id));
+            }
+            groups.len() <= max_groups
+        };
+        // the size keeping the blocks before `inner_id`
+        let size_before = |inner_id: usize| ((inner_id * BLOCK_SZ) as u32).min(self.size);
+        if self.mapping() == BlockMapping::Extents {
+            let extents = self.extents(block_device)?;
+            let mut end: usize = extents.iter().map(|extent| extent.len as usize).sum();
+            for (i, extent) in extents.iter().enumerate().rev() {
+                let first = end - extent.len as usize;
+                // the extent blocks modified are bounded as well
+                if extents.len() - i > EXTENT_BLOCK_COUNT {
+                    return Ok(size_before(end));
                 }
-                // fill current
-                get_block_cache(
-                    indirect2[a0] as usize,
-                    Arc::clone(block_device)
-                )
-                .lock()
-                .modify(0, |indirect1: &mut IndirectBlock| {
-                    indirect1[b0] = new_blocks.next().unwrap();
-                });
-                // move to next
-                b0 += 1;
-                if b0 == INODE_INDIRECT1_COUNT {
-                    b0 = 0;
-                    a0 += 1;
+                for inner_id in (first.max(kept_blocks)..end).rev() {
+                    if extent.start != 0 && !fits(extent.start + (inner_id - first) as u32) {
+                        return Ok(size_before(inner_id + 1));
+                    }
+                }
+                if first <= kept_blocks {
+                    break;
+                }
+                end = first;
+            }
+            return Ok(new_size);
+        }
+        for inner_id in (kept_blocks..self.data_blocks() as usize).rev() {
+            let mut blocks = vec![self.get_block_id(inner_id as u32, block_device)?];
+            // indirect blocks left empty once the block is deallocated
+            if inner_id == DIRECT_BOUND {
+                blocks.push(self.indirect1);
+            }
+            if inner_id >= INDIRECT1_BOUND {
+                let index = inner_id - INDIRECT1_BOUND;
+                let (a, b) = (index / INODE_INDIRECT1_COUNT, index % INODE_INDIRECT1_COUNT);
+                if b == 0 {
+                    blocks.push(read_indirect(self.indirect2, a, block_device)?);
                 }
             }
-        });
+            if inner_id == INDIRECT1_BOUND {
+                blocks.push(self.indirect2);
+            }
+            for block_id in blocks {
+                if !fits(block_id) {
+                    return Ok(size_before(inner_id + 1));
+                }
+            }
+        }
+        Ok(new_size)
     }
-    /// Clear size to zero and return blocks that should be deallocated
-    /// and clear the block contents to zero later
-    pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+    /// Change the size of current disk inode and return blocks that should be deallocated.
+    ///
+    /// Growing leaves a hole up to the new size. Shrinking releases the data blocks past
+    /// the new size as well as the indirect blocks left empty, and clears the rest of the
+    /// last block, since data past the size must read as zero once the file grows again.
+    pub fn truncate(&mut self, new_size: u32, block_device: &Arc<dyn BlockDevice>) -> IoResult<Vec<u32>> {
+        assert!(new_size as usize <= self.mapping().max_file_size());
         let mut v: Vec<u32> = Vec::new();
-        let mut data_blocks = self.data_blocks() as usize;
-        self.size = 0;
-        let mut current_blocks = 0usize;
-        // direct
-        while current_blocks < data_blocks.min(INODE_DIRECT_COUNT) {
-            v.push(self.direct[current_blocks]);
-            self.direct[current_blocks] = 0;
-            current_blocks += 1;
+        if new_size >= self.size {
+            self.size = new_size;
+            return Ok(v);
         }
-        // indirect1 block
-        if data_blocks > INODE_DIRECT_COUNT {
-            v.push(self.indirect1);
-            data_blocks -= INODE_DIRECT_COUNT;
-            current_blocks = 0;
-        } else {
-            return v;
+        let kept_blocks = Self::_data_blocks(new_size) as usize;
+        let data_blocks = self.data_blocks() as usize;
+        self.size = new_size;
+        // clear the rest of the last block
+        let tail = new_size as usize % BLOCK_SZ;
+        if tail > 0 {
+            let block_id = self.get_block_id(kept_blocks as u32 - 1, block_device)?;
+            if block_id != 0 {
+                get_block_cache(block_id as usize, Arc::clone(block_device))?
+                    .lock()
+                    .modify(0, |data_block: &mut DataBlock| {
+                        data_block[tail..].iter_mut().for_each(|p| *p = 0);
+                    });
+            }
+        }
+        if self.mapping() == BlockMapping::Extents {
+            let extents = self.extents(block_device)?;
+            let mut kept: Vec<Extent> = Vec::new();
+            let mut first = 0;
+            for extent in extents.iter() {
+                let keep = (kept_blocks as u32).saturating_sub(first).min(extent.len);
+                if keep > 0 {
+                    kept.push(Extent { start: extent.start, len: keep });
+                }
+                if extent.start != 0 {
+                    v.extend(extent.start + keep..extent.start + extent.len);
+                }
+                first += extent.len;
+            }
+            // the blocks past the last extent are holes already
+            while matches!(kept.last(), Some(extent) if extent.start == 0) {
+                kept.pop();
+            }
+            self.store_extents(&extents, &kept, &mut core::iter::empty(), block_device)?;
+            v.extend(self.release_extent_blocks(kept.len(), block_device)?);
+            return Ok(v);
+        }
+        // direct
+        for inner_id in kept_blocks.min(INODE_DIRECT_COUNT)..data_blocks.min(INODE_DIRECT_COUNT) {
+            if self.direct[inner_id] != 0 {
+                v.push(self.direct[inner_id]);
+                self.direct[inner_id] = 0;
+            }
         }
         // indirect1
-        get_block_cache(
-            self.indirect1 as usize,
-            Arc::clone(block_device),
-        )
-        .lock()
-        .modify(0, |indirect1: &mut IndirectBlock| {
-            while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
-                v.push(indirect1[current_blocks]);
-                //indirect1[current_blocks] = 0;
-                current_blocks += 1;
+        if data_blocks > INODE_DIRECT_COUNT && self.indirect1 != 0 {
+            let from = kept_blocks.saturating_sub(INODE_DIRECT_COUNT);
+            let to = (data_blocks - INODE_DIRECT_COUNT).min(INODE_INDIRECT1_COUNT);
+            release_indirect(self.indirect1, from, to, &mut v, block_device)?;
+            if from == 0 {
+                v.push(self.indirect1);
+                self.indirect1 = 0;
             }
-        });
-        self.indirect1 = 0;
-        // indirect2 block
-        if data_blocks > INODE_INDIRECT1_COUNT {
-            v.push(self.indirect2);
-            data_blocks -= INODE_INDIRECT1_COUNT;
-        } else {
-            return v;
         }
         // indirect2
-        assert!(data_blocks <= INODE_INDIRECT2_COUNT);
-        let a1 = data_blocks / INODE_INDIRECT1_COUNT;
-        let b1 = data_blocks % INODE_INDIRECT1_COUNT;
-        get_block_cache(
-            self.indirect2 as usize,
-            Arc::clone(block_device),
-        )
-        .lock()
-        .modify(0, |indirect2: &mut IndirectBlock| {
-            // full indirect1 blocks
-            for i in 0..a1 {
-                v.push(indirect2[i]);
-                get_block_cache(
-                    indirect2[i] as usize,
-                    Arc::clone(block_device),
-                )
-                .lock()
-                .modify(0, |indirect1: &mut IndirectBlock| {
-                    for j in 0..INODE_INDIRECT1_COUNT {
-                        v.push(indirect1[j]);
-                        //indirect1[j] = 0;
+        if data_blocks > INDIRECT1_BOUND && self.indirect2 != 0 {
+            let from = kept_blocks.saturating_sub(INDIRECT1_BOUND);
+            let to = data_blocks - INDIRECT1_BOUND;
+            // data blocks under each low-level indirect1 block
+            let a1 = ceil_div(to, INODE_INDIRECT1_COUNT);
+            for a in from / INODE_INDIRECT1_COUNT..a1 {
+                let indirect1 = read_indirect(self.indirect2, a, block_device)?;
+                if indirect1 != 0 {
+                    let base = a * INODE_INDIRECT1_COUNT;
+                    let b0 = from.saturating_sub(base);
+                    let b1 = (to - base).min(INODE_INDIRECT1_COUNT);
+                    release_indirect(indirect1, b0, b1, &mut v, block_device)?;
+                }
+            }
+            // low-level indirect1 blocks left empty
+            let a0 = ceil_div(from, INODE_INDIRECT1_COUNT);
+            release_indirect(self.indirect2, a0, a1, &mut v, block_device)?;
+            if from == 0 {
+                v.push(self.indirect2);
+                self.indirect2 = 0;
+            }
+        }
+        Ok(v)
+    }
+    /// Get all blocks held by current disk inode, including indirect blocks
+    /// and the attribute block
+    pub fn all_blocks(&self, block_device: &Arc<dyn BlockDevice>) -> IoResult<Vec<u32>> {
+        let mut v = self.mapped_blocks(block_device)?;
+        v.extend(Some(self.xattr).filter(|block_id| *block_id != 0));
+        Ok(v)
+    }
+    /// Get the blocks mapping the data of current disk inode, including indirect blocks
+    fn mapped_blocks(&self, block_device: &Arc<dyn BlockDevice>) -> IoResult<Vec<u32>> {
+        if self.mapping() == BlockMapping::Extents {
+            let mut v: Vec<u32> = Vec::new();
+            for extent in self.extents(block_device)? {
+                if extent.start != 0 {
+                    v.extend(extent.start..extent.start + extent.len);
+                }
+            }
+            if self.indirect1 != 0 {
+                v.push(self.indirect1);
+            }
+            if self.indirect2 != 0 {
+                v.push(self.indirect2);
+                v.extend(indirect_entries(self.indirect2, 0, EXTENT_INDEX_COUNT, block_device)?);
+            }
+            return Ok(v);
+        }
+        let data_blocks = self.data_blocks() as usize;
+        assert!(data_blocks <= INDIRECT2_BOUND);
+        let mut v: Vec<u32> = self.direct[..data_blocks.min(INODE_DIRECT_COUNT)]
+            .iter()
+            .copied()
+            .filter(|block_id| *block_id != 0)
+            .collect();
+        // indirect1
+        if data_blocks > INODE_DIRECT_COUNT && self.indirect1 != 0 {
+            v.push(self.indirect1);
+            let to = (data_blocks - INODE_DIRECT_COUNT).min(INODE_INDIRECT1_COUNT);
+            v.extend(indirect_entries(self.indirect1, 0, to, block_device)?);
+        }
+        // indirect2
+        if data_blocks > INDIRECT1_BOUND && self.indirect2 != 0 {
+            v.push(self.indirect2);
+            let to = data_blocks - INDIRECT1_BOUND;
+            let a1 = ceil_div(to, INODE_INDIRECT1_COUNT);
+            for a in 0..a1 {
+                let indirect1 = read_indirect(self.indirect2, a, block_device)?;
+                if indirect1 != 0 {
+                    v.push(indirect1);
+                    let b1 = (to - a * INODE_INDIRECT1_COUNT).min(INODE_INDIRECT1_COUNT);
+                    v.extend(indirect_entries(indirect1, 0, b1, block_device)?);
+                }
+            }
+        }
+        Ok(v)
+    }
+    /// Release the data and the extended attributes of current disk inode,
+    /// return the blocks to be deallocated
+    pub fn release(&mut self, block_device: &Arc<dyn BlockDevice>) -> IoResult<Vec<u32>> {
+        let mut v = self.truncate(0, block_device)?;
+        v.extend(self.clear_xattrs());
+        Ok(v)
+    }
+    /// Get the block holding the extended attributes, 0 if there are none
+    pub fn xattr_block(&self) -> u32 {
+        self.xattr
+    }
+    /// Get the extended attributes as (name, value), in the order they were first set
+    pub fn xattrs(&self, block_device: &Arc<dyn BlockDevice>) -> IoResult<Vec<(String, Vec<u8>)>> {
+        if self.xattr == 0 {
+            return Ok(Vec::new());
+        }
+        Ok(get_block_cache(self.xattr as usize, Arc::clone(block_device))?
+            .lock()
+            .read(0, |block: &DataBlock| {
+                let mut xattrs = Vec::new();
+                let mut pos = 0;
+                while pos + XATTR_HEADER_SZ <= BLOCK_SZ && block[pos] != 0 {
+                    let name_start = pos + XATTR_HEADER_SZ;
+                    let value_start = name_start + block[pos] as usize;
+                    let end = value_start + u16::from_le_bytes([block[pos + 1], block[pos + 2]]) as usize;
+                    if end > BLOCK_SZ {
+                        break;
                     }
-                });
-                //indirect2[i] = 0;
-            }
-            // last indirect1 block
-            if b1 > 0 {
-                v.push(indirect2[a1]);
-                get_block_cache(
-                    indirect2[a1] as usize,
-                    Arc::clone(block_device),
-                )
+                    let name = String::from_utf8_lossy(&block[name_start..value_start]).into_owned();
+                    xattrs.push((name, block[value_start..end].to_vec()));
+                    pos = end;
+                }
+                xattrs
+            }))
+    }
+    /// Store the extended attributes, which must fit in a block as `xattrs_size` tells,
+    /// in the attribute block `block_id`
+    pub fn set_xattrs(
+        &mut self,
+        xattrs: &[(String, Vec<u8>)],
+        block_id: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> IoResult<()> {
+        assert!(xattrs_size(xattrs) <= BLOCK_SZ);
+        get_block_cache(block_id as usize, Arc::clone(block_device))?
+            .lock()
+            .modify(0, |block: &mut DataBlock| {
+                block.fill(0);
+                let mut pos = 0;
+                for (name, value) in xattrs {
+                    block[pos] = name.len() as u8;
+                    block[pos + 1..pos + XATTR_HEADER_SZ].copy_from_slice(&(value.len() as u16).to_le_bytes());
+                    pos += XATTR_HEADER_SZ;
+                    block[pos..pos + name.len()].copy_from_slice(name.as_bytes());
+                    pos += name.len();
+                    block[pos..pos + value.len()].copy_from_slice(value);
+                    pos += value.len();
+                }
+            });
+        self.xattr = block_id;
+        Ok(())
+    }
+    /// Drop the extended attributes, return the attribute block to be deallocated
+    pub fn clear_xattrs(&mut self) -> Option<u32> {
+        match core::mem::take(&mut self.xattr) {
+            0 => None,
+            block_id => Some(block_id),
+        }
+    }
+    /// Get the extents of current disk inode in the order of the data they map
+    fn extents(&self, block_device: &Arc<dyn BlockDevice>) -> IoResult<Vec<Extent>> {
+        let mut v: Vec<Extent> = self.direct[..2 * INODE_EXTENT_COUNT]
+            .chunks(2)
+            .map(|pair| Extent { start: pair[0], len: pair[1] })
+            .take_while(|extent| extent.len > 0)
+            .collect();
+        if v.len() == INODE_EXTENT_COUNT && self.indirect1 != 0 {
+            v.extend(read_extents(self.indirect1, block_device)?);
+        }
+        if v.len() == EXTENT1_BOUND && self.indirect2 != 0 {
+            for index in 0..EXTENT_INDEX_COUNT {
+                let block_id = read_indirect(self.indirect2, index, block_device)?;
+                if block_id == 0 {
+                    break;
+                }
+                let extents = read_extents(block_id, block_device)?;
+                let full = extents.len() == EXTENT_BLOCK_COUNT;
+                v.extend(extents);
+                if !full {
+                    break;
+                }
+            }
+        }
+        Ok(v)
+    }
+    /// Replace the extents `old` of current disk inode with `new`, writing only those
+    /// which change, and map the extent blocks needed by the new ones with `new_blocks`.
+    /// The extent blocks left unnecessary are kept until `release_extent_blocks`.
+    fn store_extents(
+        &mut self,
+        old: &[Extent],
+        new: &[Extent],
+        new_blocks: &mut impl Iterator<Item = u32>,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> IoResult<()> {
+        let from = old.iter().zip(new.iter()).take_while(|(old, new)| old == new).count();
+        // the extents left past the new ones are cleared to end the list
+        let to = old.len().max(new.len());
+        for index in from..to {
+            let extent = new.get(index).copied().unwrap_or_default();
+            if index < INODE_EXTENT_COUNT {
+                self.direct[2 * index] = extent.start;
+                self.direct[2 * index + 1] = extent.len;
+                continue;
+            }
+            let (block_id, index) = if index < EXTENT1_BOUND {
+                if self.indirect1 == 0 {
+                    self.indirect1 = new_blocks.next().unwrap();
+                }
+                (self.indirect1, index - INODE_EXTENT_COUNT)
+            } else {
+                let last = index - EXTENT1_BOUND;
+                if self.indirect2 == 0 {
+                    self.indirect2 = new_blocks.next().unwrap();
+                }
+                let block_id = map_indirect(
+                    self.indirect2,
+                    last / EXTENT_BLOCK_COUNT,
+                    new_blocks,
+                    block_device,
+                )?;
+                (block_id, last % EXTENT_BLOCK_COUNT)
+            };
+            get_block_cache(block_id as usize, Arc::clone(block_device))?
                 .lock()
-                .modify(0, |indirect1: &mut IndirectBlock| {
-                    for j in 0..b1 {
-                        v.push(indirect1[j]);
-                        //indirect1[j] = 0;
-                    }
+                .modify(0, |extent_block: &mut ExtentBlock| {
+                    extent_block[index] = extent;
                 });
-                //indirect2[a1] = 0;
+        }
+        Ok(())
+    }
+    /// Unmap the extent blocks which are unnecessary for `count` extents
+    /// and return them
+    fn release_extent_blocks(
+        &mut self,
+        count: usize,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> IoResult<Vec<u32>> {
+        let mut v: Vec<u32> = Vec::new();
+        if self.indirect2 != 0 {
+            let from = ceil_div(count.saturating_sub(EXTENT1_BOUND), EXTENT_BLOCK_COUNT);
+            release_indirect(self.indirect2, from, EXTENT_INDEX_COUNT, &mut v, block_device)?;
+            if from == 0 {
+                v.push(self.indirect2);
+                self.indirect2 = 0;
             }
-        });
-        self.indirect2 = 0;
-        v
+        }
+        if count <= INODE_EXTENT_COUNT && self.indirect1 != 0 {
+            v.push(self.indirect1);
+            self.indirect1 = 0;
+        }
+        Ok(v)
+    }
+    /// Load the blocks holding data in [offset, offset + len) into the block cache,
+    /// physically consecutive blocks in a single request,
+    /// return the end of the data from offset which is cached afterwards
+    pub fn prefetch(&self, offset: usize, len: usize, block_device: &Arc<dyn BlockDevice>) -> IoResult<usize> {
+        let end = (offset + len).min(self.size as usize);
+        if offset >= end {
+            return Ok(offset);
+        }
+        // runs of consecutive blocks as (first block id, number of blocks),
+        // leaving out holes which read as zero without the device
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        let mut mapped: Vec<usize> = Vec::new();
+        for inner_id in offset / BLOCK_SZ..ceil_div(end, BLOCK_SZ) {
+            let block_id = self.get_block_id(inner_id as u32, block_device)? as usize;
+            if block_id == 0 {
+                continue;
+            }
+            mapped.push(inner_id);
+            match runs.last_mut() {
+                Some((first, count)) if *first + *count == block_id => *count += 1,
+                _ => runs.push((block_id, 1)),
+            }
+        }
+        let cached = block_cache_prefetch(&runs, Arc::clone(block_device));
+        Ok(match mapped.get(cached) {
+            Some(&inner_id) => (inner_id * BLOCK_SZ).max(offset),
+            None => end,
+        })
+    }
+    /// Get the directory entries of a directory disk inode with their first slots,
+    /// skipping corrupt entries
+    pub fn dirents(&self, block_device: &Arc<dyn BlockDevice>) -> IoResult<Vec<(usize, DirEntry)>> {
+        Ok(self
+            .dirent_slots(block_device)?
+            .into_iter()
+            .filter_map(|(slot, _, dirent)| Some((slot, dirent?)))
+            .collect())
+    }
+    /// Get the first slots and the numbers of slots of the directory entries of a
+    /// directory disk inode, with the entries or None for the corrupt ones.
+    /// A corrupt entry takes the extension slots following its first slot.
+    pub fn dirent_slots(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> IoResult<Vec<(usize, usize, Option<DirEntry>)>> {
+        let bytes = self.read_all(block_device)?;
+        let file_count = bytes.len() / DIRENT_SZ;
+        let is_ext = |i: usize| bytes[i * DIRENT_SZ] == EXT_SLOT_MARK;
+        let mut v: Vec<(usize, usize, Option<DirEntry>)> = Vec::new();
+        let mut slot = 0;
+        while slot < file_count {
+            if bytes[slot * DIRENT_SZ] == 0 || is_ext(slot) {
+                slot += 1;
+                continue;
+            }
+            let dirent = DirEntry::from_bytes(&bytes[slot * DIRENT_SZ..]);
+            let slots = match &dirent {
+                Some(dirent) => dirent.slots(),
+                None => 1 + (slot + 1..file_count).take_while(|&i| is_ext(i)).count(),
+            };
+            v.push((slot, slots, dirent));
+            slot += slots;
+        }
+        Ok(v)
+    }
+    /// Read all data of current disk inode
+    pub fn read_all(&self, block_device: &Arc<dyn BlockDevice>) -> IoResult<Vec<u8>> {
+        let mut bytes = vec![0u8; self.size as usize];
+        self.read_at(0, &mut bytes, block_device)?;
+        Ok(bytes)
     }
     /// Read data from current disk inode
     pub fn read_at(
@@ -349,11 +847,11 @@ impl DiskInode {
         offset: usize,
         buf: &mut [u8],
         block_device: &Arc<dyn BlockDevice>,
-    ) -> usize {
+    ) -> IoResult<usize> {
         let mut start = offset;
         let end = (offset + buf.len()).min(self.size as usize);
         if start >= end {
-            return 0;
+            return Ok(0);
         }
         let mut start_block = start / BLOCK_SZ;
         let mut read_size = 0usize;
@@ -364,34 +862,40 @@ impl DiskInode {
             // read and update read size
             let block_read_size = end_current_block - start;
             let dst = &mut buf[read_size..read_size + block_read_size];
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .read(0, |data_block: &DataBlock| {
-                let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
-                dst.copy_from_slice(src);
-            });
+            let block_id = self.get_block_id(start_block as u32, block_device)?;
+            if block_id == 0 {
+                // a hole
+                dst.iter_mut().for_each(|p| *p = 0);
+            } else {
+                get_block_cache(block_id as usize, Arc::clone(block_device))?
+                    .lock()
+                    .read(0, |data_block: &DataBlock| {
+                        let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
+                        dst.copy_from_slice(src);
+                    });
+            }
             read_size += block_read_size;
             // move to next block
             if end_current_block == end { break; }
             start_block += 1;
             start = end_current_block;
         }
-        read_size
+        Ok(read_size)
     }
     /// Write data into current disk inode
-    /// size must be adjusted properly beforehand
+    /// size must be adjusted and blocks must be mapped properly beforehand
     pub fn write_at(
         &mut self,
         offset: usize,
         buf: &[u8],
         block_device: &Arc<dyn BlockDevice>,
-    ) -> usize {
+    ) -> IoResult<usize> {
         let mut start = offset;
         let end = (offset + buf.len()).min(self.size as usize);
         assert!(start <= end);
+        if start == end {
+            return Ok(0);
+        }
         let mut start_block = start / BLOCK_SZ;
         let mut write_size = 0usize;
         loop {
@@ -400,10 +904,12 @@ impl DiskInode {
             end_current_block = end_current_block.min(end);
             // write and update write size
             let block_write_size = end_current_block - start;
+            let block_id = self.get_block_id(start_block as u32, block_device)?;
+            assert_ne!(block_id, 0, "writing to a hole");
             get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
+                block_id as usize,
                 Arc::clone(block_device)
-            )
+            )?
             .lock()
             .modify(0, |data_block: &mut DataBlock| {
                 let src = &buf[write_size..write_size + block_write_size];
@@ -416,59 +922,267 @@ impl DiskInode {
             start_block += 1;
             start = end_current_block;
         }
-        write_size
+        Ok(write_size)
     }
 }
 
-/// A directory entry
-#[repr(C)]
+/// Read an entry of an indirect block, which is 0 if the indirect block is a hole
+fn read_indirect(block_id: u32, index: usize, block_device: &Arc<dyn BlockDevice>) -> IoResult<u32> {
+    if block_id == 0 {
+        return Ok(0);
+    }
+    Ok(get_block_cache(block_id as usize, Arc::clone(block_device))?
+        .lock()
+        .read(0, |indirect_block: &IndirectBlock| indirect_block[index]))
+}
+
+/// Get an entry of an indirect block, mapping it to the next new block if it is a hole
+fn map_indirect(
+    block_id: u32,
+    index: usize,
+    new_blocks: &mut impl Iterator<Item = u32>,
+    block_device: &Arc<dyn BlockDevice>,
+) -> IoResult<u32> {
+    Ok(get_block_cache(block_id as usize, Arc::clone(block_device))?
+        .lock()
+        .modify(0, |indirect_block: &mut IndirectBlock| {
+            if indirect_block[index] == 0 {
+                indirect_block[index] = new_blocks.next().unwrap();
+            }
+            indirect_block[index]
+        }))
+}
+
+/// Get the entries in [from, to) of an indirect block which are not holes
+fn indirect_entries(
+    block_id: u32,
+    from: usize,
+    to: usize,
+    block_device: &Arc<dyn BlockDevice>,
+) -> IoResult<Vec<u32>> {
+    if from >= to {
+        return Ok(Vec::new());
+    }
+    Ok(get_block_cache(block_id as usize, Arc::clone(block_device))?
+        .lock()
+        .read(0, |indirect_block: &IndirectBlock| {
+            indirect_block[from..to]
+                .iter()
+                .copied()
+                .filter(|entry| *entry != 0)
+                .collect()
+        }))
+}
+
+/// Collect the entries in [from, to) of an indirect block into `v`,
+/// turning them into holes unless the whole indirect block is released
+fn release_indirect(
+    block_id: u32,
+    from: usize,
+    to: usize,
+    v: &mut Vec<u32>,
+    block_device: &Arc<dyn BlockDevice>,
+) -> IoResult<()> {
+    let entries = indirect_entries(block_id, from, to, block_device)?;
+    if from > 0 && !entries.is_empty() {
+        get_block_cache(block_id as usize, Arc::clone(block_device))?
+            .lock()
+            .modify(0, |indirect_block: &mut IndirectBlock| {
+                indirect_block[from..to].iter_mut().for_each(|entry| *entry = 0);
+            });
+    }
+    v.extend(entries);
+    Ok(())
+}
+
+/// Get the number of extent blocks holding `count` extents
+fn extent_blocks(count: usize) -> usize {
+    if count <= INODE_EXTENT_COUNT {
+        0
+    } else if count <= EXTENT1_BOUND {
+        1
+    } else {
+        // the index block and the extent blocks under it
+        2 + ceil_div(count - EXTENT1_BOUND, EXTENT_BLOCK_COUNT)
+    }
+}
+
+/// Read the extents of an extent block up to the end of the list
+fn read_extents(block_id: u32, block_device: &Arc<dyn BlockDevice>) -> IoResult<Vec<Extent>> {
+    Ok(get_block_cache(block_id as usize, Arc::clone(block_device))?
+        .lock()
+        .read(0, |extent_block: &ExtentBlock| {
+            extent_block
+                .iter()
+                .copied()
+                .take_while(|extent| extent.len > 0)
+                .collect()
+        }))
+}
+
+/// Get the extents mapping the block of the given inner id to `block_id` besides `extents`,
+/// in which the block is a hole. Consecutive blocks are merged into one extent, as are holes.
+fn map_extent(extents: &[Extent], inner_id: u32, block_id: u32) -> Vec<Extent> {
+    let mut v: Vec<Extent> = Vec::with_capacity(extents.len() + 2);
+    let block = Extent { start: block_id, len: 1 };
+    let mut first = 0;
+    for extent in extents.iter() {
+        if first <= inner_id && inner_id < first + extent.len {
+            // split the hole around the block
+            push_extent(&mut v, Extent { start: 0, len: inner_id - first });
+            push_extent(&mut v, block);
+            push_extent(&mut v, Extent { start: 0, len: first + extent.len - inner_id - 1 });
+        } else {
+            push_extent(&mut v, *extent);
+        }
+        first += extent.len;
+    }
+    if inner_id >= first {
+        push_extent(&mut v, Extent { start: 0, len: inner_id - first });
+        push_extent(&mut v, block);
+    }
+    v
+}
+
+/// Append an extent to a list, merging it into the last one if it follows it
+fn push_extent(v: &mut Vec<Extent>, extent: Extent) {
+    if extent.len == 0 {
+        return;
+    }
+    if let Some(last) = v.last_mut() {
+        let follows = match last.start {
+            0 => extent.start == 0,
+            start => start.checked_add(last.len) == Some(extent.start),
+        };
+        if follows {
+            last.len += extent.len;
+            return;
+        }
+    }
+    v.push(extent);
+}
+
+/// Get the number of bytes taken by extended attributes in an attribute block
+pub fn xattrs_size(xattrs: &[(String, Vec<u8>)]) -> usize {
+    xattrs.iter().map(|(name, value)| XATTR_HEADER_SZ + name.len() + value.len()).sum()
+}
+
+/// Divide rounding up, `div_ceil` being unstable on the toolchain of the kernels
+pub fn ceil_div(n: usize, d: usize) -> usize {
+    if n == 0 {
+        0
+    } else {
+        (n - 1) / d + 1
+    }
+}
+
+/// A directory entry.
+///
+/// An entry is stored in a slot of `DIRENT_SZ` bytes: the name padded with 0 up to
+/// `SHORT_NAME_LENGTH_LIMIT` bytes, the number of extension slots and the inode number.
+/// The rest of a longer name is held by the extension slots following it, each of which
+/// starts with `EXT_SLOT_MARK`. Slots starting with 0 are free.
 pub struct DirEntry {
-    name: [u8; NAME_LENGTH_LIMIT + 1],
+    name: [u8; NAME_LENGTH_LIMIT],
+    name_len: usize,
     inode_number: u32,
 }
 
-/// Size of a directory entry
+/// Size of a directory entry slot
 pub const DIRENT_SZ: usize = 32;
 
+/// Error of creating a directory entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirEntryError {
+    /// The name is empty
+    EmptyName,
+    /// The name contains '/' or '\0'
+    InvalidName,
+    /// The name is longer than `NAME_LENGTH_LIMIT`
+    NameTooLong,
+}
+
 impl DirEntry {
-    /// Create an empty directory entry
-    pub fn empty() -> Self {
-        Self {
-            name: [0u8; NAME_LENGTH_LIMIT + 1],
-            inode_number: 0,
-        }
-    }
     /// Crate a directory entry from name and inode number
-    pub fn new(name: &str, inode_number: u32) -> Self {
-        let mut bytes = [0u8; NAME_LENGTH_LIMIT + 1];
+    pub fn new(name: &str, inode_number: u32) -> core::result::Result<Self, DirEntryError> {
+        if name.is_empty() {
+            return Err(DirEntryError::EmptyName);
+        }
+        if name.contains(&['/', '\0'][..]) {
+            return Err(DirEntryError::InvalidName);
+        }
+        if name.len() > NAME_LENGTH_LIMIT {
+            return Err(DirEntryError::NameTooLong);
+        }
+        let mut bytes = [0u8; NAME_LENGTH_LIMIT];
         bytes[..name.len()].copy_from_slice(name.as_bytes());
-        Self {
+        Ok(Self {
             name: bytes,
+            name_len: name.len(),
             inode_number,
-        }
+        })
     }
-    /// Serialize into bytes
-    pub fn as_bytes(&self) -> &[u8] {
-        unsafe {
-            core::slice::from_raw_parts(
-                self as *const _ as usize as *const u8,
-                DIRENT_SZ,
-            )
+    /// Parse the entry whose first slot starts `bytes`,
+    /// return None if the slot is free or an extension slot, or if the entry is corrupt
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes[0] == 0 || bytes[0] == EXT_SLOT_MARK {
+            return None;
+        }
+        let head = &bytes[..DIRENT_SZ];
+        let mut name = [0u8; NAME_LENGTH_LIMIT];
+        let mut name_len = 0;
+        let ext_slots = head[SHORT_NAME_LENGTH_LIMIT] as usize;
+        // the last extension slot has to start within the name length limit
+        if SHORT_NAME_LENGTH_LIMIT + ext_slots * EXT_NAME_LENGTH >= NAME_LENGTH_LIMIT + EXT_NAME_LENGTH
+            || bytes.len() < (1 + ext_slots) * DIRENT_SZ {
+            return None;
+        }
+        let exts = &bytes[DIRENT_SZ..(1 + ext_slots) * DIRENT_SZ];
+        if exts.chunks(DIRENT_SZ).any(|ext| ext[0] != EXT_SLOT_MARK) {
+            return None;
         }
+        let parts = core::iter::once(&head[..SHORT_NAME_LENGTH_LIMIT])
+            .chain(exts.chunks(DIRENT_SZ).map(|ext| &ext[1..]));
+        for part in parts {
+            let len = part.iter().position(|b| *b == 0).unwrap_or(part.len());
+            if name_len + len > NAME_LENGTH_LIMIT {
+                return None;
+            }
+            name[name_len..name_len + len].copy_from_slice(&part[..len]);
+            name_len += len;
+        }
+        core::str::from_utf8(&name[..name_len]).ok()?;
+        let mut inode_number = [0u8; 4];
+        inode_number.copy_from_slice(&head[DIRENT_SZ - 4..]);
+        Some(Self {
+            name,
+            name_len,
+            inode_number: u32::from_ne_bytes(inode_number),
+        })
     }
-    /// Serialize into mutable bytes
-    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
-        unsafe {
-            core::slice::from_raw_parts_mut(
-                self as *mut _ as usize as *mut u8,
-                DIRENT_SZ,
-            )
+    /// Serialize into the bytes of all its slots
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; self.slots() * DIRENT_SZ];
+        let name = &self.name[..self.name_len];
+        let head_len = name.len().min(SHORT_NAME_LENGTH_LIMIT);
+        bytes[..head_len].copy_from_slice(&name[..head_len]);
+        bytes[SHORT_NAME_LENGTH_LIMIT] = (self.slots() - 1) as u8;
+        bytes[DIRENT_SZ - 4..DIRENT_SZ].copy_from_slice(&self.inode_number.to_ne_bytes());
+        for (ext, part) in bytes[DIRENT_SZ..]
+            .chunks_mut(DIRENT_SZ)
+            .zip(name[head_len..].chunks(EXT_NAME_LENGTH)) {
+            ext[0] = EXT_SLOT_MARK;
+            ext[1..1 + part.len()].copy_from_slice(part);
         }
+        bytes
+    }
+    /// Get the number of slots taken by the entry
+    pub fn slots(&self) -> usize {
+        1 + ceil_div(self.name_len.max(SHORT_NAME_LENGTH_LIMIT) - SHORT_NAME_LENGTH_LIMIT, EXT_NAME_LENGTH)
     }
     /// Get name of the entry
     pub fn name(&self) -> &str {
-        let len = (0usize..).find(|i| self.name[*i] == 0).unwrap();
-        core::str::from_utf8(&self.name[..len]).unwrap()
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap()
     }
     /// Get inode number of the entry
     pub fn inode_number(&self) -> u32 {
@@ -8,12 +8,40 @@ mod efs;
 mod bitmap;
 mod vfs;
 mod block_cache;
+mod cache_policy;
+mod journal;
+mod fsck;
 
 /// Use a block size of 512 bytes
 pub const BLOCK_SZ: usize = 512;
-pub use block_dev::BlockDevice;
-pub use efs::EasyFileSystem;
-pub use vfs::Inode;
+pub use block_dev::{BlockDevice, IoError, IoResult};
+pub use efs::{EasyFileSystem, FsGeometry, FsStat};
+pub use vfs::{Inode, Metadata};
+pub use layout::{
+    BlockMapping,
+    DirEntry,
+    DirEntryError,
+    DIRENT_SZ,
+    MAX_EXTENT_FILE_SIZE,
+    MAX_FILE_SIZE,
+    NAME_LENGTH_LIMIT,
+    SYMLINK_MAX_LEN,
+    XATTR_NAME_MAX,
+};
+pub use fsck::{FsckProblem, FsckReport};
+pub use block_cache::{BlockCacheStats, BLOCK_CACHE_SIZE};
+pub use cache_policy::{CachePolicy, LruPolicy, ClockPolicy};
 use layout::*;
 use bitmap::Bitmap;
-use block_cache::{get_block_cache, block_cache_sync_all};
+use block_cache::{
+    get_block_cache,
+    block_cache_sync_all,
+    block_cache_dirty,
+    block_cache_discard,
+    block_cache_init,
+    block_cache_release,
+    block_cache_stats,
+    block_cache_prefetch,
+    block_cache_sync_batch,
+};
+use journal::Journal;
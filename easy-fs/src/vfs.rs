@@ -4,208 +4,921 @@ use super::{
     DiskInodeType,
     DirEntry,
     EasyFileSystem,
+    FsStat,
+    IoResult,
+    BLOCK_SZ,
     DIRENT_SZ,
+    DEFAULT_FILE_MODE,
+    DEFAULT_DIR_MODE,
+    DEFAULT_SYMLINK_MODE,
+    SYMLINK_MAX_LEN,
+    XATTR_NAME_MAX,
+    xattrs_size,
+    ceil_div,
     get_block_cache,
-    block_cache_sync_all,
 };
 use alloc::sync::Arc;
 use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
-use spin::{Mutex, MutexGuard};
+use spin::{Mutex, RwLock};
 
-/// Virtual filesystem layer over easy-fs
+/// The max number of bytes written by a transaction
+const TRANSACTION_WRITE_SIZE: usize = 4 * BLOCK_SZ;
+
+/// The max number of data bitmap blocks modified by a transaction shrinking a file,
+/// which leaves room among the blocks reserved for it in the log for the others it modifies
+const TRANSACTION_BITMAP_BLOCKS: usize = 8;
+
+/// Seconds after which the access time is updated again even if the inode is not modified
+const ATIME_UPDATE_INTERVAL: u64 = 24 * 60 * 60;
+
+/// The number of blocks read ahead once reads turn out to be sequential
+const READ_AHEAD_MIN: usize = 4;
+/// The max number of blocks read ahead
+const READ_AHEAD_MAX: usize = 32;
+
+/// Virtual filesystem layer over easy-fs.
+///
+/// Reading an inode holds its lock shared, and modifying it holds its lock exclusively
+/// within a transaction. Reads only lock a single inode at a time. The locks are released
+/// before the transaction ends, since it waits for the other transactions of its group.
 pub struct Inode {
+    inode_id: u32,
     block_id: usize,
     block_offset: usize,
-    fs: Arc<Mutex<EasyFileSystem>>,
+    fs: Arc<EasyFileSystem>,
     block_device: Arc<dyn BlockDevice>,
+    /// lock of the inode, shared by all vfs inodes of it
+    lock: Arc<RwLock<()>>,
+    read_ahead: Mutex<ReadAhead>,
+}
+
+/// Metadata of an inode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub inode_id: u32,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    pub size: u32,
+    /// number of hard links
+    pub nlink: u32,
+    /// permission bits
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// time of the last access, in seconds
+    pub atime: u64,
+    /// time of the last modification of the data, in seconds
+    pub mtime: u64,
+    /// time of the last change of the data or metadata, in seconds
+    pub ctime: u64,
+}
+
+/// Read-ahead state of the reads through an inode
+struct ReadAhead {
+    /// offset right after the last read
+    next_offset: usize,
+    /// blocks to read beyond the current read
+    window: usize,
+    /// end of the data read ahead so far
+    cached_end: usize,
 }
 
 impl Inode {
     /// Create a vfs inode
     pub fn new(
+        inode_id: u32,
         block_id: u32,
         block_offset: usize,
-        fs: Arc<Mutex<EasyFileSystem>>,
+        fs: Arc<EasyFileSystem>,
         block_device: Arc<dyn BlockDevice>,
     ) -> Self {
         Self {
+            inode_id,
             block_id: block_id as usize,
             block_offset,
+            lock: fs.inode_lock(inode_id),
             fs,
             block_device,
+            read_ahead: Mutex::new(ReadAhead {
+                next_offset: 0,
+                window: 0,
+                cached_end: 0,
+            }),
         }
     }
+    /// Get a vfs inode of the given inode on the same filesystem
+    fn inode(&self, inode_id: u32) -> Arc<Inode> {
+        let (block_id, block_offset) = self.fs.get_disk_inode_pos(inode_id);
+        Arc::new(Self::new(
+            inode_id,
+            block_id,
+            block_offset,
+            Arc::clone(&self.fs),
+            Arc::clone(&self.block_device),
+        ))
+    }
+    /// Get a copy of the disk inode, so that reading the data it refers to
+    /// does not keep the block holding it locked
+    fn disk_inode(&self) -> IoResult<DiskInode> {
+        self.read_disk_inode(|disk_inode| disk_inode.clone())
+    }
     /// Call a function over a disk inode to read it
-    fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
-        get_block_cache(
+    fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> IoResult<V> {
+        Ok(get_block_cache(
             self.block_id,
             Arc::clone(&self.block_device)
-        ).lock().read(self.block_offset, f)
+        )?.lock().read(self.block_offset, f))
     }
     /// Call a function over a disk inode to modify it
-    fn modify_disk_inode<V>(&self, f: impl FnOnce(&mut DiskInode) -> V) -> V {
-        get_block_cache(
+    fn modify_disk_inode<V>(&self, f: impl FnOnce(&mut DiskInode) -> V) -> IoResult<V> {
+        Ok(get_block_cache(
             self.block_id,
             Arc::clone(&self.block_device)
-        ).lock().modify(self.block_offset, f)
+        )?.lock().modify(self.block_offset, f))
     }
-    /// Find inode under a disk inode by name
-    fn find_inode_id(
+    /// Store a copy of the disk inode modified with the lock of current inode held
+    /// exclusively since it was got, so that modifying the data it refers to
+    /// does not keep the block holding it locked
+    fn store_disk_inode(&self, disk_inode: DiskInode) -> IoResult<()> {
+        self.modify_disk_inode(|stored| *stored = disk_inode)
+    }
+    /// Get the inode number of current inode
+    pub fn inode_id(&self) -> u32 {
+        self.inode_id
+    }
+    /// Whether current inode is a directory
+    pub fn is_dir(&self) -> IoResult<bool> {
+        let _inode = self.lock.read();
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+    /// Get the number of hard links to current inode
+    pub fn nlink(&self) -> IoResult<u32> {
+        let _inode = self.lock.read();
+        self.read_disk_inode(|disk_inode| disk_inode.nlink)
+    }
+    /// Get the metadata of current inode
+    pub fn metadata(&self) -> IoResult<Metadata> {
+        let _inode = self.lock.read();
+        self.read_disk_inode(|disk_inode| Metadata {
+            inode_id: self.inode_id,
+            is_dir: disk_inode.is_dir(),
+            is_symlink: disk_inode.is_symlink(),
+            size: disk_inode.size,
+            nlink: disk_inode.nlink,
+            mode: disk_inode.mode,
+            uid: disk_inode.uid,
+            gid: disk_inode.gid,
+            atime: disk_inode.atime,
+            mtime: disk_inode.mtime,
+            ctime: disk_inode.ctime,
+        })
+    }
+    /// Set the permission bits of current inode
+    pub fn set_mode(&self, mode: u32) -> IoResult<()> {
+        let transaction = self.fs.begin_transaction();
+        let inode = self.lock.write();
+        let now = self.fs.now();
+        let result = self.modify_disk_inode(|disk_inode| {
+            disk_inode.mode = mode & 0o7777;
+            disk_inode.ctime = now;
+        });
+        drop(inode);
+        self.fs.end_transaction(transaction, result)
+    }
+    /// Set the owner and the owner group of current inode
+    pub fn set_owner(&self, uid: u32, gid: u32) -> IoResult<()> {
+        let transaction = self.fs.begin_transaction();
+        let inode = self.lock.write();
+        let now = self.fs.now();
+        let result = self.modify_disk_inode(|disk_inode| {
+            disk_inode.uid = uid;
+            disk_inode.gid = gid;
+            disk_inode.ctime = now;
+        });
+        drop(inode);
+        self.fs.end_transaction(transaction, result)
+    }
+    /// Set the access and modification time of current inode
+    pub fn set_times(&self, atime: u64, mtime: u64) -> IoResult<()> {
+        let transaction = self.fs.begin_transaction();
+        let inode = self.lock.write();
+        let now = self.fs.now();
+        let result = self.modify_disk_inode(|disk_inode| {
+            disk_inode.atime = atime;
+            disk_inode.mtime = mtime;
+            disk_inode.ctime = now;
+        });
+        drop(inode);
+        self.fs.end_transaction(transaction, result)
+    }
+    /// Find the first slot and the directory entry under a disk inode by name
+    fn find_dirent(
         &self,
         name: &str,
         disk_inode: &DiskInode,
-    ) -> Option<u32> {
+    ) -> IoResult<Option<(usize, DirEntry)>> {
         // assert it is a directory
         assert!(disk_inode.is_dir());
-        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-        let mut dirent = DirEntry::empty();
-        for i in 0..file_count {
-            assert_eq!(
-                disk_inode.read_at(
-                    DIRENT_SZ * i,
-                    dirent.as_bytes_mut(),
-                    &self.block_device,
-                ),
-                DIRENT_SZ,
-            );
-            if dirent.name() == name {
-                return Some(dirent.inode_number() as u32);
-            }
-        }
-        None
-    }
-    /// Find inode under current inode by name
-    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
-        let fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            self.find_inode_id(name, disk_inode)
-            .map(|inode_id| {
-                let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
-                Arc::new(Self::new(
-                    block_id,
-                    block_offset,
-                    self.fs.clone(),
-                    self.block_device.clone(),
-                ))
-            })
-        })
+        Ok(disk_inode
+            .dirents(&self.block_device)?
+            .into_iter()
+            .find(|(_, dirent)| dirent.name() == name))
     }
-    /// Increase the size of a disk inode
-    fn increase_size(
+    /// Find inode under a disk inode by name
+    fn find_inode_id(
         &self,
-        new_size: u32,
+        name: &str,
+        disk_inode: &DiskInode,
+    ) -> IoResult<Option<u32>> {
+        Ok(self.find_dirent(name, disk_inode)?
+            .map(|(_, dirent)| dirent.inode_number()))
+    }
+    /// Find inode under current inode by path,
+    /// components are separated by '/' and may be '.' or '..'
+    pub fn find(&self, path: &str) -> IoResult<Option<Arc<Inode>>> {
+        let mut inode_id = self.inode_id;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let child = self.inode(inode_id).find_child(name)?;
+            inode_id = match child {
+                Some(inode_id) => inode_id,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(self.inode(inode_id)))
+    }
+    /// Find the inode directly under current inode by name
+    fn find_child(&self, name: &str) -> IoResult<Option<u32>> {
+        let _inode = self.lock.read();
+        let disk_inode = self.disk_inode()?;
+        if disk_inode.is_dir() {
+            self.find_inode_id(name, &disk_inode)
+        } else {
+            Ok(None)
+        }
+    }
+    /// Map the blocks holding data in [offset, offset + len) of a disk inode, allocating
+    /// those which are holes, and increase the size to cover them.
+    /// Return the number of bytes from offset that are mapped,
+    /// which falls short of len once the disk is full or the file reaches its max size.
+    fn map_range(
+        &self,
+        offset: usize,
+        len: usize,
         disk_inode: &mut DiskInode,
-        fs: &mut MutexGuard<EasyFileSystem>,
-    ) {
-        if new_size < disk_inode.size {
-            return;
-        }
-        let blocks_needed = disk_inode.blocks_num_needed(new_size);
-        let mut v: Vec<u32> = Vec::new();
-        for _ in 0..blocks_needed {
-            v.push(fs.alloc_data());
-        }
-        disk_inode.increase_size(new_size, v, &self.block_device);
-    }
-    /// Create inode under current inode by name
-    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
-        let mut fs = self.fs.lock();
-        if self.modify_disk_inode(|root_inode| {
-            // assert it is a directory
-            assert!(root_inode.is_dir());
-            // has the file been created?
-            self.find_inode_id(name, root_inode)
-        }).is_some() {
-            return None;
-        }
-        // create a new file
-        // alloc a inode with an indirect block
-        let new_inode_id = fs.alloc_inode();
-        // initialize inode
-        let (new_inode_block_id, new_inode_block_offset) 
-            = fs.get_disk_inode_pos(new_inode_id);
-        get_block_cache(
-            new_inode_block_id as usize,
-            Arc::clone(&self.block_device)
-        ).lock().modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-            new_inode.initialize(DiskInodeType::File);
-        });
-        self.modify_disk_inode(|root_inode| {
-            // append file in the dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            // write dirent
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
-        });
-
-        let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        block_cache_sync_all();
-        // return inode
-        Some(Arc::new(Self::new(
-            block_id,
-            block_offset,
-            self.fs.clone(),
-            self.block_device.clone(),
-        )))
-        // release efs lock automatically by compiler
+    ) -> IoResult<usize> {
+        let end = (offset + len).min(disk_inode.mapping().max_file_size());
+        if offset >= end {
+            return Ok(0);
+        }
+        let mut mapped_end = end;
+        for inner_id in offset / BLOCK_SZ..ceil_div(end, BLOCK_SZ) {
+            let blocks_needed = match disk_inode.blocks_num_needed(inner_id as u32, &self.block_device)? {
+                Some(blocks_needed) => blocks_needed,
+                None => {
+                    mapped_end = (inner_id * BLOCK_SZ).max(offset);
+                    break;
+                }
+            };
+            // the data block is preferably the one following the block before it,
+            // so that the data is stored consecutively
+            let hint = match inner_id {
+                0 => 0,
+                _ => match disk_inode.get_block_id(inner_id as u32 - 1, &self.block_device)? {
+                    0 => 0,
+                    block_id => block_id + 1,
+                },
+            };
+            let mut v: Vec<u32> = Vec::new();
+            while v.len() < blocks_needed as usize {
+                match self.fs.alloc_data(if v.is_empty() { hint } else { 0 })? {
+                    Some(block_id) => v.push(block_id),
+                    None => break,
+                }
+            }
+            if v.len() < blocks_needed as usize {
+                for block_id in v {
+                    self.fs.dealloc_data(block_id)?;
+                }
+                mapped_end = (inner_id * BLOCK_SZ).max(offset);
+                break;
+            }
+            for block_id in disk_inode.map_block(inner_id as u32, v, &self.block_device)? {
+                self.fs.dealloc_data(block_id)?;
+            }
+        }
+        disk_inode.size = disk_inode.size.max(mapped_end as u32);
+        Ok(mapped_end - offset)
     }
-    /// List inodes under current inode
-    pub fn ls(&self) -> Vec<String> {
-        let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let mut v: Vec<String> = Vec::new();
-            for i in 0..file_count {
-                let mut dirent = DirEntry::empty();
-                assert_eq!(
-                    disk_inode.read_at(
-                        i * DIRENT_SZ,
-                        dirent.as_bytes_mut(),
-                        &self.block_device,
-                    ),
-                    DIRENT_SZ,
-                );
-                v.push(String::from(dirent.name()));
+    /// Append a directory entry to a directory disk inode,
+    /// reusing the slots of removed entries if there are enough consecutive ones.
+    /// The name must have been checked by `valid_name`.
+    /// Return false if the directory cannot grow to hold the entry.
+    fn add_dirent(
+        &self,
+        name: &str,
+        inode_id: u32,
+        disk_inode: &mut DiskInode,
+    ) -> IoResult<bool> {
+        let dirent = DirEntry::new(name, inode_id).unwrap();
+        let slots = dirent.slots();
+        let bytes = disk_inode.read_all(&self.block_device)?;
+        let file_count = bytes.len() / DIRENT_SZ;
+        let is_free = |i: usize| bytes[i * DIRENT_SZ] == 0;
+        let slot = match (0..file_count)
+            .find(|&i| i + slots <= file_count && (i..i + slots).all(is_free)) {
+            Some(slot) => slot,
+            None => {
+                // increase size, reusing the free slots at the end
+                let mut slot = file_count;
+                while slot > 0 && is_free(slot - 1) {
+                    slot -= 1;
+                }
+                let len = slots * DIRENT_SZ;
+                if self.map_range(slot * DIRENT_SZ, len, disk_inode)? < len {
+                    return Ok(false);
+                }
+                slot
             }
-            v
-        })
+        };
+        // write dirent
+        disk_inode.write_at(
+            slot * DIRENT_SZ,
+            &dirent.to_bytes(),
+            &self.block_device,
+        )?;
+        Ok(true)
+    }
+    /// Create a file under current inode by path
+    pub fn create(&self, path: &str) -> IoResult<Option<Arc<Inode>>> {
+        self.create_inode(path, DiskInodeType::File, &[])
+    }
+    /// Create a directory under current inode by path
+    pub fn create_dir(&self, path: &str) -> IoResult<Option<Arc<Inode>>> {
+        self.create_inode(path, DiskInodeType::Directory, &[])
+    }
+    /// Create a symbolic link at `path` under current inode referring to `target`,
+    /// which is neither checked nor resolved
+    pub fn symlink(&self, target: &str, path: &str) -> IoResult<Option<Arc<Inode>>> {
+        if target.is_empty() || target.len() > SYMLINK_MAX_LEN {
+            return Ok(None);
+        }
+        self.create_inode(path, DiskInodeType::SymLink, target.as_bytes())
+    }
+    /// Get the target path of current inode, or None if it is not a symbolic link
+    pub fn readlink(&self) -> IoResult<Option<String>> {
+        let _inode = self.lock.read();
+        let disk_inode = self.disk_inode()?;
+        if !disk_inode.is_symlink() {
+            return Ok(None);
+        }
+        let mut target = vec![0u8; disk_inode.size as usize];
+        disk_inode.read_at(0, &mut target, &self.block_device)?;
+        Ok(Some(String::from_utf8_lossy(&target).into_owned()))
+    }
+    /// Create an inode of the given type holding `contents` under current inode by path
+    fn create_inode(&self, path: &str, type_: DiskInodeType, contents: &[u8]) -> IoResult<Option<Arc<Inode>>> {
+        let (parent_path, name) = split_path(path);
+        match self.find(parent_path)? {
+            Some(parent) => parent.create_child(name, type_, contents),
+            None => Ok(None),
+        }
+    }
+    /// Create an inode of the given type holding `contents` directly under current inode by name
+    fn create_child(&self, name: &str, type_: DiskInodeType, contents: &[u8]) -> IoResult<Option<Arc<Inode>>> {
+        let fs = &self.fs;
+        // dropping the new inode if it is not added may release it, which takes a transaction
+        // of its own, so it is dropped after this one
+        let mut created = None;
+        let transaction = fs.begin_transaction();
+        let inode = self.lock.write();
+        let result = (|| -> IoResult<Option<Arc<Inode>>> {
+            if !valid_name(name, fs) || !self.read_disk_inode(|dir_inode| -> IoResult<bool> {
+                // only a directory not removed yet can hold children, and names are unique in it
+                Ok(dir_inode.is_dir()
+                    && dir_inode.nlink > 0
+                    && self.find_inode_id(name, dir_inode)?.is_none())
+            })?? {
+                return Ok(None);
+            }
+            let now = fs.now();
+            let mapping = fs.block_mapping();
+            // create a new inode
+            let new_inode_id = match fs.alloc_inode()? {
+                Some(inode_id) => inode_id,
+                None => return Ok(None),
+            };
+            // initialize inode, which cannot be found by others before it is added
+            let new_inode = created.insert(self.inode(new_inode_id));
+            let _new_inode = new_inode.lock.write();
+            let initialized = new_inode.modify_disk_inode(|disk_inode| -> IoResult<bool> {
+                let mode = match type_ {
+                    DiskInodeType::File => DEFAULT_FILE_MODE,
+                    DiskInodeType::Directory => DEFAULT_DIR_MODE,
+                    DiskInodeType::SymLink => DEFAULT_SYMLINK_MODE,
+                };
+                disk_inode.initialize(type_, mode, now, mapping);
+                // a new directory starts with '.' and '..'
+                if type_ == DiskInodeType::Directory {
+                    return Ok(self.add_dirent(".", new_inode_id, disk_inode)?
+                        && self.add_dirent("..", self.inode_id, disk_inode)?);
+                }
+                if self.map_range(0, contents.len(), disk_inode)? < contents.len() {
+                    return Ok(false);
+                }
+                disk_inode.write_at(0, contents, &self.block_device)?;
+                Ok(true)
+            })??;
+            let added = initialized && self.modify_disk_inode(|dir_inode| -> IoResult<bool> {
+                let added = self.add_dirent(name, new_inode_id, dir_inode)?;
+                if added {
+                    dir_inode.mtime = now;
+                    dir_inode.ctime = now;
+                }
+                Ok(added)
+            })??;
+            if !added {
+                // the disk is full, release the new inode,
+                // which holds a single block at most so it is released in a single step
+                new_inode.release_step()?;
+                return Ok(None);
+            }
+            // return inode
+            Ok(Some(Arc::clone(new_inode)))
+        })();
+        drop(inode);
+        fs.end_transaction(transaction, result)
+    }
+    /// Create a hard link `new_path` under current inode to the file at `old_path`
+    pub fn link(&self, old_path: &str, new_path: &str) -> IoResult<bool> {
+        let (parent_path, name) = split_path(new_path);
+        let (target, parent) = match (self.find(old_path)?, self.find(parent_path)?) {
+            (Some(target), Some(parent)) => (target, parent),
+            _ => return Ok(false),
+        };
+        let fs = &self.fs;
+        if !valid_name(name, fs) {
+            return Ok(false);
+        }
+        let transaction = fs.begin_transaction();
+        let parent_inode = parent.lock.write();
+        // the target may be the parent itself once it is removed and its inode reused
+        let target_inode = if Arc::ptr_eq(&parent.lock, &target.lock) {
+            None
+        } else {
+            Some(target.lock.write())
+        };
+        let now = fs.now();
+        let result = (|| -> IoResult<bool> {
+            // linking directories could make the tree cyclic,
+            // and the target may have been removed since it was found
+            if target.read_disk_inode(|disk_inode| disk_inode.is_dir() || disk_inode.nlink == 0)? {
+                return Ok(false);
+            }
+            let linked = parent.modify_disk_inode(|dir_inode| -> IoResult<bool> {
+                if !dir_inode.is_dir()
+                    || dir_inode.nlink == 0
+                    || parent.find_inode_id(name, dir_inode)?.is_some() {
+                    return Ok(false);
+                }
+                let added = parent.add_dirent(name, target.inode_id, dir_inode)?;
+                if added {
+                    dir_inode.mtime = now;
+                    dir_inode.ctime = now;
+                }
+                Ok(added)
+            })??;
+            if linked {
+                target.modify_disk_inode(|disk_inode| {
+                    disk_inode.nlink += 1;
+                    disk_inode.ctime = now;
+                })?;
+            }
+            Ok(linked)
+        })();
+        drop((target_inode, parent_inode));
+        fs.end_transaction(transaction, result)
+    }
+    /// Remove the file at `path` under current inode,
+    /// its data and inode are released once no link nor vfs inode refers to it
+    pub fn unlink(&self, path: &str) -> IoResult<bool> {
+        let (parent_path, name) = split_path(path);
+        if name.is_empty() || name == "." || name == ".." {
+            return Ok(false);
+        }
+        match self.find(parent_path)? {
+            Some(parent) => parent.unlink_child(name),
+            None => Ok(false),
+        }
+    }
+    /// Remove the file directly under current inode by name
+    fn unlink_child(&self, name: &str) -> IoResult<bool> {
+        let fs = &self.fs;
+        // dropping the removed inode may release it, which takes a transaction of its own,
+        // so it is dropped after this one
+        let mut removed = None;
+        let transaction = fs.begin_transaction();
+        let inode = self.lock.write();
+        let result = (|| -> IoResult<bool> {
+            let (slot, dirent) = match self.read_disk_inode(|dir_inode| {
+                if dir_inode.is_dir() {
+                    self.find_dirent(name, dir_inode)
+                } else {
+                    Ok(None)
+                }
+            })?? {
+                Some(pair) => pair,
+                None => return Ok(false),
+            };
+            let target = removed.insert(self.inode(dirent.inode_number()));
+            let _target = target.lock.write();
+            // directories are removed by rmdir
+            if target.read_disk_inode(|disk_inode| disk_inode.is_dir())? {
+                return Ok(false);
+            }
+            let now = fs.now();
+            self.modify_disk_inode(|dir_inode| -> IoResult<()> {
+                dir_inode.write_at(slot * DIRENT_SZ, &vec![0u8; dirent.slots() * DIRENT_SZ], &self.block_device)?;
+                dir_inode.mtime = now;
+                dir_inode.ctime = now;
+                Ok(())
+            })??;
+            let nlink = target.modify_disk_inode(|disk_inode| {
+                disk_inode.nlink -= 1;
+                disk_inode.ctime = now;
+                disk_inode.nlink
+            })?;
+            // an inode still in use is released by the last vfs inode of it,
+            // as is the rest of a large file once the removed inode is dropped
+            if nlink == 0 && !fs.inode_shared(&target.lock) {
+                target.release_step()?;
+            }
+            Ok(true)
+        })();
+        drop(inode);
+        fs.end_transaction(transaction, result)
+    }
+    /// Remove an empty directory under current inode by path,
+    /// it is released once no vfs inode refers to it
+    pub fn rmdir(&self, path: &str) -> IoResult<bool> {
+        let (parent_path, name) = split_path(path);
+        if name.is_empty() || name == "." || name == ".." {
+            return Ok(false);
+        }
+        match self.find(parent_path)? {
+            Some(parent) => parent.remove_child_dir(name),
+            None => Ok(false),
+        }
+    }
+    /// Remove an empty directory directly under current inode by name
+    fn remove_child_dir(&self, name: &str) -> IoResult<bool> {
+        let fs = &self.fs;
+        // dropped after the transaction as in `unlink_child`
+        let mut removed = None;
+        let transaction = fs.begin_transaction();
+        let inode = self.lock.write();
+        let result = (|| -> IoResult<bool> {
+            let (slot, dirent) = match self.read_disk_inode(|dir_inode| {
+                if dir_inode.is_dir() {
+                    self.find_dirent(name, dir_inode)
+                } else {
+                    Ok(None)
+                }
+            })?? {
+                Some(pair) => pair,
+                None => return Ok(false),
+            };
+            let target = removed.insert(self.inode(dirent.inode_number()));
+            let _target = target.lock.write();
+            // refuse to remove a file or a non-empty directory
+            let disk_inode = target.disk_inode()?;
+            if !disk_inode.is_dir() || !self.is_empty_dir(&disk_inode)? {
+                return Ok(false);
+            }
+            let now = fs.now();
+            self.modify_disk_inode(|dir_inode| -> IoResult<()> {
+                dir_inode.write_at(slot * DIRENT_SZ, &vec![0u8; dirent.slots() * DIRENT_SZ], &self.block_device)?;
+                dir_inode.mtime = now;
+                dir_inode.ctime = now;
+                Ok(())
+            })??;
+            // a directory still in use stays empty, nothing can be added to it
+            target.modify_disk_inode(|disk_inode| {
+                disk_inode.nlink = 0;
+                disk_inode.ctime = now;
+            })?;
+            if !fs.inode_shared(&target.lock) {
+                target.release_step()?;
+            }
+            Ok(true)
+        })();
+        drop(inode);
+        fs.end_transaction(transaction, result)
+    }
+    /// Release a step of the data of current inode, which no directory entry refers to,
+    /// as part of the transaction in progress, then its inode once the data is released.
+    /// Return whether the inode is released.
+    fn release_step(&self) -> IoResult<bool> {
+        let released = self.modify_disk_inode(|disk_inode| -> IoResult<bool> {
+            let size = self.shrink_step(0, disk_inode)?;
+            for data_block in disk_inode.truncate(size, &self.block_device)? {
+                self.fs.dealloc_data(data_block)?;
+            }
+            Ok(size == 0)
+        })??;
+        if !released {
+            return Ok(false);
+        }
+        let data_blocks_dealloc = self.modify_disk_inode(|disk_inode| {
+            disk_inode.release(&self.block_device)
+        })??;
+        for data_block in data_blocks_dealloc.into_iter() {
+            self.fs.dealloc_data(data_block)?;
+        }
+        self.fs.dealloc_inode(self.inode_id)?;
+        Ok(true)
+    }
+    /// Release current inode if it has been removed while in use, in a transaction per step.
+    /// Until it is released it is an orphan, which fsck releases after a crash.
+    fn release_removed(&self) -> IoResult<()> {
+        let fs = &self.fs;
+        let removed = || -> IoResult<bool> {
+            Ok(self.read_disk_inode(|disk_inode| disk_inode.nlink == 0)?
+                && fs.inode_bitmap.is_allocated(&self.block_device, self.inode_id as usize)?)
+        };
+        if !removed()? {
+            return Ok(());
+        }
+        loop {
+            let transaction = fs.begin_transaction();
+            // a vfs inode may have been got since by whoever found the inode before it was removed,
+            // then that one releases it
+            let lock = fs.inode_lock(self.inode_id);
+            let inode = lock.write();
+            let result = (|| -> IoResult<bool> {
+                if fs.inode_shared(&lock) || !removed()? {
+                    return Ok(true);
+                }
+                self.release_step()
+            })();
+            drop(inode);
+            if fs.end_transaction(transaction, result)? {
+                return Ok(());
+            }
+        }
+    }
+    /// Whether a directory disk inode contains nothing but '.' and '..'
+    fn is_empty_dir(&self, disk_inode: &DiskInode) -> IoResult<bool> {
+        Ok(disk_inode
+            .dirents(&self.block_device)?
+            .iter()
+            .all(|(_, dirent)| dirent.name() == "." || dirent.name() == ".."))
+    }
+    /// List inodes under current inode
+    pub fn ls(&self) -> IoResult<Vec<String>> {
+        let _inode = self.lock.read();
+        Ok(self.disk_inode()?
+            .dirents(&self.block_device)?
+            .iter()
+            .filter(|(_, dirent)| dirent.name() != "." && dirent.name() != "..")
+            .map(|(_, dirent)| String::from(dirent.name()))
+            .collect())
     }
     /// Read data from current inode
-    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> IoResult<usize> {
+        let inode = self.lock.read();
+        let mut read_ahead = self.read_ahead.lock();
+        let disk_inode = self.disk_inode()?;
+        let len = (|| -> IoResult<usize> {
+            // grow the window while reads are sequential, drop it once they are not,
+            // and read ahead again only when the data read ahead runs out
+            if offset == read_ahead.next_offset {
+                read_ahead.window = (read_ahead.window * 2).clamp(READ_AHEAD_MIN, READ_AHEAD_MAX);
+                if offset + buf.len() > read_ahead.cached_end {
+                    read_ahead.cached_end = disk_inode.prefetch(
+                        offset,
+                        buf.len() + read_ahead.window * BLOCK_SZ,
+                        &self.block_device,
+                    )?;
+                }
+            } else {
+                read_ahead.window = 0;
+                read_ahead.cached_end = disk_inode.prefetch(offset, buf.len(), &self.block_device)?;
+            }
             disk_inode.read_at(offset, buf, &self.block_device)
-        })
+        })()?;
+        read_ahead.next_offset = offset + len;
+        drop(read_ahead);
+        drop(inode);
+        // like relatime, the access time is only updated when it is older than
+        // the last modification or a day, to spare a transaction on most reads
+        let now = self.fs.now();
+        let stale = disk_inode.atime < now && (disk_inode.atime <= disk_inode.mtime
+            || disk_inode.atime + ATIME_UPDATE_INTERVAL <= now);
+        if len > 0 && stale {
+            let transaction = self.fs.begin_transaction();
+            let inode = self.lock.write();
+            // the data is read already, so failing to update the access time is no error
+            let result = self.modify_disk_inode(|disk_inode| disk_inode.atime = now);
+            drop(inode);
+            let _ = self.fs.end_transaction(transaction, result);
+        }
+        Ok(len)
     }
-    /// Write data to current inode
-    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
-        let mut fs = self.fs.lock();
-        let size = self.modify_disk_inode(|disk_inode| {
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-            disk_inode.write_at(offset, buf, &self.block_device)
-        });
-        block_cache_sync_all();
-        size
+    /// Write data to current inode,
+    /// leaving a hole between the end of file and offset if it is beyond.
+    /// Return the number of bytes written, which falls short once the disk is full,
+    /// or an I/O error happens after some of the data is written.
+    ///
+    /// Like the shrinking of `truncate`, a large write is split into transactions,
+    /// between which the inode is unlocked, so it is not atomic to other writes.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> IoResult<usize> {
+        let fs = &self.fs;
+        let now = fs.now();
+        let mut size = 0usize;
+        // split the write so that blocks modified by each transaction fit in the log
+        for chunk in buf.chunks(TRANSACTION_WRITE_SIZE) {
+            let chunk_offset = offset + size;
+            let transaction = fs.begin_transaction();
+            let inode = self.lock.write();
+            let result = (|| -> IoResult<usize> {
+                let mut disk_inode = self.disk_inode()?;
+                let len = self.map_range(chunk_offset, chunk.len(), &mut disk_inode)?;
+                if len > 0 {
+                    disk_inode.mtime = now;
+                    disk_inode.ctime = now;
+                }
+                let write_size = disk_inode.write_at(chunk_offset, &chunk[..len], &self.block_device)?;
+                self.store_disk_inode(disk_inode)?;
+                Ok(write_size)
+            })();
+            drop(inode);
+            let write_size = match fs.end_transaction(transaction, result) {
+                Ok(write_size) => write_size,
+                Err(err) if size == 0 => return Err(err),
+                // the chunks committed so far are written
+                Err(_) => break,
+            };
+            size += write_size;
+            if write_size < chunk.len() {
+                break;
+            }
+        }
+        Ok(size)
+    }
+    /// Change the size of current inode to `new_size`,
+    /// releasing the blocks past it or leaving a hole up to it.
+    /// Return false if the size is beyond the max size of a file.
+    pub fn truncate(&self, new_size: u32) -> IoResult<bool> {
+        let fs = &self.fs;
+        if new_size as usize > self.read_disk_inode(|disk_inode| disk_inode.mapping().max_file_size())? {
+            return Ok(false);
+        }
+        let now = fs.now();
+        // shrink in steps so that blocks modified by each transaction fit in the log
+        loop {
+            let transaction = fs.begin_transaction();
+            let inode = self.lock.write();
+            let result = (|| -> IoResult<bool> {
+                let mut disk_inode = self.disk_inode()?;
+                disk_inode.mtime = now;
+                disk_inode.ctime = now;
+                let size = self.shrink_step(new_size, &disk_inode)?;
+                for data_block in disk_inode.truncate(size, &self.block_device)? {
+                    fs.dealloc_data(data_block)?;
+                }
+                self.store_disk_inode(disk_inode)?;
+                Ok(size == new_size)
+            })();
+            drop(inode);
+            if fs.end_transaction(transaction, result)? {
+                return Ok(true);
+            }
+        }
+    }
+    /// Get the size to shrink a disk inode to by a transaction on the way to `new_size`
+    fn shrink_step(&self, new_size: u32, disk_inode: &DiskInode) -> IoResult<u32> {
+        if new_size >= disk_inode.size {
+            return Ok(new_size);
+        }
+        disk_inode.shrink_step(
+            new_size,
+            TRANSACTION_BITMAP_BLOCKS,
+            |block_id| self.fs.data_bitmap_block(block_id),
+            &self.block_device,
+        )
+    }
+    /// Get the usage statistics of the filesystem holding current inode
+    pub fn fs_stat(&self) -> IoResult<FsStat> {
+        self.fs.stat()
     }
     /// Clear the data in current inode
-    pub fn clear(&self) {
-        let mut fs = self.fs.lock();
-        self.modify_disk_inode(|disk_inode| {
-            let size = disk_inode.size;
-            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
-            assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
-            for data_block in data_blocks_dealloc.into_iter() {
-                fs.dealloc_data(data_block);
+    pub fn clear(&self) -> IoResult<()> {
+        self.truncate(0).map(|_| ())
+    }
+    /// Get the value of an extended attribute of current inode, or None if it is not set
+    pub fn get_xattr(&self, name: &str) -> IoResult<Option<Vec<u8>>> {
+        let _inode = self.lock.read();
+        Ok(self.disk_inode()?
+            .xattrs(&self.block_device)?
+            .into_iter()
+            .find(|(xattr_name, _)| xattr_name == name)
+            .map(|(_, value)| value))
+    }
+    /// List the names of the extended attributes of current inode
+    pub fn list_xattr(&self) -> IoResult<Vec<String>> {
+        let _inode = self.lock.read();
+        Ok(self.disk_inode()?
+            .xattrs(&self.block_device)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    }
+    /// Set an extended attribute of current inode, replacing its value if it is set.
+    /// Return false if the name is invalid, the attributes of the inode do not fit
+    /// in a block along with it, or the disk is full.
+    pub fn set_xattr(&self, name: &str, value: &[u8]) -> IoResult<bool> {
+        if name.is_empty() || name.len() > XATTR_NAME_MAX || name.contains('\0') {
+            return Ok(false);
+        }
+        self.update_xattrs(|xattrs| {
+            match xattrs.iter_mut().find(|(xattr_name, _)| xattr_name == name) {
+                Some((_, xattr_value)) => *xattr_value = value.to_vec(),
+                None => xattrs.push((String::from(name), value.to_vec())),
             }
-        });
-        block_cache_sync_all();
+            true
+        })
+    }
+    /// Remove an extended attribute of current inode, return false if it is not set
+    pub fn remove_xattr(&self, name: &str) -> IoResult<bool> {
+        self.update_xattrs(|xattrs| {
+            let len = xattrs.len();
+            xattrs.retain(|(xattr_name, _)| xattr_name != name);
+            xattrs.len() < len
+        })
+    }
+    /// Change the extended attributes of current inode by `f` unless it returns false,
+    /// allocating the attribute block once there are any and releasing it once there are none
+    fn update_xattrs(&self, f: impl FnOnce(&mut Vec<(String, Vec<u8>)>) -> bool) -> IoResult<bool> {
+        let fs = &self.fs;
+        let transaction = fs.begin_transaction();
+        let inode = self.lock.write();
+        let now = fs.now();
+        let result = (|| -> IoResult<bool> {
+            let (mut xattrs, block_id) = self.read_disk_inode(|disk_inode| -> IoResult<_> {
+                Ok((disk_inode.xattrs(&self.block_device)?, disk_inode.xattr_block()))
+            })??;
+            if !f(&mut xattrs) || xattrs_size(&xattrs) > BLOCK_SZ {
+                return Ok(false);
+            }
+            if xattrs.is_empty() {
+                if let Some(block_id) = self.modify_disk_inode(|disk_inode| disk_inode.clear_xattrs())? {
+                    fs.dealloc_data(block_id)?;
+                }
+            } else {
+                let block_id = match block_id {
+                    0 => match fs.alloc_data(0)? {
+                        Some(block_id) => block_id,
+                        None => return Ok(false),
+                    },
+                    block_id => block_id,
+                };
+                self.modify_disk_inode(|disk_inode| {
+                    disk_inode.set_xattrs(&xattrs, block_id, &self.block_device)
+                })??;
+            }
+            self.modify_disk_inode(|disk_inode| disk_inode.ctime = now)?;
+            Ok(true)
+        })();
+        drop(inode);
+        fs.end_transaction(transaction, result)
+    }
+}
+
+impl Drop for Inode {
+    /// Release the inode once the last vfs inode of it is dropped after it was removed
+    fn drop(&mut self) {
+        let lock = core::mem::replace(&mut self.lock, Arc::new(RwLock::new(())));
+        if self.fs.put_inode_lock(lock) {
+            // failing to release it leaves an orphan inode, which fsck releases
+            let _ = self.release_removed();
+        }
+    }
+}
+
+/// Whether a name can be given to a new directory entry of the filesystem
+fn valid_name(name: &str, fs: &EasyFileSystem) -> bool {
+    name != "." && name != ".."
+        && name.len() <= fs.name_length_limit()
+        && DirEntry::new(name, 0).is_ok()
+}
+
+/// Split a path into the path of its parent directory and its last component
+fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(pos) => (&path[..pos], &path[pos + 1..]),
+        None => ("", path),
     }
 }
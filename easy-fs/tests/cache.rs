@@ -0,0 +1,93 @@
+mod common;
+
+use common::{read_file, CountingDevice, MemDevice};
+use easy_fs::{BlockDevice, BlockMapping, CachePolicy, ClockPolicy, EasyFileSystem, LruPolicy, BLOCK_SZ};
+use std::sync::Arc;
+
+#[test]
+fn efs_cache_test() {
+    // LRU substitutes the least recently used block, CLOCK the first one unreferenced
+    let mut lru = LruPolicy::new();
+    let mut clock = ClockPolicy::new();
+    for slot in 0..3 {
+        lru.insert(slot);
+        clock.insert(slot);
+    }
+    lru.access(0);
+    assert_eq!(lru.evict(&mut |_| true), Some(1));
+    assert_eq!(lru.evict(&mut |slot| slot != 2), Some(0));
+    assert_eq!(clock.evict(&mut |_| true), Some(0));
+    clock.insert(0);
+    clock.access(1);
+    assert_eq!(clock.evict(&mut |_| true), Some(2));
+    assert_eq!(clock.evict(&mut |slot| slot == 2), Some(2));
+    assert_eq!(clock.evict(&mut |_| false), None);
+    // the last slot moves into the substituted one
+    lru.insert(0);
+    lru.insert(1);
+    assert_eq!(lru.evict(&mut |slot| slot == 0), Some(0));
+    lru.relocate(2, 0);
+    assert_eq!(lru.evict(&mut |_| true), Some(0));
+    assert_eq!(lru.evict(&mut |_| true), Some(1));
+    assert_eq!(lru.evict(&mut |_| true), None);
+    clock.access(0);
+    clock.relocate(2, 1);
+    assert_eq!(clock.evict(&mut |slot| slot == 2), None);
+    assert_eq!(clock.evict(&mut |_| true), Some(1));
+    let mem_device: Arc<dyn BlockDevice> = Arc::new(MemDevice::new(4096));
+    EasyFileSystem::create(mem_device.clone(), 4096, 1, BlockMapping::Indirect).unwrap();
+    let policies: [Box<dyn CachePolicy>; 2] = [Box::new(LruPolicy::new()), Box::new(ClockPolicy::new())];
+    for policy in policies {
+        // a tiny cache is exceeded instead of running out of blocks
+        let efs = EasyFileSystem::open_with_cache(mem_device.clone(), 2, policy).unwrap();
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let file = root_inode
+            .find("file").unwrap()
+            .or_else(|| root_inode.create("file").unwrap())
+            .unwrap();
+        let data = [b'c'; 40 * BLOCK_SZ];
+        file.write_at(0, &data).unwrap();
+        let mut buffer = [0u8; 40 * BLOCK_SZ];
+        assert_eq!(file.read_at(0, &mut buffer).unwrap(), data.len());
+        assert!(buffer == data);
+        let stats = efs.cache_stats();
+        assert!(stats.hits > 0);
+        assert!(stats.evictions > 0);
+        assert!(stats.overflows > 0);
+        // the cache shrinks back to its capacity once the blocks are no longer in use
+        assert_eq!(stats.cached, 2);
+        file.clear().unwrap();
+    }
+}
+
+#[test]
+fn efs_read_ahead_test() {
+    let mem_device: Arc<dyn BlockDevice> = Arc::new(MemDevice::new(4096));
+    let data: Vec<u8> = (0..200 * BLOCK_SZ).map(|i| (i / BLOCK_SZ) as u8).collect();
+    {
+        let efs = EasyFileSystem::create(mem_device.clone(), 4096, 1, BlockMapping::Indirect).unwrap();
+        EasyFileSystem::root_inode(&efs)
+            .create("file").unwrap()
+            .unwrap()
+            .write_at(0, &data).unwrap();
+    }
+    // read the file sequentially in blocks from a cold cache
+    let counting_device = Arc::new(CountingDevice::new(mem_device));
+    let efs = EasyFileSystem::open(counting_device.clone()).unwrap();
+    let file = EasyFileSystem::root_inode(&efs).find("file").unwrap().unwrap();
+    counting_device.take_reads();
+    let mut buffer = [0u8; BLOCK_SZ];
+    let mut read_data: Vec<u8> = Vec::new();
+    loop {
+        let len = file.read_at(read_data.len(), &mut buffer).unwrap();
+        if len == 0 {
+            break;
+        }
+        read_data.extend_from_slice(&buffer[..len]);
+    }
+    assert!(read_data == data);
+    let requests = counting_device.take_reads();
+    assert!(requests < 200 / 4, "{} requests to read 200 blocks", requests);
+    assert!(efs.cache_stats().read_ahead > 0);
+    assert_eq!(read_file(&file).unwrap(), data);
+}
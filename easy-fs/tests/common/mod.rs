@@ -0,0 +1,238 @@
+//! Block devices and a model of the files shared by the tests of easy-fs
+
+#![allow(dead_code)]
+
+use easy_fs::{BlockDevice, Inode, IoError, IoResult, BLOCK_SZ};
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A BlockDevice keeping its blocks in memory
+pub struct MemDevice(pub Mutex<Vec<u8>>);
+
+impl MemDevice {
+    pub fn new(blocks: usize) -> Self {
+        Self(Mutex::new(vec![0u8; blocks * BLOCK_SZ]))
+    }
+    /// Find the last block holding exactly `data`
+    pub fn find_block(&self, data: &[u8]) -> Option<usize> {
+        self.0.lock().unwrap().chunks(BLOCK_SZ).rposition(|block| block == data)
+    }
+}
+
+impl BlockDevice for MemDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> IoResult<()> {
+        let blocks = self.0.lock().unwrap();
+        let block = blocks.get(block_id * BLOCK_SZ..(block_id + 1) * BLOCK_SZ).ok_or(IoError::OutOfRange)?;
+        buf.copy_from_slice(block);
+        Ok(())
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> IoResult<()> {
+        let mut blocks = self.0.lock().unwrap();
+        let block = blocks.get_mut(block_id * BLOCK_SZ..(block_id + 1) * BLOCK_SZ).ok_or(IoError::OutOfRange)?;
+        block.copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// Faults injected by a `FaultyDevice`
+#[derive(Default)]
+pub struct Faults {
+    /// writes left before every further write is dropped, unlimited if None
+    pub write_budget: Option<usize>,
+    /// whether a write has been dropped
+    pub dropped: bool,
+    /// bits flipped in the data read from blocks, as (block id, bit)
+    pub flipped_bits: Vec<(usize, usize)>,
+    /// blocks of which only the first half is read before the request fails
+    pub short_reads: Vec<usize>,
+    /// blocks which fail to be read
+    pub failing_reads: Vec<usize>,
+    /// blocks which fail to be written
+    pub failing_writes: Vec<usize>,
+}
+
+/// A BlockDevice injecting faults into the requests issued to another one
+pub struct FaultyDevice(pub Arc<dyn BlockDevice>, pub Mutex<Faults>);
+
+impl FaultyDevice {
+    pub fn new(block_device: Arc<dyn BlockDevice>, faults: Faults) -> Self {
+        Self(block_device, Mutex::new(faults))
+    }
+    /// Replace the faults injected from now on
+    pub fn set_faults(&self, faults: Faults) {
+        *self.1.lock().unwrap() = faults;
+    }
+}
+
+impl BlockDevice for FaultyDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> IoResult<()> {
+        let faults = self.1.lock().unwrap();
+        if faults.failing_reads.contains(&block_id) {
+            return Err(IoError::Device);
+        }
+        self.0.read_block(block_id, buf)?;
+        if faults.short_reads.contains(&block_id) {
+            buf[BLOCK_SZ / 2..].fill(0);
+            return Err(IoError::ShortTransfer);
+        }
+        for &(_, bit) in faults.flipped_bits.iter().filter(|(id, _)| *id == block_id) {
+            buf[bit / 8] ^= 1 << (bit % 8);
+        }
+        Ok(())
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> IoResult<()> {
+        let mut faults = self.1.lock().unwrap();
+        if faults.failing_writes.contains(&block_id) {
+            return Err(IoError::Device);
+        }
+        match faults.write_budget {
+            Some(0) => faults.dropped = true,
+            Some(ref mut budget) => {
+                *budget -= 1;
+                self.0.write_block(block_id, buf)?;
+            }
+            None => self.0.write_block(block_id, buf)?,
+        }
+        Ok(())
+    }
+}
+
+/// A BlockDevice counting the requests issued to another one
+pub struct CountingDevice(pub Arc<dyn BlockDevice>, pub AtomicUsize);
+
+impl CountingDevice {
+    pub fn new(block_device: Arc<dyn BlockDevice>) -> Self {
+        Self(block_device, AtomicUsize::new(0))
+    }
+    /// Get the number of read requests so far and start counting from zero again
+    pub fn take_reads(&self) -> usize {
+        self.1.swap(0, Ordering::SeqCst)
+    }
+}
+
+impl BlockDevice for CountingDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> IoResult<()> {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        self.0.read_block(block_id, buf)
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> IoResult<()> {
+        self.0.write_block(block_id, buf)
+    }
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) -> IoResult<()> {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        self.0.read_blocks(block_id, buf)
+    }
+}
+
+/// Read all data of a file inode
+pub fn read_file(inode: &Inode) -> IoResult<Vec<u8>> {
+    let mut data = vec![0u8; inode.metadata()?.size as usize];
+    let len = inode.read_at(0, &mut data)?;
+    data.truncate(len);
+    Ok(data)
+}
+
+/// An operation on the files of a filesystem
+#[derive(Debug, Clone)]
+pub enum FileOp {
+    Create(String),
+    Write(String, usize, Vec<u8>),
+    Read(String, usize, usize),
+    Truncate(String, u32),
+    Unlink(String),
+    /// drop the filesystem and open it again
+    Reopen,
+}
+
+/// Generate a random operation on the files named from a small pool,
+/// writing at most `max_write` bytes at once
+pub fn random_file_op(rng: &mut StdRng, max_write: usize) -> FileOp {
+    let names = ["a", "b", "c.txt", "a_name_longer_than_a_single_directory_entry"];
+    let name = names[rng.gen_range(0..names.len())].to_string();
+    // offsets reach the doubly indirect blocks now and then
+    let offset = if rng.gen_bool(0.1) { rng.gen_range(0..200_000) } else { rng.gen_range(0..20_000) };
+    match rng.gen_range(0..20) {
+        0..=3 => FileOp::Create(name),
+        4..=9 => {
+            let len = rng.gen_range(0..=max_write);
+            FileOp::Write(name, offset, (0..len).map(|_| rng.gen()).collect())
+        }
+        10..=13 => FileOp::Read(name, offset, rng.gen_range(0..5000)),
+        14..=16 => FileOp::Truncate(name, offset as u32),
+        17..=18 => FileOp::Unlink(name),
+        _ => FileOp::Reopen,
+    }
+}
+
+/// Apply an operation to the model of the files, which maps names to data
+pub fn apply_file_op(model: &mut BTreeMap<String, Vec<u8>>, op: &FileOp) {
+    match op {
+        FileOp::Create(name) => {
+            model.entry(name.clone()).or_default();
+        }
+        FileOp::Write(name, offset, data) => {
+            if let Some(file) = model.get_mut(name) {
+                if file.len() < offset + data.len() {
+                    file.resize(offset + data.len(), 0);
+                }
+                file[*offset..offset + data.len()].copy_from_slice(data);
+            }
+        }
+        FileOp::Truncate(name, size) => {
+            if let Some(file) = model.get_mut(name) {
+                file.resize(*size as usize, 0);
+            }
+        }
+        FileOp::Unlink(name) => {
+            model.remove(name);
+        }
+        FileOp::Read(..) | FileOp::Reopen => {}
+    }
+}
+
+/// Apply an operation to the root directory of a filesystem,
+/// checking the result of a read against the model
+pub fn run_file_op(root_inode: &Inode, model: &BTreeMap<String, Vec<u8>>, op: &FileOp) {
+    match op {
+        FileOp::Create(name) => {
+            assert_eq!(root_inode.create(name).unwrap().is_some(), !model.contains_key(name), "{:?}", op);
+        }
+        FileOp::Write(name, offset, data) => {
+            if let Some(file) = root_inode.find(name).unwrap() {
+                assert_eq!(file.write_at(*offset, data).unwrap(), data.len(), "{:?}", op);
+            }
+        }
+        FileOp::Read(name, offset, len) => {
+            if let Some(file) = root_inode.find(name).unwrap() {
+                let expected = &model[name];
+                let expected = &expected[(*offset).min(expected.len())..(offset + len).min(expected.len())];
+                let mut buffer = vec![0u8; *len];
+                let read_len = file.read_at(*offset, &mut buffer).unwrap();
+                assert_eq!(&buffer[..read_len], expected, "{:?}", op);
+            }
+        }
+        FileOp::Truncate(name, size) => {
+            if let Some(file) = root_inode.find(name).unwrap() {
+                assert!(file.truncate(*size).unwrap(), "{:?}", op);
+            }
+        }
+        FileOp::Unlink(name) => {
+            assert_eq!(root_inode.unlink(name).unwrap(), model.contains_key(name), "{:?}", op);
+        }
+        FileOp::Reopen => {}
+    }
+}
+
+/// Check that the files of a filesystem are the ones of the model
+pub fn check_files(root_inode: &Inode, model: &BTreeMap<String, Vec<u8>>) -> bool {
+    let mut names = root_inode.ls().unwrap();
+    names.sort();
+    names.iter().eq(model.keys())
+        && model.iter().all(|(name, data)| {
+            let file = root_inode.find(name).unwrap().unwrap();
+            file.metadata().unwrap().size as usize == data.len() && read_file(&file).unwrap() == *data
+        })
+}
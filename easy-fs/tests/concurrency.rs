@@ -0,0 +1,159 @@
+mod common;
+
+use common::{read_file, MemDevice};
+use easy_fs::{BlockDevice, BlockMapping, EasyFileSystem, IoResult, BLOCK_SZ};
+use rand::{Rng, SeedableRng};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// whether the next read of the thread stalls
+    static STALL: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A BlockDevice stalling the next read of a thread marked by `STALL`
+/// until another thread reads a block, or a timeout passes
+struct StallingDevice {
+    device: Arc<dyn BlockDevice>,
+    /// reads by threads not stalling
+    reads: AtomicUsize,
+    /// whether a read is stalling
+    stalling: AtomicBool,
+    /// whether a stalling read timed out
+    timed_out: AtomicBool,
+}
+
+impl BlockDevice for StallingDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> IoResult<()> {
+        if STALL.with(|stall| stall.replace(false)) {
+            let reads = self.reads.load(Ordering::SeqCst);
+            self.stalling.store(true, Ordering::SeqCst);
+            let start = Instant::now();
+            while self.reads.load(Ordering::SeqCst) == reads {
+                if start.elapsed() > Duration::from_secs(5) {
+                    self.timed_out.store(true, Ordering::SeqCst);
+                    break;
+                }
+                std::thread::yield_now();
+            }
+        } else {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+        }
+        self.device.read_block(block_id, buf)
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> IoResult<()> {
+        self.device.write_block(block_id, buf)
+    }
+}
+
+#[test]
+fn efs_parallel_writers_test() {
+    let stalling_device = Arc::new(StallingDevice {
+        device: Arc::new(MemDevice::new(4096)),
+        reads: AtomicUsize::new(0),
+        stalling: AtomicBool::new(false),
+        timed_out: AtomicBool::new(false),
+    });
+    let block_device: Arc<dyn BlockDevice> = stalling_device.clone();
+    let efs = EasyFileSystem::create(block_device.clone(), 4096, 1, BlockMapping::Indirect).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    for name in ["a", "b"] {
+        root_inode.create(name).unwrap().unwrap().write_at(0, &[0u8; BLOCK_SZ]).unwrap();
+    }
+    drop((root_inode, efs));
+    // only the data blocks of the files are left to be read by the writers
+    let efs = EasyFileSystem::open(block_device).unwrap();
+    efs.set_relax(std::thread::yield_now);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let filea = root_inode.find("a").unwrap().unwrap();
+    let fileb = root_inode.find("b").unwrap().unwrap();
+    filea.metadata().unwrap();
+    fileb.metadata().unwrap();
+    // the write of a stalls in the middle of its transaction until b is written
+    let writer = std::thread::spawn(move || {
+        STALL.with(|stall| stall.set(true));
+        assert_eq!(filea.write_at(0, &[b'a'; BLOCK_SZ]).unwrap(), BLOCK_SZ);
+        filea
+    });
+    while !stalling_device.stalling.load(Ordering::SeqCst) {
+        std::thread::yield_now();
+    }
+    assert_eq!(fileb.write_at(0, &[b'b'; BLOCK_SZ]).unwrap(), BLOCK_SZ);
+    let filea = writer.join().unwrap();
+    assert!(!stalling_device.timed_out.load(Ordering::SeqCst), "the writers ran one at a time");
+    assert_eq!(read_file(&filea).unwrap(), [b'a'; BLOCK_SZ]);
+    assert_eq!(read_file(&fileb).unwrap(), [b'b'; BLOCK_SZ]);
+    let report = EasyFileSystem::fsck(&efs, false).unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems);
+}
+
+#[test]
+fn efs_concurrent_writers_test() {
+    let mem_device: Arc<dyn BlockDevice> = Arc::new(MemDevice::new(16384));
+    let efs = EasyFileSystem::create(mem_device, 16384, 1, BlockMapping::Extents).unwrap();
+    efs.set_relax(std::thread::yield_now);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let done = Arc::new(AtomicBool::new(false));
+    // the filesystem is checked while it is written, each check seeing no transaction in progress
+    let checker = {
+        let efs = Arc::clone(&efs);
+        let done = Arc::clone(&done);
+        std::thread::spawn(move || {
+            let mut checks = 0;
+            loop {
+                let report = EasyFileSystem::fsck(&efs, false).unwrap();
+                assert!(report.is_clean(), "{:?}", report.problems);
+                checks += 1;
+                if done.load(Ordering::SeqCst) {
+                    return checks;
+                }
+                // leave the writers some time between the checks
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        })
+    };
+    let mut threads = Vec::new();
+    for id in 0..8u64 {
+        let root_inode = Arc::clone(&root_inode);
+        threads.push(std::thread::spawn(move || {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(id);
+            let file = root_inode.create(&format!("file{}", id)).unwrap().unwrap();
+            let mut model: Vec<u8> = Vec::new();
+            for _ in 0..100 {
+                if rng.gen_bool(0.2) {
+                    let new_size = rng.gen_range(0..model.len() + 1);
+                    assert!(file.truncate(new_size as u32).unwrap());
+                    model.truncate(new_size);
+                } else {
+                    let offset = rng.gen_range(0..model.len() + BLOCK_SZ);
+                    let data: Vec<u8> = (0..rng.gen_range(1..9 * BLOCK_SZ)).map(|_| rng.gen()).collect();
+                    assert_eq!(file.write_at(offset, &data).unwrap(), data.len());
+                    if model.len() < offset + data.len() {
+                        model.resize(offset + data.len(), 0);
+                    }
+                    model[offset..offset + data.len()].copy_from_slice(&data);
+                }
+                assert_eq!(read_file(&file).unwrap(), model);
+            }
+            model
+        }));
+    }
+    let models: Vec<Vec<u8>> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+    done.store(true, Ordering::SeqCst);
+    assert!(checker.join().unwrap() > 1);
+    drop(root_inode);
+    let efs = {
+        let block_device = Arc::clone(&efs.block_device);
+        drop(efs);
+        EasyFileSystem::open(block_device).unwrap()
+    };
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    for (id, model) in models.iter().enumerate() {
+        let file = root_inode.find(&format!("file{}", id)).unwrap().unwrap();
+        assert_eq!(read_file(&file).unwrap(), *model);
+    }
+    let report = EasyFileSystem::fsck(&efs, false).unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems);
+}
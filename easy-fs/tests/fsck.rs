@@ -0,0 +1,100 @@
+mod common;
+
+use common::MemDevice;
+use easy_fs::{BlockDevice, BlockMapping, EasyFileSystem, FsGeometry, FsckProblem, BLOCK_SZ};
+use std::sync::Arc;
+
+#[test]
+fn efs_fsck_test() {
+    let mem_device = Arc::new(MemDevice::new(4096));
+    let efs = EasyFileSystem::create(mem_device.clone(), 4096, 1, BlockMapping::Indirect).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let filea = root_inode.create("filea").unwrap().unwrap();
+    filea.write_at(0, &[b'a'; 2 * BLOCK_SZ]).unwrap();
+    root_inode.create_dir("dir").unwrap().unwrap();
+    root_inode.create("dir/fileb").unwrap().unwrap();
+    assert!(root_inode.link("dir/fileb", "filec").unwrap());
+    let report = EasyFileSystem::fsck(&efs, false).unwrap();
+    assert!(report.is_clean());
+    let filea_id = filea.inode_id() as usize;
+    drop((filea, root_inode, efs));
+    // free the inode of filea and allocate an unused one in the inode bitmap,
+    // which follows the super block and the log
+    let geometry = FsGeometry::new(4096, (BLOCK_SZ * 8) as u32).unwrap();
+    let inode_bitmap_block = 1 + geometry.log_blocks as usize;
+    let mut bitmap_block = [0u8; BLOCK_SZ];
+    mem_device.read_block(inode_bitmap_block, &mut bitmap_block).unwrap();
+    bitmap_block[filea_id / 8] &= !(1 << (filea_id % 8));
+    bitmap_block[100 / 8] |= 1 << (100 % 8);
+    mem_device.write_block(inode_bitmap_block, &bitmap_block).unwrap();
+    let efs = EasyFileSystem::open(mem_device.clone()).unwrap();
+    let report = EasyFileSystem::fsck(&efs, true).unwrap();
+    assert!(report.repaired);
+    assert!(report.problems.contains(&FsckProblem::DanglingEntry {
+        dir_inode_id: 0,
+        name: String::from("filea"),
+        inode_id: filea_id as u32,
+    }));
+    assert!(report.problems.contains(&FsckProblem::OrphanInode { inode_id: 100 }));
+    let leaked = report
+        .problems
+        .iter()
+        .filter(|problem| matches!(problem, FsckProblem::LeakedBlock { .. }))
+        .count();
+    assert_eq!(leaked, 2);
+    assert_eq!(report.problems.len(), 4);
+    // nothing is left after the repair
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+    drop(efs);
+    let efs = EasyFileSystem::open(mem_device).unwrap();
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert!(root_inode.find("filea").unwrap().is_none());
+    assert_eq!(root_inode.find("filec").unwrap().unwrap().nlink().unwrap(), 2);
+}
+
+#[test]
+fn efs_fsck_corrupt_entry_test() {
+    let mem_device = Arc::new(MemDevice::new(4096));
+    let efs = EasyFileSystem::create(mem_device.clone(), 4096, 1, BlockMapping::Indirect).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let long_name = "a_long_name_taking_an_extension_slot";
+    root_inode.create(long_name).unwrap().unwrap();
+    root_inode.create("bad_utf8").unwrap().unwrap();
+    root_inode.create("good").unwrap().unwrap();
+    drop((root_inode, efs));
+    // make the long name run past the name length limit and the short one invalid UTF-8,
+    // the first slot of an entry holding 27 bytes of the name and the number of extension slots
+    let head = &long_name.as_bytes()[..27];
+    let mut block = [0u8; BLOCK_SZ];
+    // the directory block is the last block holding the entries, the others being in the log
+    let block_id = (0..4096)
+        .filter(|&block_id| {
+            mem_device.read_block(block_id, &mut block).unwrap();
+            block.windows(head.len()).any(|window| window == head)
+        })
+        .last()
+        .unwrap();
+    mem_device.read_block(block_id, &mut block).unwrap();
+    let long_slot = block.windows(head.len()).position(|window| window == head).unwrap();
+    block[long_slot + 27] = 0xff;
+    let bad_slot = block.windows(8).position(|window| window == b"bad_utf8").unwrap();
+    block[bad_slot] = 0xc3;
+    block[bad_slot + 1] = 0x28;
+    mem_device.write_block(block_id, &block).unwrap();
+    let efs = EasyFileSystem::open(mem_device.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root_inode.ls().unwrap(), vec!["good"]);
+    let report = EasyFileSystem::fsck(&efs, true).unwrap();
+    assert!(report.repaired);
+    let corrupt = report
+        .problems
+        .iter()
+        .filter(|problem| matches!(problem, FsckProblem::CorruptEntry { dir_inode_id: 0, .. }))
+        .count();
+    assert_eq!(corrupt, 2);
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+    // the slots of the entries are free again
+    root_inode.create(long_name).unwrap().unwrap();
+    assert_eq!(root_inode.ls().unwrap(), vec![long_name, "good"]);
+}
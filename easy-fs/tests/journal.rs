@@ -0,0 +1,231 @@
+mod common;
+
+use common::{apply_file_op, check_files, random_file_op, read_file, run_file_op, FaultyDevice, Faults, FileOp, MemDevice};
+use easy_fs::{BlockDevice, BlockMapping, EasyFileSystem, FsGeometry, FsckProblem, IoError, BLOCK_SZ};
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[test]
+fn efs_journal_test() {
+    let data = [b'j'; 3 * BLOCK_SZ];
+    for budget in 0.. {
+        let mem_device: Arc<dyn BlockDevice> = Arc::new(MemDevice::new(4096));
+        {
+            let efs = EasyFileSystem::create(mem_device.clone(), 4096, 1, BlockMapping::Indirect).unwrap();
+            EasyFileSystem::root_inode(&efs).create("old").unwrap().unwrap();
+        }
+        // crash after `budget` writes while creating and writing a file
+        let crash_device = Arc::new(FaultyDevice::new(mem_device.clone(), Faults {
+            write_budget: Some(budget),
+            ..Default::default()
+        }));
+        {
+            let efs = EasyFileSystem::open(crash_device.clone()).unwrap();
+            let root_inode = EasyFileSystem::root_inode(&efs);
+            let file = root_inode.create("new").unwrap().unwrap();
+            file.write_at(0, &data).unwrap();
+        }
+        let finished = !crash_device.1.lock().unwrap().dropped;
+        // every transaction is either installed completely or not at all
+        let efs = EasyFileSystem::open(mem_device).unwrap();
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        assert!(root_inode.find("old").unwrap().is_some());
+        match root_inode.find("new").unwrap() {
+            Some(file) => {
+                let mut buffer = [0u8; 3 * BLOCK_SZ];
+                let len = file.read_at(0, &mut buffer).unwrap();
+                assert!(len == 0 || len == data.len());
+                assert!(buffer[..len].iter().all(|&b| b == b'j'));
+                if finished {
+                    assert_eq!(len, data.len());
+                    break;
+                }
+            }
+            None => assert!(!finished),
+        }
+    }
+}
+
+#[test]
+fn efs_corrupt_log_test() {
+    let mem_device: Arc<dyn BlockDevice> = Arc::new(MemDevice::new(4096));
+    EasyFileSystem::create(mem_device.clone(), 4096, 1, BlockMapping::Indirect).unwrap();
+    // the log header follows the super block, holding the number of logged blocks
+    // and their home block ids
+    let write_header = |count: u32, block_id: u32| {
+        let mut header = [0u8; BLOCK_SZ];
+        header[..4].copy_from_slice(&count.to_ne_bytes());
+        header[4..8].copy_from_slice(&block_id.to_ne_bytes());
+        mem_device.write_block(1, &header).unwrap();
+    };
+    write_header(u32::MAX, 0);
+    assert!(matches!(EasyFileSystem::open(mem_device.clone()), Err(IoError::Corrupt)));
+    // a logged block cannot be installed into the log
+    write_header(1, 2);
+    assert!(matches!(EasyFileSystem::open(mem_device.clone()), Err(IoError::Corrupt)));
+    write_header(0, 0);
+    assert!(EasyFileSystem::open(mem_device).is_ok());
+}
+
+#[test]
+fn efs_fault_test() {
+    // a crash leaves the files as they were before or after the operation in progress,
+    // given that each operation is a single transaction
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    for _ in 0..40 {
+        let mem_device: Arc<dyn BlockDevice> = Arc::new(MemDevice::new(2048));
+        EasyFileSystem::create(mem_device.clone(), 2048, 1, BlockMapping::Indirect).unwrap();
+        let faulty_device = Arc::new(FaultyDevice::new(mem_device.clone(), Faults {
+            write_budget: Some(rng.gen_range(0..2000)),
+            ..Default::default()
+        }));
+        let mut model = BTreeMap::new();
+        let mut before = model.clone();
+        {
+            let efs = EasyFileSystem::open(faulty_device.clone()).unwrap();
+            let root_inode = EasyFileSystem::root_inode(&efs);
+            while !faulty_device.1.lock().unwrap().dropped {
+                let op = match random_file_op(&mut rng, 4 * BLOCK_SZ) {
+                    FileOp::Reopen => continue,
+                    op => op,
+                };
+                before = model.clone();
+                run_file_op(&root_inode, &model, &op);
+                apply_file_op(&mut model, &op);
+            }
+        }
+        let efs = EasyFileSystem::open(mem_device).unwrap();
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        assert!(check_files(&root_inode, &before) || check_files(&root_inode, &model));
+        assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+    }
+    // a flipped bit of a data block shows up in the file only
+    let mem_device = Arc::new(MemDevice::new(2048));
+    let data: Vec<u8> = (0..3 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    {
+        let efs = EasyFileSystem::create(mem_device.clone(), 2048, 1, BlockMapping::Indirect).unwrap();
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        root_inode.create("file").unwrap().unwrap().write_at(0, &data).unwrap();
+    }
+    // the log holds a copy of the block as well, before the data area
+    let block_id = mem_device.find_block(&data[BLOCK_SZ..2 * BLOCK_SZ]).unwrap();
+    let faulty_device = Arc::new(FaultyDevice::new(mem_device, Faults {
+        flipped_bits: vec![(block_id, 13)],
+        ..Default::default()
+    }));
+    {
+        let efs = EasyFileSystem::open(faulty_device.clone()).unwrap();
+        let file = EasyFileSystem::root_inode(&efs).find("file").unwrap().unwrap();
+        let mut flipped = data.clone();
+        flipped[BLOCK_SZ + 1] ^= 1 << 5;
+        assert_eq!(read_file(&file).unwrap(), flipped);
+        assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+    }
+    // a flipped bit of the data bitmap frees the block of the root directory
+    let geometry = FsGeometry::new(2048, BLOCK_SZ as u32 * 8).unwrap();
+    let data_bitmap_block = 1 + geometry.log_blocks + geometry.inode_bitmap_blocks + geometry.inode_area_blocks;
+    let data_area_start = data_bitmap_block + geometry.data_bitmap_blocks;
+    faulty_device.set_faults(Faults {
+        flipped_bits: vec![(data_bitmap_block as usize, 0)],
+        ..Default::default()
+    });
+    {
+        let efs = EasyFileSystem::open(faulty_device.clone()).unwrap();
+        let report = EasyFileSystem::fsck(&efs, false).unwrap();
+        assert_eq!(report.problems, vec![FsckProblem::UnmarkedBlock { block_id: data_area_start }]);
+    }
+    // a short read fails the read, and the partial block is not kept in the cache
+    faulty_device.set_faults(Faults {
+        short_reads: vec![block_id],
+        ..Default::default()
+    });
+    let efs = EasyFileSystem::open(faulty_device.clone()).unwrap();
+    let file = EasyFileSystem::root_inode(&efs).find("file").unwrap().unwrap();
+    let mut buffer = vec![0u8; data.len()];
+    assert_eq!(file.read_at(0, &mut buffer), Err(IoError::ShortTransfer));
+    faulty_device.set_faults(Faults::default());
+    assert_eq!(read_file(&file).unwrap(), data);
+}
+
+#[test]
+fn efs_io_error_test() {
+    let mem_device = Arc::new(MemDevice::new(2048));
+    let data: Vec<u8> = (0..3 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    {
+        let efs = EasyFileSystem::create(mem_device.clone(), 2048, 1, BlockMapping::Indirect).unwrap();
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        root_inode.create("file").unwrap().unwrap().write_at(0, &data).unwrap();
+    }
+    let block_id = mem_device.find_block(&data[BLOCK_SZ..2 * BLOCK_SZ]).unwrap();
+    let faulty_device = Arc::new(FaultyDevice::new(mem_device.clone(), Faults {
+        failing_reads: vec![block_id],
+        ..Default::default()
+    }));
+    let efs = EasyFileSystem::open(faulty_device.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let file = root_inode.find("file").unwrap().unwrap();
+    // a failing read is reported, and the blocks read before are not affected
+    let mut buffer = [0u8; BLOCK_SZ];
+    assert_eq!(file.read_at(0, &mut buffer), Ok(BLOCK_SZ));
+    assert_eq!(file.read_at(BLOCK_SZ, &mut buffer), Err(IoError::Device));
+    // a write failing before the commit point, to the first log block or the log header
+    // following the super block, drops the operation as a whole
+    faulty_device.set_faults(Faults { failing_writes: vec![2], ..Default::default() });
+    assert!(matches!(root_inode.create("new"), Err(IoError::Device)));
+    assert_eq!(file.write_at(0, b"lost"), Err(IoError::Device));
+    faulty_device.set_faults(Faults { failing_writes: vec![1], ..Default::default() });
+    assert_eq!(root_inode.unlink("file"), Err(IoError::Device));
+    faulty_device.set_faults(Faults::default());
+    assert!(root_inode.find("new").unwrap().is_none());
+    assert_eq!(read_file(&file).unwrap(), data);
+    // a write failing after the commit point leaves the operation done,
+    // and it is installed from the log before the next transaction
+    faulty_device.set_faults(Faults { failing_writes: vec![block_id], ..Default::default() });
+    assert_eq!(file.write_at(BLOCK_SZ, b"kept"), Ok(4));
+    faulty_device.set_faults(Faults::default());
+    root_inode.create("new").unwrap().unwrap();
+    drop((file, root_inode, efs));
+    let efs = EasyFileSystem::open(mem_device).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let mut kept = data;
+    kept[BLOCK_SZ..BLOCK_SZ + 4].copy_from_slice(b"kept");
+    assert_eq!(read_file(&root_inode.find("file").unwrap().unwrap()).unwrap(), kept);
+    assert!(root_inode.find("new").unwrap().is_some());
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+}
+
+#[test]
+fn efs_large_release_test() {
+    for mapping in [BlockMapping::Indirect, BlockMapping::Extents] {
+        // leave two free blocks at the start of each group of data blocks
+        // covered by a data bitmap block, so that a file is spread over all of them
+        let groups = 36;
+        let geometry = FsGeometry::with_data_blocks(4096, groups * BLOCK_SZ as u32 * 8).unwrap();
+        let mem_device = Arc::new(MemDevice::new(geometry.total_blocks as usize));
+        EasyFileSystem::create_with_geometry(mem_device.clone(), geometry, mapping).unwrap();
+        let data_bitmap_block = 1 + geometry.log_blocks + geometry.inode_bitmap_blocks + geometry.inode_area_blocks;
+        for group in 0..geometry.data_bitmap_blocks {
+            let mut bitmap_block = [0xffu8; BLOCK_SZ];
+            bitmap_block[0] = if group == 0 { 0b1111_1001 } else { 0b1111_1100 };
+            mem_device.write_block((data_bitmap_block + group) as usize, &bitmap_block).unwrap();
+        }
+        let efs = EasyFileSystem::open(mem_device.clone()).unwrap();
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let free_blocks = root_inode.fs_stat().unwrap().free_blocks;
+        // releasing the file modifies more bitmap blocks than the log holds
+        let data: Vec<u8> = (0..64 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+        let file = root_inode.create("file").unwrap().unwrap();
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+        assert!(file.truncate(BLOCK_SZ as u32).unwrap());
+        assert_eq!(read_file(&file).unwrap(), data[..BLOCK_SZ]);
+        assert_eq!(root_inode.fs_stat().unwrap().free_blocks, free_blocks - 1);
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+        drop(file);
+        assert!(root_inode.unlink("file").unwrap());
+        assert_eq!(root_inode.fs_stat().unwrap().free_blocks, free_blocks);
+        drop((root_inode, efs));
+        let efs = EasyFileSystem::open(mem_device).unwrap();
+        assert_eq!(efs.stat().unwrap().free_blocks, free_blocks);
+    }
+}
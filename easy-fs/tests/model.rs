@@ -0,0 +1,92 @@
+mod common;
+
+use common::{apply_file_op, check_files, random_file_op, read_file, run_file_op, FileOp, MemDevice};
+use easy_fs::{BlockDevice, BlockMapping, EasyFileSystem, BLOCK_SZ};
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[test]
+fn efs_model_test() {
+    for seed in 0..8 {
+        let mapping = if seed % 2 == 0 { BlockMapping::Indirect } else { BlockMapping::Extents };
+        let mem_device: Arc<dyn BlockDevice> = Arc::new(MemDevice::new(4096));
+        let mut efs = EasyFileSystem::create(mem_device.clone(), 4096, 1, mapping).unwrap();
+        let mut root_inode = EasyFileSystem::root_inode(&efs);
+        let mut model = BTreeMap::new();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        for _ in 0..300 {
+            let op = random_file_op(&mut rng, 20_000);
+            if let FileOp::Reopen = op {
+                drop((root_inode, efs));
+                efs = EasyFileSystem::open(mem_device.clone()).unwrap();
+                root_inode = EasyFileSystem::root_inode(&efs);
+            }
+            run_file_op(&root_inode, &model, &op);
+            apply_file_op(&mut model, &op);
+        }
+        assert!(check_files(&root_inode, &model), "seed {}", seed);
+        let report = EasyFileSystem::fsck(&efs, false).unwrap();
+        assert!(report.is_clean(), "seed {}: {:?}", seed, report.problems);
+    }
+}
+
+#[test]
+fn efs_stress_test() {
+    let mem_device: Arc<dyn BlockDevice> = Arc::new(MemDevice::new(16384));
+    let efs = EasyFileSystem::create(mem_device, 16384, 1, BlockMapping::Indirect).unwrap();
+    efs.set_relax(std::thread::yield_now);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let shared_data: Vec<u8> = (0..5 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    let shared = root_inode.create("shared").unwrap().unwrap();
+    shared.write_at(0, &shared_data).unwrap();
+    let mut threads = Vec::new();
+    for id in 0..8u64 {
+        let root_inode = Arc::clone(&root_inode);
+        let shared_data = shared_data.clone();
+        threads.push(std::thread::spawn(move || {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(id);
+            let dir = root_inode.create_dir(&format!("dir{}", id)).unwrap().unwrap();
+            let file = dir.create("file").unwrap().unwrap();
+            let mut model: Vec<u8> = Vec::new();
+            for round in 0..50 {
+                // files of every thread are written, read and removed concurrently
+                match rng.gen_range(0..4) {
+                    0 => {
+                        let new_size = rng.gen_range(0..2 * model.len() + 1);
+                        assert!(file.truncate(new_size as u32).unwrap());
+                        model.resize(new_size, 0);
+                    }
+                    1 => {
+                        let name = format!("tmp{}", round);
+                        dir.create(&name).unwrap().unwrap().write_at(0, b"tmp").unwrap();
+                        assert!(dir.ls().unwrap().contains(&name));
+                        assert!(dir.unlink(&name).unwrap());
+                    }
+                    _ => {
+                        let offset = rng.gen_range(0..model.len() + BLOCK_SZ);
+                        let data: Vec<u8> = (0..rng.gen_range(1..3 * BLOCK_SZ)).map(|_| rng.gen()).collect();
+                        assert_eq!(file.write_at(offset, &data).unwrap(), data.len());
+                        if model.len() < offset + data.len() {
+                            model.resize(offset + data.len(), 0);
+                        }
+                        model[offset..offset + data.len()].copy_from_slice(&data);
+                    }
+                }
+                assert_eq!(read_file(&file).unwrap(), model);
+                // the file written by none of them reads the same all along
+                let shared = root_inode.find("shared").unwrap().unwrap();
+                assert_eq!(read_file(&shared).unwrap(), shared_data);
+                assert!(root_inode.ls().unwrap().contains(&format!("dir{}", id)));
+            }
+            model
+        }));
+    }
+    let models: Vec<Vec<u8>> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+    for (id, model) in models.iter().enumerate() {
+        let file = root_inode.find(&format!("dir{}/file", id)).unwrap().unwrap();
+        assert_eq!(read_file(&file).unwrap(), *model);
+    }
+    let report = EasyFileSystem::fsck(&efs, false).unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems);
+}
@@ -0,0 +1,391 @@
+mod common;
+
+use common::{read_file, MemDevice};
+use easy_fs::{
+    BlockDevice, BlockMapping, DirEntry, DirEntryError, EasyFileSystem, BLOCK_SZ, MAX_FILE_SIZE,
+    NAME_LENGTH_LIMIT, SYMLINK_MAX_LEN, XATTR_NAME_MAX,
+};
+use std::sync::Arc;
+
+/// Create a filesystem of `blocks` blocks in memory, its inodes mapping data as `mapping`
+fn mem_fs(blocks: u32, mapping: BlockMapping) -> Arc<EasyFileSystem> {
+    let mem_device: Arc<dyn BlockDevice> = Arc::new(MemDevice::new(blocks as usize));
+    EasyFileSystem::create(mem_device, blocks, 1, mapping).unwrap()
+}
+
+#[test]
+fn efs_dir_test() {
+    let efs = mem_fs(4096, BlockMapping::Indirect);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert!(root_inode.ls().unwrap().is_empty());
+    let dir_a = root_inode.create_dir("a").unwrap().unwrap();
+    assert!(dir_a.is_dir().unwrap());
+    root_inode.create_dir("a/b").unwrap().unwrap();
+    assert!(root_inode.create_dir("a/b").unwrap().is_none());
+    assert!(root_inode.create_dir("x/y").unwrap().is_none());
+    let file_c = root_inode.create("a/b/c").unwrap().unwrap();
+    assert!(!file_c.is_dir().unwrap());
+    file_c.write_at(0, b"nested").unwrap();
+    // path walking honours '.' and '..'
+    let found = root_inode.find("/a/./b/../b/c").unwrap().unwrap();
+    assert_eq!(found.inode_id(), file_c.inode_id());
+    assert_eq!(root_inode.find("..").unwrap().unwrap().inode_id(), root_inode.inode_id());
+    assert_eq!(dir_a.find("b/..").unwrap().unwrap().inode_id(), dir_a.inode_id());
+    assert!(root_inode.find("a/b/c/d").unwrap().is_none());
+    assert_eq!(root_inode.ls().unwrap(), vec!["a"]);
+    assert_eq!(dir_a.ls().unwrap(), vec!["b"]);
+    // only empty directories can be removed
+    assert!(!root_inode.rmdir("a").unwrap());
+    assert!(!root_inode.rmdir("a/b/c").unwrap());
+    assert!(root_inode.create_dir("a/d").unwrap().is_some());
+    assert!(root_inode.rmdir("a/d").unwrap());
+    assert!(root_inode.find("a/d").unwrap().is_none());
+    assert_eq!(dir_a.ls().unwrap(), vec!["b"]);
+    // the freed directory entry is reused
+    root_inode.create("a/e").unwrap().unwrap();
+    assert_eq!(dir_a.ls().unwrap(), vec!["b", "e"]);
+}
+
+#[test]
+fn efs_link_test() {
+    let efs = mem_fs(4096, BlockMapping::Indirect);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let filea = root_inode.create("filea").unwrap().unwrap();
+    filea.write_at(0, b"linked").unwrap();
+    assert_eq!(filea.nlink().unwrap(), 1);
+    root_inode.create_dir("dir").unwrap().unwrap();
+    assert!(root_inode.link("filea", "dir/fileb").unwrap());
+    assert_eq!(filea.nlink().unwrap(), 2);
+    // a name can not be linked twice and directories can not be linked
+    assert!(!root_inode.link("filea", "dir/fileb").unwrap());
+    assert!(!root_inode.link("dir", "dir2").unwrap());
+    let fileb = root_inode.find("dir/fileb").unwrap().unwrap();
+    assert_eq!(fileb.inode_id(), filea.inode_id());
+    let mut buffer = [0u8; 16];
+    let len = fileb.read_at(0, &mut buffer).unwrap();
+    assert_eq!(&buffer[..len], b"linked");
+    // the data survives until the last link is removed
+    assert!(root_inode.unlink("filea").unwrap());
+    assert!(root_inode.find("filea").unwrap().is_none());
+    assert_eq!(fileb.nlink().unwrap(), 1);
+    assert_eq!(fileb.read_at(0, &mut buffer).unwrap(), len);
+    assert!(!root_inode.unlink("dir").unwrap());
+    assert!(root_inode.unlink("dir/fileb").unwrap());
+    assert!(!root_inode.unlink("dir/fileb").unwrap());
+    // the released inode is allocated again
+    let inode_id = fileb.inode_id();
+    drop((filea, fileb));
+    let filec = root_inode.create("filec").unwrap().unwrap();
+    assert_eq!(filec.inode_id(), inode_id);
+    assert_eq!(filec.read_at(0, &mut buffer).unwrap(), 0);
+}
+
+#[test]
+fn efs_unlink_open_test() {
+    let efs = mem_fs(4096, BlockMapping::Indirect);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let stat = root_inode.fs_stat().unwrap();
+    let data = [b'o'; 3 * BLOCK_SZ];
+    let file = root_inode.create("file").unwrap().unwrap();
+    file.write_at(0, &data).unwrap();
+    let dir = root_inode.create_dir("dir").unwrap().unwrap();
+    // a file removed while open keeps its data and inode until it is closed
+    assert!(root_inode.unlink("file").unwrap());
+    assert!(root_inode.find("file").unwrap().is_none());
+    assert_eq!(file.nlink().unwrap(), 0);
+    assert_eq!(read_file(&file).unwrap(), data);
+    assert_eq!(file.write_at(data.len(), b"more").unwrap(), 4);
+    assert!(!root_inode.link("dir", "file").unwrap());
+    assert!(root_inode.create("other").unwrap().unwrap().inode_id() != file.inode_id());
+    assert!(root_inode.unlink("other").unwrap());
+    // nothing can be added to a directory removed while open
+    assert!(root_inode.rmdir("dir").unwrap());
+    assert!(dir.create("file").unwrap().is_none());
+    assert!(dir.create_dir("dir").unwrap().is_none());
+    assert!(root_inode.find("dir").unwrap().is_none());
+    let report = EasyFileSystem::fsck(&efs, false).unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems);
+    assert!(root_inode.fs_stat().unwrap().free_inodes < stat.free_inodes);
+    drop((file, dir));
+    assert_eq!(root_inode.fs_stat().unwrap(), stat);
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+}
+
+#[test]
+fn efs_metadata_test() {
+    let efs = mem_fs(4096, BlockMapping::Indirect);
+    efs.set_clock(|| 1000);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let dir = root_inode.create_dir("dir").unwrap().unwrap();
+    let file = root_inode.create("dir/file").unwrap().unwrap();
+    let metadata = file.metadata().unwrap();
+    assert_eq!((metadata.mode, metadata.uid, metadata.gid), (0o644, 0, 0));
+    assert_eq!((metadata.atime, metadata.mtime, metadata.ctime), (1000, 1000, 1000));
+    assert_eq!(dir.metadata().unwrap().mode, 0o755);
+    assert_eq!(dir.metadata().unwrap().mtime, 1000);
+    // writing stamps the modification time, reading the access time
+    efs.set_clock(|| 2000);
+    file.write_at(0, b"metadata").unwrap();
+    let metadata = file.metadata().unwrap();
+    assert_eq!((metadata.size, metadata.mtime, metadata.ctime), (8, 2000, 2000));
+    assert_eq!(metadata.atime, 1000);
+    efs.set_clock(|| 3000);
+    let mut buffer = [0u8; 8];
+    file.read_at(0, &mut buffer).unwrap();
+    assert_eq!(file.metadata().unwrap().atime, 3000);
+    // permission bits, ownership and times are set as they are given
+    file.set_mode(0o100755).unwrap();
+    file.set_owner(1000, 100).unwrap();
+    file.set_times(10, 20).unwrap();
+    let metadata = file.metadata().unwrap();
+    assert_eq!((metadata.mode, metadata.uid, metadata.gid), (0o755, 1000, 100));
+    assert_eq!((metadata.atime, metadata.mtime, metadata.ctime), (10, 20, 3000));
+    // linking changes the directory
+    assert!(root_inode.link("dir/file", "file").unwrap());
+    assert_eq!(root_inode.metadata().unwrap().mtime, 3000);
+    assert_eq!(dir.metadata().unwrap().mtime, 1000);
+}
+
+#[test]
+fn efs_long_name_test() {
+    let efs = mem_fs(4096, BlockMapping::Indirect);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    // names longer than a slot take extension slots
+    let long_name = "ch8b_race_adder_mutex_spin_with_a_longer_name";
+    let file = root_inode.create(long_name).unwrap().unwrap();
+    file.write_at(0, b"long").unwrap();
+    root_inode.create("short").unwrap().unwrap();
+    assert_eq!(root_inode.ls().unwrap(), vec![long_name, "short"]);
+    let mut buffer = [0u8; 4];
+    root_inode.find(long_name).unwrap().unwrap().read_at(0, &mut buffer).unwrap();
+    assert_eq!(&buffer, b"long");
+    assert!(root_inode.find("ch8b_race_adder_mutex_spin").unwrap().is_none());
+    // the slots of a removed entry are reused
+    let dir_size = root_inode.metadata().unwrap().size;
+    assert!(root_inode.unlink(long_name).unwrap());
+    assert!(root_inode.find(long_name).unwrap().is_none());
+    root_inode.create("ch8b_race_adder_mutex_spin").unwrap().unwrap();
+    assert_eq!(root_inode.metadata().unwrap().size, dir_size);
+    assert_eq!(root_inode.ls().unwrap(), vec!["ch8b_race_adder_mutex_spin", "short"]);
+    // names are limited to NAME_LENGTH_LIMIT bytes
+    let max_name = "n".repeat(NAME_LENGTH_LIMIT);
+    root_inode.create(&max_name).unwrap().unwrap();
+    assert!(root_inode.find(&max_name).unwrap().is_some());
+    assert!(root_inode.create(&"n".repeat(NAME_LENGTH_LIMIT + 1)).unwrap().is_none());
+    assert!(root_inode.create("a/b").unwrap().is_none());
+    assert_eq!(DirEntry::new("", 1).err(), Some(DirEntryError::EmptyName));
+    assert_eq!(DirEntry::new("a\0b", 1).err(), Some(DirEntryError::InvalidName));
+    assert_eq!(
+        DirEntry::new(&max_name, 1).map(|dirent| dirent.name().len()),
+        Ok(NAME_LENGTH_LIMIT)
+    );
+    assert_eq!(
+        DirEntry::new(&"n".repeat(NAME_LENGTH_LIMIT + 1), 1).err(),
+        Some(DirEntryError::NameTooLong)
+    );
+    // everything survives a reopen
+    drop((file, root_inode));
+    let block_device = Arc::clone(&efs.block_device);
+    drop(efs);
+    let efs = EasyFileSystem::open(block_device).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert!(root_inode.find(&max_name).unwrap().is_some());
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+}
+
+#[test]
+fn efs_truncate_test() {
+    let efs = mem_fs(4096, BlockMapping::Indirect);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    // data spanning direct, indirect1 and indirect2 blocks
+    let file = root_inode.create("file").unwrap().unwrap();
+    let data: Vec<u8> = (0..200 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    file.write_at(0, &data).unwrap();
+    // shrink into the middle of a block, the rest of which reads as zero once grown
+    let new_size = 20 * BLOCK_SZ + 100;
+    file.truncate(new_size as u32).unwrap();
+    assert_eq!(file.metadata().unwrap().size as usize, new_size);
+    let mut buffer = vec![0xffu8; 200 * BLOCK_SZ];
+    assert_eq!(file.read_at(0, &mut buffer).unwrap(), new_size);
+    assert_eq!(&buffer[..new_size], &data[..new_size]);
+    file.truncate((150 * BLOCK_SZ) as u32).unwrap();
+    assert_eq!(file.read_at(0, &mut buffer).unwrap(), 150 * BLOCK_SZ);
+    assert_eq!(&buffer[..new_size], &data[..new_size]);
+    assert!(buffer[new_size..150 * BLOCK_SZ].iter().all(|b| *b == 0));
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+    // a write far beyond the end of file leaves a hole without allocating it,
+    // which would not fit in the filesystem otherwise
+    let sparse = root_inode.create("sparse").unwrap().unwrap();
+    let offset = 8_000_000;
+    assert_eq!(sparse.write_at(offset, b"end").unwrap(), 3);
+    assert_eq!(sparse.metadata().unwrap().size as usize, offset + 3);
+    let mut buffer = [0xffu8; 2 * BLOCK_SZ];
+    assert_eq!(sparse.read_at(offset / 2, &mut buffer).unwrap(), 2 * BLOCK_SZ);
+    assert!(buffer.iter().all(|b| *b == 0));
+    assert_eq!(sparse.read_at(offset - 2, &mut buffer).unwrap(), 5);
+    assert_eq!(&buffer[..5], b"\0\0end");
+    // filling a block of the hole leaves the rest of it alone
+    sparse.write_at(offset / 2, b"middle").unwrap();
+    assert_eq!(sparse.read_at(offset / 2 - 1, &mut buffer[..8]).unwrap(), 8);
+    assert_eq!(&buffer[..8], b"\0middle\0");
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+    // every block is released, which fsck would report as leaked otherwise
+    file.truncate(0).unwrap();
+    sparse.truncate(0).unwrap();
+    drop((file, sparse));
+    assert!(root_inode.unlink("file").unwrap());
+    assert!(root_inode.unlink("sparse").unwrap());
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+}
+
+#[test]
+fn efs_stat_test() {
+    let efs = mem_fs(1200, BlockMapping::Indirect);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let stat = root_inode.fs_stat().unwrap();
+    assert_eq!(stat, efs.stat().unwrap());
+    assert_eq!((stat.block_size, stat.total_blocks), (BLOCK_SZ as u32, 1200));
+    assert_eq!((stat.inodes, stat.free_inodes), (4096, 4095));
+    // the root directory holds a block
+    assert_eq!(stat.free_blocks, stat.data_blocks - 1);
+    assert_eq!(stat.name_length_limit as usize, NAME_LENGTH_LIMIT);
+    let file = root_inode.create("file").unwrap().unwrap();
+    file.write_at(0, &[1u8; 3 * BLOCK_SZ]).unwrap();
+    let after_write = root_inode.fs_stat().unwrap();
+    assert_eq!(after_write.free_inodes, stat.free_inodes - 1);
+    assert_eq!(after_write.free_blocks, stat.free_blocks - 3);
+    // a write filling the disk falls short
+    let free = after_write.free_blocks as usize;
+    let data = vec![2u8; (free + 10) * BLOCK_SZ];
+    let written = file.write_at(3 * BLOCK_SZ, &data).unwrap();
+    assert!(written < data.len());
+    assert_eq!(written % BLOCK_SZ, 0);
+    assert_eq!(file.metadata().unwrap().size as usize, 3 * BLOCK_SZ + written);
+    assert_eq!(root_inode.fs_stat().unwrap().free_blocks, 0);
+    assert_eq!(file.write_at(file.metadata().unwrap().size as usize, b"full").unwrap(), 0);
+    // creation fails once the directory cannot grow past its block of 32-byte slots,
+    // without leaking the inode
+    let free_inodes = root_inode.fs_stat().unwrap().free_inodes;
+    let slots = BLOCK_SZ / 32;
+    let created = (0..slots)
+        .take_while(|i| root_inode.create(&format!("f{}", i)).unwrap().is_some())
+        .count();
+    assert!(created < slots);
+    assert!(root_inode.create_dir("dir").unwrap().is_none());
+    assert!(!root_inode.link("file", "link").unwrap());
+    assert_eq!(root_inode.fs_stat().unwrap().free_inodes, free_inodes - created as u32);
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+    // removing the file releases its blocks
+    drop(file);
+    assert!(root_inode.unlink("file").unwrap());
+    assert_eq!(root_inode.fs_stat().unwrap().free_blocks as usize, free + 3);
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+}
+
+#[test]
+fn efs_extent_test() {
+    let efs = mem_fs(32768, BlockMapping::Extents);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let free_blocks = root_inode.fs_stat().unwrap().free_blocks;
+    // a file past the max size of indirect blocks is stored in a single extent
+    let big = root_inode.create("big").unwrap().unwrap();
+    let data: Vec<u8> = (0..MAX_FILE_SIZE + 100 * BLOCK_SZ).map(|i| (i * 7 / 5) as u8).collect();
+    assert_eq!(big.write_at(0, &data).unwrap(), data.len());
+    assert_eq!(read_file(&big).unwrap(), data);
+    let used = free_blocks - root_inode.fs_stat().unwrap().free_blocks;
+    assert_eq!(used as usize, data.len() / BLOCK_SZ);
+    // files growing in turn are fragmented into more extents than an inode holds
+    let files: Vec<_> = (0..2)
+        .map(|i| root_inode.create(&format!("file{}", i)).unwrap().unwrap())
+        .collect();
+    let chunk = vec![0x5au8; BLOCK_SZ];
+    for i in 0..200 {
+        for file in files.iter() {
+            assert_eq!(file.write_at(i * BLOCK_SZ, &chunk).unwrap(), BLOCK_SZ);
+        }
+    }
+    // a hole splits an extent
+    files[0].write_at(300 * BLOCK_SZ, &chunk).unwrap();
+    files[0].truncate((250 * BLOCK_SZ) as u32).unwrap();
+    for file in files.iter() {
+        assert!(read_file(file).unwrap()[..200 * BLOCK_SZ].iter().all(|byte| *byte == 0x5a));
+    }
+    let report = EasyFileSystem::fsck(&efs, false).unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems);
+    // all blocks are released along with the extent blocks
+    drop((big, files));
+    for name in ["big", "file0", "file1"].iter() {
+        assert!(root_inode.unlink(name).unwrap());
+    }
+    assert_eq!(root_inode.fs_stat().unwrap().free_blocks, free_blocks);
+}
+
+#[test]
+fn efs_symlink_test() {
+    let efs = mem_fs(4096, BlockMapping::Indirect);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let free_blocks = root_inode.fs_stat().unwrap().free_blocks;
+    root_inode.create_dir("bin").unwrap().unwrap();
+    root_inode.create("bin/app-1.2").unwrap().unwrap().write_at(0, b"\x7fELF").unwrap();
+    // the target is kept as it is, whether it exists or not
+    let alias = root_inode.symlink("app-1.2", "bin/app").unwrap().unwrap();
+    let metadata = alias.metadata().unwrap();
+    assert!(metadata.is_symlink && !metadata.is_dir);
+    assert_eq!(metadata.size, 7);
+    assert_eq!(alias.readlink().unwrap(), Some(String::from("app-1.2")));
+    let dangling = root_inode.symlink("/missing/file", "dangling").unwrap().unwrap();
+    assert_eq!(dangling.readlink().unwrap(), Some(String::from("/missing/file")));
+    assert_eq!(root_inode.find("bin/app-1.2").unwrap().unwrap().readlink().unwrap(), None);
+    // an empty, too long or taken path is refused
+    assert!(root_inode.symlink("", "empty").unwrap().is_none());
+    assert!(root_inode.symlink(&"a".repeat(SYMLINK_MAX_LEN + 1), "long").unwrap().is_none());
+    assert!(root_inode.symlink("app-1.2", "bin/app").unwrap().is_none());
+    let long_target = "b".repeat(SYMLINK_MAX_LEN);
+    let long = root_inode.symlink(&long_target, "long").unwrap().unwrap();
+    assert_eq!(long.readlink().unwrap(), Some(long_target));
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+    // removing links releases their blocks, and leaves their targets alone
+    drop((alias, dangling, long));
+    for path in ["bin/app", "dangling", "long"].iter() {
+        assert!(root_inode.unlink(path).unwrap());
+    }
+    assert!(root_inode.find("bin/app-1.2").unwrap().is_some());
+    assert!(root_inode.unlink("bin/app-1.2").unwrap());
+    assert!(root_inode.rmdir("bin").unwrap());
+    assert_eq!(root_inode.fs_stat().unwrap().free_blocks, free_blocks);
+}
+
+#[test]
+fn efs_xattr_test() {
+    let efs = mem_fs(4096, BlockMapping::Indirect);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let free_blocks = root_inode.fs_stat().unwrap().free_blocks;
+    let file = root_inode.create("test").unwrap().unwrap();
+    assert_eq!(file.list_xattr().unwrap(), Vec::<String>::new());
+    assert!(file.set_xattr("exit_code", b"0").unwrap());
+    assert!(file.set_xattr("category", b"fs").unwrap());
+    // the attributes take a block of their own
+    assert_eq!(root_inode.fs_stat().unwrap().free_blocks, free_blocks - 1);
+    assert!(file.set_xattr("exit_code", b"-1").unwrap());
+    assert_eq!(file.get_xattr("exit_code").unwrap(), Some(b"-1".to_vec()));
+    assert_eq!(file.get_xattr("missing").unwrap(), None);
+    assert_eq!(file.list_xattr().unwrap(), vec!["exit_code", "category"]);
+    // the attributes of an inode have to fit in a block
+    assert!(!file.set_xattr("", b"empty").unwrap());
+    assert!(!file.set_xattr(&"n".repeat(XATTR_NAME_MAX + 1), b"long").unwrap());
+    assert!(!file.set_xattr("big", &[0u8; BLOCK_SZ]).unwrap());
+    assert!(file.set_xattr("big", &[7u8; 400]).unwrap());
+    assert_eq!(file.get_xattr("big").unwrap(), Some(vec![7u8; 400]));
+    assert!(EasyFileSystem::fsck(&efs, false).unwrap().is_clean());
+    // the block is released along with the last attribute
+    for name in ["exit_code", "category", "big"].iter() {
+        assert!(file.remove_xattr(name).unwrap());
+    }
+    assert!(!file.remove_xattr("big").unwrap());
+    assert_eq!(root_inode.fs_stat().unwrap().free_blocks, free_blocks);
+    // and along with the inode
+    assert!(file.set_xattr("category", b"fs").unwrap());
+    drop(file);
+    assert!(root_inode.unlink("test").unwrap());
+    assert_eq!(root_inode.fs_stat().unwrap().free_blocks, free_blocks);
+}
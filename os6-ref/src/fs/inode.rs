@@ -41,27 +41,28 @@ impl OSInode {
             })},
         }
     }
-    /// Read all data inside a inode into vector
-    pub fn read_all(&self) -> Vec<u8> {
+    /// Read all data inside a inode into vector,
+    /// return None if an I/O error happens
+    pub fn read_all(&self) -> Option<Vec<u8>> {
         let mut inner = self.inner.exclusive_access();
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
+            let len = inner.inode.read_at(inner.offset, &mut buffer).ok()?;
             if len == 0 {
                 break;
             }
             inner.offset += len;
             v.extend_from_slice(&buffer[..len]);
         }
-        v
+        Some(v)
     }
 }
 
 lazy_static! {
     /// The root of all inodes, or '/' in short
     pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
+        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone()).expect("Error when opening easy-fs!");
         Arc::new(EasyFileSystem::root_inode(&efs))
     };
 }
@@ -69,7 +70,7 @@ lazy_static! {
 /// List all files in the filesystems
 pub fn list_apps() {
     println!("/**** APPS ****");
-    for app in ROOT_INODE.ls() {
+    for app in ROOT_INODE.ls().expect("Error when listing apps!") {
         println!("{}", app);
     }
     println!("**************/");
@@ -105,9 +106,9 @@ impl OpenFlags {
 pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
     if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
+        if let Some(inode) = ROOT_INODE.find(name).ok()? {
             // clear size
-            inode.clear();
+            inode.clear().ok()?;
             Some(Arc::new(OSInode::new(
                 readable,
                 writable,
@@ -115,7 +116,7 @@ pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
             )))
         } else {
             // create file
-            ROOT_INODE.create(name)
+            ROOT_INODE.create(name).ok()?
                 .map(|inode| {
                     Arc::new(OSInode::new(
                         readable,
@@ -125,16 +126,16 @@ pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
                 })
         }
     } else {
-        ROOT_INODE.find(name)
-            .map(|inode| {
+        ROOT_INODE.find(name).ok()?
+            .and_then(|inode| {
                 if flags.contains(OpenFlags::TRUNC) {
-                    inode.clear();
+                    inode.clear().ok()?;
                 }
-                Arc::new(OSInode::new(
+                Some(Arc::new(OSInode::new(
                     readable,
                     writable,
                     inode
-                ))
+                )))
             })
     }
 }
@@ -142,28 +143,40 @@ pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
 impl File for OSInode {
     fn readable(&self) -> bool { self.readable }
     fn writable(&self) -> bool { self.writable }
-    fn read(&self, mut buf: UserBuffer) -> usize {
+    fn read(&self, mut buf: UserBuffer) -> Option<usize> {
         let mut inner = self.inner.exclusive_access();
         let mut total_read_size = 0usize;
         for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
+            let read_size = match inner.inode.read_at(inner.offset, *slice) {
+                Ok(read_size) => read_size,
+                // report the data read so far, the error shows up on the next read
+                Err(_) if total_read_size > 0 => break,
+                Err(_) => return None,
+            };
             if read_size == 0 {
                 break;
             }
             inner.offset += read_size;
             total_read_size += read_size;
         }
-        total_read_size
+        Some(total_read_size)
     }
-    fn write(&self, buf: UserBuffer) -> usize {
+    fn write(&self, buf: UserBuffer) -> Option<usize> {
         let mut inner = self.inner.exclusive_access();
         let mut total_write_size = 0usize;
         for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
+            let write_size = match inner.inode.write_at(inner.offset, *slice) {
+                Ok(write_size) => write_size,
+                Err(_) if total_write_size > 0 => break,
+                Err(_) => return None,
+            };
             inner.offset += write_size;
             total_write_size += write_size;
+            // the disk is full or an I/O error happened partway
+            if write_size < slice.len() {
+                break;
+            }
         }
-        total_write_size
+        Some(total_write_size)
     }
 }
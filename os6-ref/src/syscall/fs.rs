@@ -22,9 +22,12 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
         let file = file.clone();
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.write(
-            UserBuffer::new(translated_byte_buffer(token, buf, len))
-        ) as isize
+        match file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) {
+            // nothing can be written once the disk is full
+            Some(0) if len > 0 => -1,
+            Some(write_size) => write_size as isize,
+            None => -1,
+        }
     } else {
         -1
     }
@@ -41,9 +44,10 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
         let file = file.clone();
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.read(
-            UserBuffer::new(translated_byte_buffer(token, buf, len))
-        ) as isize
+        match file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) {
+            Some(read_size) => read_size as isize,
+            None => -1,
+        }
     } else {
         -1
     }
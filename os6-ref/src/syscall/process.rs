@@ -61,8 +61,8 @@ pub fn sys_fork() -> isize {
 pub fn sys_exec(path: *const u8) -> isize {
     let token = current_user_token();
     let path = translated_str(token, path);
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        let all_data = app_inode.read_all();
+    if let Some(all_data) = open_file(path.as_str(), OpenFlags::RDONLY)
+        .and_then(|app_inode| app_inode.read_all()) {
         let task = current_task().unwrap();
         task.exec(all_data.as_slice());
         0
@@ -96,7 +96,7 @@ lazy_static! {
     /// but we have user_shell, so we don't need to change it.
     pub static ref INITPROC: Arc<TaskControlBlock> = Arc::new({
         let inode = open_file("ch6b_initproc", OpenFlags::RDONLY).unwrap();
-        let v = inode.read_all();
+        let v = inode.read_all().unwrap();
         TaskControlBlock::new(v.as_slice())
     });
 }
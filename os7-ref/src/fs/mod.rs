@@ -8,8 +8,12 @@ use crate::mm::UserBuffer;
 pub trait File : Send + Sync {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
-    fn read(&self, buf: UserBuffer) -> usize;
-    fn write(&self, buf: UserBuffer) -> usize;
+    /// Read into `buf`, return the number of bytes read,
+    /// or None if an I/O error happens before anything is read
+    fn read(&self, buf: UserBuffer) -> Option<usize>;
+    /// Write from `buf`, return the number of bytes written,
+    /// or None if an I/O error happens before anything is written
+    fn write(&self, buf: UserBuffer) -> Option<usize>;
 }
 
 /// The stat of a inode
@@ -11,7 +11,7 @@ pub struct Stdout;
 impl File for Stdin {
     fn readable(&self) -> bool { true }
     fn writable(&self) -> bool { false }
-    fn read(&self, mut user_buf: UserBuffer) -> usize {
+    fn read(&self, mut user_buf: UserBuffer) -> Option<usize> {
         assert_eq!(user_buf.len(), 1);
         // busy loop
         let mut c: usize;
@@ -26,9 +26,9 @@ impl File for Stdin {
         }
         let ch = c as u8;
         unsafe { user_buf.buffers[0].as_mut_ptr().write_volatile(ch); }
-        1
+        Some(1)
     }
-    fn write(&self, _user_buf: UserBuffer) -> usize {
+    fn write(&self, _user_buf: UserBuffer) -> Option<usize> {
         panic!("Cannot write to stdin!");
     }
 }
@@ -36,13 +36,13 @@ impl File for Stdin {
 impl File for Stdout {
     fn readable(&self) -> bool { false }
     fn writable(&self) -> bool { true }
-    fn read(&self, _user_buf: UserBuffer) -> usize{
+    fn read(&self, _user_buf: UserBuffer) -> Option<usize> {
         panic!("Cannot read from stdout!");
     }
-    fn write(&self, user_buf: UserBuffer) -> usize {
+    fn write(&self, user_buf: UserBuffer) -> Option<usize> {
         for buffer in user_buf.buffers.iter() {
             print!("{}", core::str::from_utf8(*buffer).unwrap());
         }
-        user_buf.len()
+        Some(user_buf.len())
     }
 }
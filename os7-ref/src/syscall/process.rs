@@ -72,8 +72,8 @@ pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
             args = args.add(1);
         }
     }
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        let all_data = app_inode.read_all();
+    if let Some(all_data) = open_file(path.as_str(), OpenFlags::RDONLY)
+        .and_then(|app_inode| app_inode.read_all()) {
         let task = current_task().unwrap();
         let argc = args_vec.len();
         task.exec(all_data.as_slice(), args_vec);
@@ -2,12 +2,18 @@
 
 pub const USER_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 20;
-pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
+pub const KERNEL_HEAP_SIZE: usize = 0x100_0000;
 pub const MEMORY_END: usize = 0x88000000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 pub const MAX_SYSCALL_NUM: usize = 500;
+/// size of the swap file preallocated at boot, at most the max size of a file mapped by extents
+pub const SWAP_SIZE: usize = 32 * 1024 * 1024;
 
+/// end of the lower half of the SV39 address space, which user mappings stay below
+pub const USER_SPACE_END: usize = 1 << 38;
+/// bottom of the user heap, far above the elf sections and the user stacks
+pub const USER_HEAP_BASE: usize = 0x10_0000_0000;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 pub const CLOCK_FREQ: usize = 12500000;
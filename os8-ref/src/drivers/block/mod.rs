@@ -16,8 +16,8 @@ pub fn block_device_test() {
     let mut read_buffer = [0u8; 512];
     for i in 0..512 {
         for byte in write_buffer.iter_mut() { *byte = i as u8; }
-        block_device.write_block(i as usize, &write_buffer);
-        block_device.read_block(i as usize, &mut read_buffer);
+        block_device.write_block(i as usize, &write_buffer).unwrap();
+        block_device.read_block(i as usize, &mut read_buffer).unwrap();
         assert_eq!(write_buffer, read_buffer);
     }
     println!("block device test passed!");
@@ -7,9 +7,13 @@ use crate::sync::UPSafeCell;
 use alloc::sync::Arc;
 use lazy_static::*;
 use bitflags::*;
+use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
-use super::File;
+use super::{File, Stat, StatFs, StatMode};
 use crate::mm::UserBuffer;
+use crate::config::PAGE_SIZE;
+use crate::timer::get_time_ms;
 
 /// A wrapper around a filesystem inode
 /// to implement File trait atop
@@ -41,27 +45,30 @@ impl OSInode {
             })},
         }
     }
-    /// Read all data inside a inode into vector
-    pub fn read_all(&self) -> Vec<u8> {
+    /// Read all data inside a inode into vector,
+    /// return None if an I/O error happens
+    pub fn read_all(&self) -> Option<Vec<u8>> {
         let mut inner = self.inner.exclusive_access();
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
+            let len = inner.inode.read_at(inner.offset, &mut buffer).ok()?;
             if len == 0 {
                 break;
             }
             inner.offset += len;
             v.extend_from_slice(&buffer[..len]);
         }
-        v
+        Some(v)
     }
 }
 
 lazy_static! {
     /// The root of all inodes, or '/' in short
     pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
+        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone()).expect("Error when opening easy-fs!");
+        // there is no real-time clock, so inodes are stamped with the time since boot
+        efs.set_clock(|| (get_time_ms() / 1000) as u64);
         Arc::new(EasyFileSystem::root_inode(&efs))
     };
 }
@@ -69,7 +76,11 @@ lazy_static! {
 /// List all files in the filesystems
 pub fn list_apps() {
     println!("/**** APPS ****");
-    for app in ROOT_INODE.ls() {
+    // hidden files such as the swap file are no apps
+    for app in ROOT_INODE.ls().expect("Error when listing apps!") {
+        if app.starts_with('.') {
+            continue;
+        }
         println!("{}", app);
     }
     println!("**************/");
@@ -101,13 +112,61 @@ impl OpenFlags {
     }
 }
 
-/// Open a file by path
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+/// The max number of symbolic links followed to resolve a path
+const MAX_SYMLINK_FOLLOWS: usize = 8;
+
+/// Resolve the symbolic links along a path, including its last component,
+/// into a path without any. A missing component is left as it is, so that
+/// a file can still be created at the resolved path.
+/// Return None if too many links are followed, or an I/O error happens.
+fn resolve_path(path: &str) -> Option<String> {
+    // the path resolved so far, and the components left, last one first
+    let mut resolved = String::new();
+    let mut pending: Vec<String> = path
+        .split('/')
+        .filter(|name| !name.is_empty())
+        .rev()
+        .map(String::from)
+        .collect();
+    let mut follows = 0;
+    while let Some(name) = pending.pop() {
+        let child = format!("{}/{}", resolved, name);
+        let target = match ROOT_INODE.find(&child).ok()? {
+            Some(inode) => inode.readlink().ok()?,
+            None => None,
+        };
+        match target {
+            Some(target) => {
+                follows += 1;
+                if follows > MAX_SYMLINK_FOLLOWS {
+                    return None;
+                }
+                // a relative target is relative to the directory holding the link
+                if target.starts_with('/') {
+                    resolved.clear();
+                }
+                pending.extend(target.split('/').filter(|name| !name.is_empty()).rev().map(String::from));
+            }
+            None => resolved = child,
+        }
+    }
+    Some(resolved)
+}
+
+/// Open a file by path, following symbolic links,
+/// an I/O error failing the open like a missing file does
+pub fn open_file(path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
+    let path = resolve_path(path)?;
+    let path = path.as_str();
     if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
+        if let Some(inode) = ROOT_INODE.find(path).ok()? {
+            // a directory can never be truncated
+            if inode.is_dir().ok()? {
+                return None;
+            }
             // clear size
-            inode.clear();
+            inode.clear().ok()?;
             Some(Arc::new(OSInode::new(
                 readable,
                 writable,
@@ -115,7 +174,7 @@ pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
             )))
         } else {
             // create file
-            ROOT_INODE.create(name)
+            ROOT_INODE.create(path).ok()?
                 .map(|inode| {
                     Arc::new(OSInode::new(
                         readable,
@@ -125,45 +184,156 @@ pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
                 })
         }
     } else {
-        ROOT_INODE.find(name)
-            .map(|inode| {
+        ROOT_INODE.find(path).ok()?
+            .and_then(|inode| {
+                // a directory can only be opened read-only
+                if inode.is_dir().ok()? && (writable || flags.contains(OpenFlags::TRUNC)) {
+                    return None;
+                }
                 if flags.contains(OpenFlags::TRUNC) {
-                    inode.clear();
+                    inode.clear().ok()?;
                 }
-                Arc::new(OSInode::new(
+                Some(Arc::new(OSInode::new(
                     readable,
                     writable,
                     inode
-                ))
+                )))
             })
     }
 }
 
+/// The file user pages are swapped out to, hidden from the list of apps
+const SWAP_FILE: &str = ".swap";
+
+/// Open the swap file, preallocating `size` bytes of it so that swapping out never needs
+/// more disk space. Only a missing file or the part it falls short of `size` is written,
+/// so the file left by an earlier boot is reused as is. Return it with the number of bytes
+/// preallocated, which falls short of `size` once the disk is full or the file reaches
+/// its max size.
+pub fn open_swap_file(size: usize) -> Option<(Arc<Inode>, usize)> {
+    let inode = match ROOT_INODE.find(SWAP_FILE).ok()? {
+        Some(inode) => inode,
+        None => ROOT_INODE.create(SWAP_FILE).ok()??,
+    };
+    // what an earlier boot left in it is of no use, but the blocks it holds are kept
+    let file_size = inode.metadata().ok()?.size as usize;
+    let mut len = file_size.min(size) / PAGE_SIZE * PAGE_SIZE;
+    if len < file_size {
+        inode.truncate(len as u32).ok()?;
+    }
+    let zeros = alloc::vec![0u8; PAGE_SIZE];
+    while len < size && inode.write_at(len, &zeros) == Ok(PAGE_SIZE) {
+        len += PAGE_SIZE;
+    }
+    Some((inode, len))
+}
+
+/// Create a directory by path
+pub fn make_dir(path: &str) -> bool {
+    matches!(ROOT_INODE.create_dir(path), Ok(Some(_)))
+}
+
+/// Remove an empty directory by path
+pub fn remove_dir(path: &str) -> bool {
+    ROOT_INODE.rmdir(path) == Ok(true)
+}
+
+/// Create a hard link at `new_path` to the file at `old_path`
+pub fn link_file(old_path: &str, new_path: &str) -> bool {
+    ROOT_INODE.link(old_path, new_path) == Ok(true)
+}
+
+/// Remove a hard link to a file by path
+pub fn unlink_file(path: &str) -> bool {
+    ROOT_INODE.unlink(path) == Ok(true)
+}
+
+/// Get the usage statistics of the filesystem holding the file at `path`
+pub fn stat_fs(path: &str) -> Option<StatFs> {
+    let inode = ROOT_INODE.find(path).ok()??;
+    inode.fs_stat().ok().map(|stat| StatFs::new(&stat))
+}
+
+/// Get the value of an extended attribute of the file at `path`, following symbolic links
+pub fn get_xattr(path: &str, name: &str) -> Option<Vec<u8>> {
+    let inode = ROOT_INODE.find(&resolve_path(path)?).ok()??;
+    inode.get_xattr(name).ok()?
+}
+
+/// Set an extended attribute of the file at `path`, following symbolic links
+pub fn set_xattr(path: &str, name: &str, value: &[u8]) -> bool {
+    match resolve_path(path).map(|path| ROOT_INODE.find(&path)) {
+        Some(Ok(Some(inode))) => inode.set_xattr(name, value) == Ok(true),
+        _ => false,
+    }
+}
+
+/// List the names of the extended attributes of the file at `path`, following symbolic links
+pub fn list_xattr(path: &str) -> Option<Vec<String>> {
+    let inode = ROOT_INODE.find(&resolve_path(path)?).ok()??;
+    inode.list_xattr().ok()
+}
+
+/// Remove an extended attribute of the file at `path`, following symbolic links
+pub fn remove_xattr(path: &str, name: &str) -> bool {
+    match resolve_path(path).map(|path| ROOT_INODE.find(&path)) {
+        Some(Ok(Some(inode))) => inode.remove_xattr(name) == Ok(true),
+        _ => false,
+    }
+}
+
 impl File for OSInode {
     fn readable(&self) -> bool { self.readable }
     fn writable(&self) -> bool { self.writable }
-    fn read(&self, mut buf: UserBuffer) -> usize {
+    fn read(&self, mut buf: UserBuffer) -> Option<usize> {
         let mut inner = self.inner.exclusive_access();
         let mut total_read_size = 0usize;
         for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
+            let read_size = match inner.inode.read_at(inner.offset, *slice) {
+                Ok(read_size) => read_size,
+                // report the data read so far, the error shows up on the next read
+                Err(_) if total_read_size > 0 => break,
+                Err(_) => return None,
+            };
             if read_size == 0 {
                 break;
             }
             inner.offset += read_size;
             total_read_size += read_size;
         }
-        total_read_size
+        Some(total_read_size)
     }
-    fn write(&self, buf: UserBuffer) -> usize {
+    fn write(&self, buf: UserBuffer) -> Option<usize> {
         let mut inner = self.inner.exclusive_access();
         let mut total_write_size = 0usize;
         for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
+            let write_size = match inner.inode.write_at(inner.offset, *slice) {
+                Ok(write_size) => write_size,
+                Err(_) if total_write_size > 0 => break,
+                Err(_) => return None,
+            };
             inner.offset += write_size;
             total_write_size += write_size;
+            // the disk is full or an I/O error happened partway
+            if write_size < slice.len() {
+                break;
+            }
         }
-        total_write_size
+        Some(total_write_size)
+    }
+    fn stat(&self) -> Option<Stat> {
+        let inner = self.inner.exclusive_access();
+        let metadata = inner.inode.metadata().ok()?;
+        let mode = if metadata.is_dir {
+            StatMode::DIR
+        } else if metadata.is_symlink {
+            StatMode::LINK
+        } else {
+            StatMode::FILE
+        };
+        Some(Stat::new(mode, &metadata))
+    }
+    fn inode(&self) -> Option<Arc<Inode>> {
+        Some(self.inner.exclusive_access().inode.clone())
     }
 }
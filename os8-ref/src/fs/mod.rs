@@ -3,13 +3,27 @@ mod inode;
 mod pipe;
 
 use crate::mm::UserBuffer;
+use alloc::sync::Arc;
+use easy_fs::{FsStat, Inode, Metadata};
 
 /// The common abstraction of all IO resources
 pub trait File : Send + Sync {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
-    fn read(&self, buf: UserBuffer) -> usize;
-    fn write(&self, buf: UserBuffer) -> usize;
+    /// Read into `buf`, return the number of bytes read,
+    /// or None if an I/O error happens before anything is read
+    fn read(&self, buf: UserBuffer) -> Option<usize>;
+    /// Write from `buf`, return the number of bytes written,
+    /// or None if an I/O error happens before anything is written
+    fn write(&self, buf: UserBuffer) -> Option<usize>;
+    /// Get the stat of the underlying inode, if there is one
+    fn stat(&self) -> Option<Stat> {
+        None
+    }
+    /// Get the underlying inode to map into memory, if there is one
+    fn inode(&self) -> Option<Arc<Inode>> {
+        None
+    }
 }
 
 /// The stat of a inode
@@ -24,8 +38,74 @@ pub struct Stat {
     pub mode: StatMode,
     /// number of hard links
     pub nlink: u32,
+    /// size in bytes
+    pub size: u64,
+    /// time of last access, in seconds
+    pub atime: u64,
+    /// time of last modification, in seconds
+    pub mtime: u64,
+    /// time of last status change, in seconds
+    pub ctime: u64,
+    /// permission bits
+    pub perm: u32,
+    /// user ID of owner
+    pub uid: u32,
+    /// group ID of owner
+    pub gid: u32,
     /// unused pad
-    pad: [u64; 7],
+    pad: [u32; 3],
+}
+
+impl Stat {
+    /// Construct the stat of an inode on the root device
+    pub fn new(mode: StatMode, metadata: &Metadata) -> Self {
+        Self {
+            dev: 0,
+            ino: metadata.inode_id as u64,
+            mode,
+            nlink: metadata.nlink,
+            size: metadata.size as u64,
+            atime: metadata.atime,
+            mtime: metadata.mtime,
+            ctime: metadata.ctime,
+            perm: metadata.mode,
+            uid: metadata.uid,
+            gid: metadata.gid,
+            pad: [0; 3],
+        }
+    }
+}
+
+/// The usage statistics of a filesystem
+#[repr(C)]
+#[derive(Debug)]
+pub struct StatFs {
+    /// size of a block in bytes
+    pub bsize: u64,
+    /// number of data blocks
+    pub blocks: u64,
+    /// number of free data blocks
+    pub bfree: u64,
+    /// number of inodes
+    pub files: u64,
+    /// number of free inodes
+    pub ffree: u64,
+    /// max length of file names
+    pub namelen: u64,
+}
+
+impl StatFs {
+    /// Construct the usage statistics from those of easy-fs
+    pub fn new(stat: &FsStat) -> Self {
+        Self {
+            bsize: stat.block_size as u64,
+            blocks: stat.data_blocks as u64,
+            bfree: stat.free_blocks as u64,
+            files: stat.inodes as u64,
+            ffree: stat.free_inodes as u64,
+            namelen: stat.name_length_limit as u64,
+        }
+    }
 }
 
 bitflags! {
@@ -37,9 +117,13 @@ bitflags! {
         const DIR   = 0o040000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// symbolic link
+        const LINK  = 0o120000;
     }
 }    
 
 pub use stdio::{Stdin, Stdout};
-pub use inode::{OSInode, open_file, OpenFlags, list_apps};
+pub use inode::{OSInode, open_file, OpenFlags, list_apps, make_dir, remove_dir, link_file, unlink_file, stat_fs};
+pub use inode::{get_xattr, set_xattr, list_xattr, remove_xattr};
+pub use inode::open_swap_file;
 pub use pipe::{Pipe, make_pipe};
@@ -71,6 +71,7 @@ pub fn rust_main() -> ! {
     // task::kernel_stackless_coroutine_test();
     // task::kernel_stackful_coroutine_test();
     fs::list_apps();
+    mm::init_swap();
     task::add_initproc();
     task::run_tasks();
     panic!("Unreachable in rust_main!");
@@ -1,14 +1,15 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
 
-use super::{frame_alloc, FrameTracker};
+use super::{frame_alloc, FrameTracker, UserPage};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE};
+use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, USER_HEAP_BASE, USER_SPACE_END};
 use crate::sync::UPSafeCell;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use easy_fs::Inode;
 use lazy_static::*;
 use riscv::register::satp;
 
@@ -40,6 +41,12 @@ pub fn kernel_token() -> usize {
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// user pages the kernel is accessing, by the address of the task they are pinned for
+    pinned: Vec<(usize, Arc<UserPage>)>,
+    /// start of the heap area, page-aligned
+    heap_bottom: usize,
+    /// program break, the end of the heap area
+    brk: usize,
 }
 
 impl MemorySet {
@@ -47,6 +54,9 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            pinned: Vec::new(),
+            heap_bottom: 0,
+            brk: 0,
         }
     }
     pub fn token(&self) -> usize {
@@ -64,6 +74,102 @@ impl MemorySet {
             None,
         );
     }
+    /// Map `[start_va, end_va)` with `permission` on demand, to the contents of `file`
+    /// if given or else to zeroed pages, `shared` with the spaces forked from this one
+    /// rather than copied on write. Return false if any page of it is mapped already.
+    pub fn mmap(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+        shared: bool,
+        file: Option<MappedFile>,
+    ) -> bool {
+        let (start_vpn, end_vpn) = (start_va.floor(), end_va.ceil());
+        if self.areas.iter().any(|area| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        }) {
+            return false;
+        }
+        let map_type = if file.is_some() { MapType::File } else { MapType::Framed };
+        let mut map_area = MapArea::new(start_va, end_va, map_type, permission | MapPermission::U);
+        map_area.shared = shared;
+        map_area.file = file;
+        self.push(map_area, None);
+        true
+    }
+    /// Unmap `[start_va, end_va)`, writing the pages of shared file areas back to the files.
+    /// The range has to be made of whole user areas other than the heap. Return false if it is not.
+    pub fn munmap(&mut self, start_va: VirtAddr, end_va: VirtAddr) -> bool {
+        let (start_vpn, end_vpn) = (start_va.floor(), end_va.ceil());
+        let heap_vpn = VirtAddr::from(self.heap_bottom).floor();
+        let mut covered = 0;
+        for area in self.areas.iter() {
+            let (area_start, area_end) = (area.vpn_range.get_start(), area.vpn_range.get_end());
+            if area_start >= end_vpn || start_vpn >= area_end {
+                continue;
+            }
+            let is_heap = area_start == heap_vpn && self.brk > self.heap_bottom;
+            if !area.is_lazy() || is_heap || area_start < start_vpn || area_end > end_vpn {
+                return false;
+            }
+            covered += area_end.0 - area_start.0;
+        }
+        if covered != end_vpn.0 - start_vpn.0 {
+            return false;
+        }
+        let page_table = &mut self.page_table;
+        self.areas.retain_mut(|area| {
+            if area.vpn_range.get_start() >= start_vpn && area.vpn_range.get_end() <= end_vpn {
+                area.unmap(page_table);
+                return false;
+            }
+            true
+        });
+        true
+    }
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+    /// Move the program break to `brk`, growing or shrinking the heap area. The pages
+    /// of the heap are populated on their first access. Return false if `brk` is below
+    /// the bottom of the heap, or the heap would grow into another area.
+    pub fn set_brk(&mut self, brk: usize) -> bool {
+        if brk < self.heap_bottom || brk > USER_SPACE_END {
+            return false;
+        }
+        let bottom_vpn = VirtAddr::from(self.heap_bottom).floor();
+        let old_end = VirtAddr::from(self.brk).ceil();
+        let new_end = VirtAddr::from(brk).ceil();
+        if new_end > old_end
+            && self.areas.iter().any(|area| {
+                area.vpn_range.get_start() < new_end && old_end < area.vpn_range.get_end()
+            })
+        {
+            return false;
+        }
+        // the heap area is left out while it is empty
+        let heap = self
+            .areas
+            .iter()
+            .position(|area| old_end > bottom_vpn && area.vpn_range.get_start() == bottom_vpn);
+        match heap {
+            Some(idx) if new_end == bottom_vpn => {
+                self.areas[idx].unmap(&mut self.page_table);
+                self.areas.remove(idx);
+            }
+            Some(idx) if new_end < old_end => self.areas[idx].shrink_to(&mut self.page_table, new_end),
+            Some(idx) => self.areas[idx].append_to(&mut self.page_table, new_end),
+            None if new_end > bottom_vpn => self.insert_framed_area(
+                self.heap_bottom.into(),
+                brk.into(),
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            ),
+            None => {}
+        }
+        self.brk = brk;
+        true
+    }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
             .areas
@@ -78,7 +184,12 @@ impl MemorySet {
     fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
         map_area.map(&mut self.page_table);
         if let Some(data) = data {
-            map_area.copy_data(&mut self.page_table, data);
+            if map_area.is_lazy() {
+                // kept until each page is first accessed
+                map_area.data = Some(Arc::from(data));
+            } else {
+                map_area.copy_data(&mut self.page_table, data);
+            }
         }
         self.areas.push(map_area);
     }
@@ -209,32 +320,160 @@ impl MemorySet {
         let max_end_va: VirtAddr = max_end_vpn.into();
         let mut user_stack_top: usize = max_end_va.into();
         user_stack_top += PAGE_SIZE;
+        // the heap is empty until the program break moves
+        memory_set.heap_bottom = USER_HEAP_BASE;
+        memory_set.brk = USER_HEAP_BASE;
         (
             memory_set,
             user_stack_top,
             elf.header.pt2.entry_point() as usize,
         )
     }
-    /// Copy an identical user_space
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
+    /// Copy an identical user_space, sharing the frames of user pages copy-on-write:
+    /// they are mapped read-only in both spaces until [`MemorySet::handle_page_fault`]
+    /// gives the first writer a copy of its own, unless their area is shared.
+    /// Pages not populated yet stay so in both, except in shared areas.
+    pub fn from_existed_user(user_space: &mut MemorySet) -> MemorySet {
         let mut memory_set = Self::new_bare();
         // map trampoline
         memory_set.map_trampoline();
-        // copy data sections/trap_context/user_stack
-        for area in user_space.areas.iter() {
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.brk = user_space.brk;
+        // share data sections/user_stack, copy trap_context
+        for area in user_space.areas.iter_mut() {
+            if area.shared {
+                // populated at once, as the pages populated later would be private to either space
+                for vpn in area.vpn_range {
+                    if !area.pages.contains_key(&vpn) {
+                        area.populate(&mut user_space.page_table, vpn);
+                    }
+                }
+            }
+            if area.is_lazy() {
+                // the pages of shared areas stay writable in both spaces
+                let shared_flags = if area.shared {
+                    area.pte_flags()
+                } else {
+                    area.pte_flags() - PTEFlags::W
+                };
+                for (vpn, page) in area.pages.iter() {
+                    // pages swapped out are mapped in either space on their next access
+                    if user_space.page_table.translate(*vpn).map_or(false, |pte| pte.is_valid()) {
+                        if !area.shared {
+                            user_space.page_table.set_flags(*vpn, shared_flags);
+                        }
+                        // a page mapped is in memory, so it needs no swapping in
+                        page.map(&mut memory_set.page_table, *vpn, shared_flags);
+                    }
+                }
+                memory_set.areas.push(area.clone());
+                continue;
+            }
+            // the other pages are copied at once
             let new_area = MapArea::from_another(area);
             memory_set.push(new_area, None);
             // copy data from another space
             for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
+                let src_ppn = user_space.page_table.translate(vpn).unwrap().ppn();
                 let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
                 dst_ppn
                     .get_bytes_array()
                     .copy_from_slice(src_ppn.get_bytes_array());
             }
         }
+        // the stale writable mappings of user_space are flushed from the TLB
+        // as the space is activated again when returning to user mode
         memory_set
     }
+    /// Handle a fault of an `access` (one of `R`, `W` or `X`) to a user page: populate
+    /// the page if it is not yet, swap it in if it is swapped out, and make it writable
+    /// for a write if it is shared copy-on-write. Return false if the access is invalid,
+    /// or the page cannot get a frame since memory and swap are full, or be swapped in.
+    pub fn handle_page_fault(&mut self, vpn: VirtPageNum, access: MapPermission) -> bool {
+        let area = match self.areas.iter_mut().find(|area| {
+            area.is_lazy() && vpn >= area.vpn_range.get_start() && vpn < area.vpn_range.get_end()
+        }) {
+            Some(area) => area,
+            None => return false,
+        };
+        if !area.map_perm.contains(access) {
+            return false;
+        }
+        if !area.pages.contains_key(&vpn) {
+            return area.populate(&mut self.page_table, vpn);
+        }
+        let flags = area.pte_flags();
+        let page = area.pages.get_mut(&vpn).unwrap();
+        // copy-on-write, unless the area is shared, the pins of the page by this space
+        // holding no other space sharing it
+        let pins = self.pinned.iter().filter(|(_, p)| Arc::ptr_eq(p, page)).count();
+        let shared = !area.shared && Arc::strong_count(page) > 1 + pins;
+        match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() => {
+                let mut pte_flags = pte.flags() | PTEFlags::A;
+                if access == MapPermission::W {
+                    pte_flags |= PTEFlags::D;
+                }
+                if pte_flags_allow(pte_flags, access) {
+                    // the hardware may leave setting the accessed and dirty bits to us,
+                    // but once they are set the PTE lets the access through, so the fault
+                    // is a real one and retrying the access would fault again
+                    if pte_flags == pte.flags() {
+                        return false;
+                    }
+                    self.page_table.set_flags(vpn, pte_flags);
+                    return true;
+                }
+            }
+            _ => {
+                if !page.map(&mut self.page_table, vpn, if shared { flags - PTEFlags::W } else { flags }) {
+                    return false;
+                }
+                if access != MapPermission::W || !shared {
+                    return true;
+                }
+            }
+        }
+        if shared {
+            let copy = match page.copy() {
+                Some(copy) => copy,
+                None => return false,
+            };
+            page.unmap(&mut self.page_table, vpn);
+            copy.map(&mut self.page_table, vpn, flags);
+            *page = copy;
+        } else {
+            self.page_table.set_flags(vpn, flags);
+        }
+        true
+    }
+    /// Get the frame of a user page for an `access` by the kernel, handling the fault first
+    /// if the page table does not allow the access, as the kernel bypasses it. The page is
+    /// kept from being swapped out for the task at address `holder` until [`MemorySet::unpin`].
+    /// Return None if the access is invalid.
+    pub fn pin(&mut self, vpn: VirtPageNum, access: MapPermission, holder: usize) -> Option<PhysPageNum> {
+        let accessible = self.page_table.translate(vpn).map_or(false, |pte| {
+            pte_flags_allow(pte.flags(), access)
+        });
+        if !accessible && !self.handle_page_fault(vpn, access) {
+            return None;
+        }
+        let page = self.areas.iter().find_map(|area| area.pages.get(&vpn))?;
+        if !self.pinned.iter().any(|(h, p)| *h == holder && Arc::ptr_eq(p, page)) {
+            page.pin();
+            self.pinned.push((holder, Arc::clone(page)));
+        }
+        Some(page.ppn())
+    }
+    /// Let the user pages pinned for the task at address `holder` be swapped out again
+    pub fn unpin(&mut self, holder: usize) {
+        self.pinned.retain(|(h, page)| {
+            if *h == holder {
+                page.unpin();
+            }
+            *h != holder
+        });
+    }
     pub fn activate(&self) {
         let satp = self.page_table.token();
         unsafe {
@@ -247,6 +486,14 @@ impl MemorySet {
     }
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
+        for (_, page) in self.pinned.drain(..) {
+            page.unpin();
+        }
+        // user pages may outlive the space, shared with a forked one,
+        // and those of shared file areas are written back
+        for area in self.areas.iter_mut().filter(|area| area.is_lazy()) {
+            area.unmap(&mut self.page_table);
+        }
         self.areas.clear();
     }
     pub fn kernel_copy() -> Self {
@@ -254,15 +501,46 @@ impl MemorySet {
         Self {
             page_table: PageTable::from_token(kernel_token()),
             areas: areas,
+            pinned: Vec::new(),
+            heap_bottom: 0,
+            brk: 0,
         }
     }
 }
 
+impl Drop for MemorySet {
+    fn drop(&mut self) {
+        self.recycle_data_pages();
+    }
+}
+
+/// Whether a PTE with `pte_flags` lets an `access` (one of `R`, `W` or `X`) through without
+/// a page fault, which takes the accessed bit, and the dirty bit for a write, to be set
+fn pte_flags_allow(pte_flags: PTEFlags, access: MapPermission) -> bool {
+    let needed = if access == MapPermission::W {
+        PTEFlags::R | PTEFlags::W | PTEFlags::A | PTEFlags::D
+    } else if access == MapPermission::X {
+        PTEFlags::X | PTEFlags::A
+    } else {
+        PTEFlags::R | PTEFlags::A
+    };
+    pte_flags.contains(PTEFlags::V | needed)
+}
+
 /// map area structure, controls a contiguous piece of virtual memory
 #[derive(Clone)]
 pub struct MapArea {
     vpn_range: VPNRange,
     data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// pages of a lazily populated area, shared with the spaces forked from or by this one
+    /// until written to
+    pages: BTreeMap<VirtPageNum, Arc<UserPage>>,
+    /// initial contents of a lazily populated area, start-aligned but maybe shorter
+    data: Option<Arc<[u8]>>,
+    /// file contents mapped by a [`MapType::File`] area
+    file: Option<MappedFile>,
+    /// the pages are shared with the spaces forked from or by this one even when written to
+    shared: bool,
     map_type: MapType,
     map_perm: MapPermission,
 }
@@ -279,6 +557,10 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(start_vpn, end_vpn),
             data_frames: BTreeMap::new(),
+            pages: BTreeMap::new(),
+            data: None,
+            file: None,
+            shared: false,
             map_type,
             map_perm,
         }
@@ -287,6 +569,10 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
+            pages: BTreeMap::new(),
+            data: None,
+            file: None,
+            shared: false,
             map_type: another.map_type,
             map_perm: another.map_perm,
         }
@@ -302,12 +588,20 @@ impl MapArea {
                 ppn = frame.ppn;
                 self.data_frames.insert(vpn, frame);
             }
+            MapType::File => unreachable!("file areas are populated on demand"),
         }
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        page_table.map(vpn, ppn, self.pte_flags());
     }
 
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        if self.is_lazy() {
+            // the page may never have been accessed, or be swapped out
+            if let Some(page) = self.pages.remove(&vpn) {
+                self.write_back(vpn, &page);
+                page.unmap(page_table, vpn);
+            }
+            return;
+        }
         #[allow(clippy::single_match)]
         match self.map_type {
             MapType::Framed => {
@@ -317,12 +611,98 @@ impl MapArea {
         }
         page_table.unmap(vpn);
     }
+    /// Whether the pages are populated on their first access rather than mapped at once,
+    /// which is the case for user pages: the kernel accesses the other pages, like trap
+    /// contexts, through their frames, so their faults could never be noticed.
+    pub fn is_lazy(&self) -> bool {
+        self.map_type == MapType::File
+            || self.map_type == MapType::Framed && self.map_perm.contains(MapPermission::U)
+    }
+    /// Get the flags of the PTEs mapping the area, a writable area being readable as well
+    /// since RISC-V reserves PTEs which are writable but not readable
+    fn pte_flags(&self) -> PTEFlags {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        if pte_flags.contains(PTEFlags::W) {
+            pte_flags | PTEFlags::R
+        } else {
+            pte_flags
+        }
+    }
+    /// Map a lazily populated page on its first access, filled with its initial contents.
+    /// Return false if they cannot be read from the file.
+    fn populate(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let page = match UserPage::new() {
+            Some(page) => page,
+            None => return false,
+        };
+        let start = (vpn.0 - self.vpn_range.get_start().0) * PAGE_SIZE;
+        if let Some(data) = &self.data {
+            if start < data.len() {
+                let src = &data[start..data.len().min(start + PAGE_SIZE)];
+                page.ppn().get_bytes_array()[..src.len()].copy_from_slice(src);
+            }
+        }
+        if let Some(file) = &self.file {
+            // the part past the end of the file stays zeroed
+            if file.inode.read_at(file.offset + start, page.ppn().get_bytes_array()).is_err() {
+                return false;
+            }
+        }
+        page.map(page_table, vpn, self.pte_flags());
+        self.pages.insert(vpn, page);
+        true
+    }
+    /// Write a page of a shared file area back to the file, up to the end of the file
+    fn write_back(&self, vpn: VirtPageNum, page: &Arc<UserPage>) {
+        let file = match &self.file {
+            Some(file) if self.shared => file,
+            _ => return,
+        };
+        let offset = file.offset + (vpn.0 - self.vpn_range.get_start().0) * PAGE_SIZE;
+        let size = match file.inode.metadata() {
+            Ok(metadata) => metadata.size as usize,
+            Err(_) => return,
+        };
+        if offset < size {
+            // nobody is left to report a failure to, like that of swapping the page in
+            if let Some(ppn) = page.load_ppn() {
+                let len = PAGE_SIZE.min(size - offset);
+                let _ = file.inode.write_at(offset, &ppn.get_bytes_array()[..len]);
+            }
+        }
+    }
+    /// Shrink the area to end at `new_end`, unmapping the pages past it
+    pub fn shrink_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        for vpn in VPNRange::new(new_end, self.vpn_range.get_end()) {
+            self.unmap_one(page_table, vpn);
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+    }
+    /// Grow the area to end at `new_end`, mapping the pages past the old end
+    pub fn append_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        if !self.is_lazy() {
+            for vpn in VPNRange::new(self.vpn_range.get_end(), new_end) {
+                self.map_one(page_table, vpn);
+            }
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+    }
     pub fn map(&mut self, page_table: &mut PageTable) {
+        if self.is_lazy() {
+            return;
+        }
         for vpn in self.vpn_range {
             self.map_one(page_table, vpn);
         }
     }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
+        if self.is_lazy() {
+            for (vpn, page) in core::mem::take(&mut self.pages) {
+                self.write_back(vpn, &page);
+                page.unmap(page_table, vpn);
+            }
+            return;
+        }
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
@@ -352,10 +732,19 @@ impl MapArea {
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
-/// map type for memory set: identical or framed
+/// map type for memory set: identical, framed or mapping a file
 pub enum MapType {
     Identical,
     Framed,
+    File,
+}
+
+/// The contents of a file mapped by a [`MapType::File`] area
+#[derive(Clone)]
+pub struct MappedFile {
+    pub inode: Arc<Inode>,
+    /// offset in the file of the start of the area, page-aligned
+    pub offset: usize,
 }
 
 bitflags! {
@@ -12,14 +12,16 @@ mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+mod swap;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 pub use address::{StepByOne, VPNRange};
 pub use frame_allocator::{frame_alloc, frame_dealloc, FrameTracker};
 pub use memory_set::{remap_test, kernel_token};
-pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
-pub use page_table::{translated_byte_buffer, translated_refmut, translated_ref, translated_str, PageTableEntry};
+pub use memory_set::{MapPermission, MappedFile, MemorySet, KERNEL_SPACE};
+pub use page_table::{translated_byte_buffer, translated_byte_buffer_mut, translated_refmut, translated_ref, translated_str, PageTableEntry};
 pub use page_table::{PTEFlags, PageTable, UserBuffer};
+pub use swap::{init_swap, UserPage};
 
 /// initiate heap allocator, frame allocator and kernel space
 pub fn init() {
@@ -1,6 +1,9 @@
 //! Implementation of [`PageTableEntry`] and [`PageTable`].
 
 use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+use super::MapPermission;
+use crate::task::current_task;
+use alloc::sync::Arc;
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
@@ -126,6 +129,12 @@ impl PageTable {
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
     }
+    /// Change the flags of a mapped page, keeping its frame
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before changing its flags", vpn);
+        *pte = PageTableEntry::new(pte.ppn(), flags | PTEFlags::V);
+    }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn).copied()
     }
@@ -144,16 +153,30 @@ impl PageTable {
     }
 }
 
-/// translate a pointer to a mutable u8 Vec through page table
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table = PageTable::from_token(token);
+/// Translate a page of the user space of the current task, which `token` has to be of, for
+/// an `access` (one of `R` or `W`) by the kernel, pinning the page in memory until the
+/// syscall of the current task returns. Return None if the access is invalid.
+fn translate_user_vpn(token: usize, vpn: VirtPageNum, access: MapPermission) -> Option<PhysPageNum> {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let mut inner = process.inner_exclusive_access();
+    assert_eq!(inner.memory_set.token(), token, "not the user space of the current task");
+    inner.memory_set.pin(vpn, access, Arc::as_ptr(&task) as usize)
+}
+
+fn translated_user_buffer(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+    access: MapPermission,
+) -> Option<Vec<&'static mut [u8]>> {
     let mut start = ptr as usize;
     let end = start + len;
     let mut v = Vec::new();
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let ppn = translate_user_vpn(token, vpn, access)?;
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
         end_va = end_va.min(VirtAddr::from(end));
@@ -164,18 +187,35 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
         }
         start = end_va.into();
     }
-    v
+    Some(v)
+}
+
+/// translate a pointer to a mutable u8 Vec through page table, for the kernel to read from,
+/// None if the buffer is not readable by the user
+pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Option<Vec<&'static mut [u8]>> {
+    translated_user_buffer(token, ptr, len, MapPermission::R)
+}
+
+/// translate a pointer to a mutable u8 Vec through page table, for the kernel to write to,
+/// None if the buffer is not writable by the user
+pub fn translated_byte_buffer_mut(token: usize, ptr: *mut u8, len: usize) -> Option<Vec<&'static mut [u8]>> {
+    translated_user_buffer(token, ptr, len, MapPermission::W)
+}
+
+/// Translate a user virtual address of the current user space
+fn translate_user_va(token: usize, va: usize, access: MapPermission) -> Option<PhysAddr> {
+    let va = VirtAddr::from(va);
+    let aligned_pa: PhysAddr = translate_user_vpn(token, va.floor(), access)?.into();
+    let aligned_pa_usize: usize = aligned_pa.into();
+    Some((aligned_pa_usize + va.page_offset()).into())
 }
 
-pub fn translated_str(token: usize, ptr: *const u8) -> String {
-    let page_table = PageTable::from_token(token);
+/// Read a nul-terminated string of the user, None if it is not readable
+pub fn translated_str(token: usize, ptr: *const u8) -> Option<String> {
     let mut string = String::new();
     let mut va = ptr as usize;
     loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(va))
-            .unwrap()
-            .get_mut());
+        let ch: u8 = *(translate_user_va(token, va, MapPermission::R)?.get_mut());
         if ch == 0 {
             break;
         } else {
@@ -183,23 +223,17 @@ pub fn translated_str(token: usize, ptr: *const u8) -> String {
             va += 1;
         }
     }
-    string
+    Some(string)
 }
 
-pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
-    let page_table = PageTable::from_token(token);
-    page_table.translate_va(VirtAddr::from(ptr as usize)).unwrap().get_mut()
+/// Translate a pointer to a value of the user, None if it is not readable
+pub fn translated_ref<T>(token: usize, ptr: *const T) -> Option<&'static T> {
+    Some(translate_user_va(token, ptr as usize, MapPermission::R)?.get_mut())
 }
 
-pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
-    //println!("into translated_refmut!");
-    let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
-    //println!("translated_refmut: before translate_va");
-    page_table
-        .translate_va(VirtAddr::from(va))
-        .unwrap()
-        .get_mut()
+/// Translate a pointer to a value of the user, None if it is not writable
+pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> Option<&'static mut T> {
+    Some(translate_user_va(token, ptr as usize, MapPermission::W)?.get_mut())
 }
 
 /// An abstraction over a buffer passed from user space to kernel space
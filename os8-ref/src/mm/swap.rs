@@ -0,0 +1,291 @@
+//! Implementation of [`UserPage`] and swapping of user pages to a swap file on easy-fs.
+//!
+//! Every populated user page is kept in a clock of the [`SwapManager`]. Once free frames
+//! run low, the clock hand passes over the pages, giving a second chance to those accessed
+//! since it last passed and writing the first other one to a slot of the swap file.
+//! The swap file is preallocated at boot, so its slots never need more disk space.
+//! Once it is full, or a page cannot be swapped in, the page fault fails.
+
+use super::frame_allocator::free_frame_count;
+use super::{frame_alloc, FrameTracker, PTEFlags, PageTable, PhysPageNum, VirtPageNum};
+use crate::config::{PAGE_SIZE, SWAP_SIZE};
+use crate::fs::open_swap_file;
+use crate::sync::UPSafeCell;
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use easy_fs::Inode;
+use lazy_static::*;
+
+/// Frames kept free for the kernel, like for page tables and kernel stacks,
+/// which are never swapped out
+const RESERVED_FRAMES: usize = 256;
+
+/// Length of the clock below which the pages gone are kept in it
+const CLOCK_PRUNE_LEN: usize = 1024;
+
+/// A user page, which is either in a frame or in a slot of the swap file.
+/// It is shared copy-on-write by the spaces forked from the same one.
+pub struct UserPage {
+    inner: UPSafeCell<UserPageInner>,
+}
+
+struct UserPageInner {
+    /// frame of the page unless it is swapped out
+    frame: Option<FrameTracker>,
+    /// slot of the swap file holding the page while it is swapped out
+    slot: Option<usize>,
+    /// (page table token, vpn) of each page table entry mapping the frame
+    mappings: Vec<(usize, VirtPageNum)>,
+    /// the kernel is accessing the frame, so it cannot be swapped out
+    pins: usize,
+}
+
+impl UserPage {
+    /// Create a page in a new frame, or return None if there is no frame left
+    pub fn new() -> Option<Arc<Self>> {
+        user_frame_alloc().map(Self::with_frame)
+    }
+    fn with_frame(frame: FrameTracker) -> Arc<Self> {
+        let page = Arc::new(Self {
+            inner: unsafe {
+                UPSafeCell::new(UserPageInner {
+                    frame: Some(frame),
+                    slot: None,
+                    // most pages are only ever mapped once
+                    mappings: Vec::with_capacity(1),
+                    pins: 0,
+                })
+            },
+        });
+        SWAP_MANAGER.exclusive_access().track(&page);
+        page
+    }
+    /// Create a page in a new frame holding a copy of the page,
+    /// or return None if there is no frame left or the page cannot be swapped in
+    pub fn copy(self: &Arc<Self>) -> Option<Arc<Self>> {
+        let frame = user_frame_alloc()?;
+        if !self.load() {
+            return None;
+        }
+        frame
+            .ppn
+            .get_bytes_array()
+            .copy_from_slice(self.ppn().get_bytes_array());
+        Some(Self::with_frame(frame))
+    }
+    /// Get the frame of the page, which has to be in memory
+    pub fn ppn(&self) -> PhysPageNum {
+        self.inner.exclusive_access().frame.as_ref().unwrap().ppn
+    }
+    /// Get the frame of the page, swapping it in first if it is swapped out,
+    /// or return None if it cannot be swapped in
+    pub fn load_ppn(self: &Arc<Self>) -> Option<PhysPageNum> {
+        self.load().then(|| self.ppn())
+    }
+    /// Swap the page in if it is swapped out,
+    /// return false if there is no frame left or the slot cannot be read
+    fn load(self: &Arc<Self>) -> bool {
+        if self.inner.exclusive_access().frame.is_some() {
+            return true;
+        }
+        // allocate before accessing the page, as other pages may be swapped out meanwhile
+        let frame = match user_frame_alloc() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let mut inner = self.inner.exclusive_access();
+        let mut manager = SWAP_MANAGER.exclusive_access();
+        // the page stays swapped out if its slot cannot be read
+        if !manager.read_slot(inner.slot.unwrap(), frame.ppn.get_bytes_array()) {
+            return false;
+        }
+        let slot = inner.slot.take().unwrap();
+        manager.slots.push(slot);
+        inner.frame = Some(frame);
+        drop(inner);
+        manager.track(self);
+        true
+    }
+    /// Map the page in a page table, swapping it in first if it is swapped out.
+    /// Return false if it cannot be swapped in.
+    pub fn map(self: &Arc<Self>, page_table: &mut PageTable, vpn: VirtPageNum, flags: PTEFlags) -> bool {
+        if !self.load() {
+            return false;
+        }
+        let mut inner = self.inner.exclusive_access();
+        page_table.map(vpn, inner.frame.as_ref().unwrap().ppn, flags);
+        inner.mappings.push((page_table.token(), vpn));
+        true
+    }
+    /// Remove the mapping of the page in a page table, if the page is mapped there
+    pub fn unmap(&self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        let token = page_table.token();
+        let mut inner = self.inner.exclusive_access();
+        if let Some(idx) = inner.mappings.iter().position(|m| *m == (token, vpn)) {
+            inner.mappings.remove(idx);
+            page_table.unmap(vpn);
+        }
+    }
+    pub fn pin(&self) {
+        self.inner.exclusive_access().pins += 1;
+    }
+    pub fn unpin(&self) {
+        self.inner.exclusive_access().pins -= 1;
+    }
+}
+
+impl Drop for UserPage {
+    fn drop(&mut self) {
+        if let Some(slot) = self.inner.exclusive_access().slot.take() {
+            SWAP_MANAGER.exclusive_access().slots.push(slot);
+        }
+    }
+}
+
+/// The clock of user pages in memory and the slots of the swap file
+pub struct SwapManager {
+    /// pages in memory, the clock hand being at the front
+    clock: VecDeque<Weak<UserPage>>,
+    /// swap file, opened by [`init_swap`]
+    file: Option<Arc<Inode>>,
+    /// slots no longer in use
+    slots: Vec<usize>,
+    /// slots used so far
+    slot_count: usize,
+    /// slots preallocated in the swap file
+    slot_limit: usize,
+    /// length of the clock from which pages gone are dropped from it
+    prune_len: usize,
+}
+
+impl SwapManager {
+    /// Put a page now in memory behind the clock hand. The pages gone meanwhile are dropped
+    /// from the clock once it doubles, so that they never pile up.
+    fn track(&mut self, page: &Arc<UserPage>) {
+        if self.clock.len() >= self.prune_len {
+            self.clock.retain(|page| page.strong_count() > 0);
+            self.prune_len = (self.clock.len() * 2).max(CLOCK_PRUNE_LEN);
+        }
+        self.clock.push_back(Arc::downgrade(page));
+    }
+    /// Get a free slot of the swap file, or None if it is full
+    fn alloc_slot(&mut self) -> Option<usize> {
+        if let Some(slot) = self.slots.pop() {
+            return Some(slot);
+        }
+        if self.slot_count == self.slot_limit {
+            return None;
+        }
+        self.slot_count += 1;
+        Some(self.slot_count - 1)
+    }
+    fn read_slot(&mut self, slot: usize, buf: &mut [u8]) -> bool {
+        let file = self.file.as_ref().unwrap();
+        file.read_at(slot * PAGE_SIZE, buf) == Ok(PAGE_SIZE)
+    }
+    fn write_slot(&mut self, slot: usize, buf: &[u8]) -> bool {
+        match &self.file {
+            Some(file) => file.write_at(slot * PAGE_SIZE, buf) == Ok(PAGE_SIZE),
+            None => false,
+        }
+    }
+    /// Move the clock hand to the next page to swap out and swap it out,
+    /// return false if there is none
+    fn swap_out(&mut self) -> bool {
+        // every page is passed at most twice
+        for _ in 0..self.clock.len() * 2 {
+            let page = match self.clock.pop_front().and_then(|page| page.upgrade()) {
+                Some(page) => page,
+                None => continue,
+            };
+            let mut inner = page.inner.exclusive_access();
+            if inner.frame.is_none() {
+                continue;
+            }
+            // second chance for the pages accessed since the clock hand last passed
+            let mut accessed = inner.pins > 0;
+            for (token, vpn) in inner.mappings.iter() {
+                let mut page_table = PageTable::from_token(*token);
+                let flags = page_table.translate(*vpn).unwrap().flags();
+                if flags.contains(PTEFlags::A) {
+                    page_table.set_flags(*vpn, flags - PTEFlags::A);
+                    accessed = true;
+                }
+            }
+            if accessed {
+                drop(inner);
+                self.clock.push_back(Arc::downgrade(&page));
+                continue;
+            }
+            let slot = match self.alloc_slot() {
+                Some(slot) => slot,
+                None => {
+                    drop(inner);
+                    self.clock.push_front(Arc::downgrade(&page));
+                    return false;
+                }
+            };
+            if !self.write_slot(slot, inner.frame.as_ref().unwrap().ppn.get_bytes_array()) {
+                self.slots.push(slot);
+                drop(inner);
+                self.clock.push_front(Arc::downgrade(&page));
+                return false;
+            }
+            for (token, vpn) in inner.mappings.drain(..) {
+                PageTable::from_token(token).unmap(vpn);
+            }
+            inner.frame = None;
+            inner.slot = Some(slot);
+            return true;
+        }
+        false
+    }
+}
+
+lazy_static! {
+    /// swap manager instance through lazy_static!
+    static ref SWAP_MANAGER: UPSafeCell<SwapManager> = unsafe {
+        UPSafeCell::new(SwapManager {
+            clock: VecDeque::new(),
+            file: None,
+            slots: Vec::new(),
+            slot_count: 0,
+            slot_limit: 0,
+            prune_len: CLOCK_PRUNE_LEN,
+        })
+    };
+}
+
+/// Open the swap file, preallocating its slots
+pub fn init_swap() {
+    let (file, size) = match open_swap_file(SWAP_SIZE) {
+        Some(swap_file) => swap_file,
+        None => {
+            println!("[kernel] Cannot open the swap file, no page will be swapped out");
+            return;
+        }
+    };
+    if size < SWAP_SIZE {
+        println!(
+            "[kernel] Only {} of the {} KiB of the swap file fit on the disk",
+            size / 1024,
+            SWAP_SIZE / 1024,
+        );
+    }
+    let mut manager = SWAP_MANAGER.exclusive_access();
+    manager.file = Some(file);
+    manager.slot_limit = size / PAGE_SIZE;
+}
+
+/// Allocate a frame for a user page, swapping other user pages out first
+/// if free frames run low. Return None if nothing can be swapped out,
+/// the frames left being reserved for the kernel.
+fn user_frame_alloc() -> Option<FrameTracker> {
+    while free_frame_count() <= RESERVED_FRAMES {
+        if !SWAP_MANAGER.exclusive_access().swap_out() {
+            return None;
+        }
+    }
+    frame_alloc()
+}
@@ -1,16 +1,28 @@
 //! File and filesystem-related syscalls
 
+use crate::fs::get_xattr;
+use crate::fs::link_file;
+use crate::fs::list_xattr;
+use crate::fs::make_dir;
 use crate::fs::make_pipe;
 use crate::fs::open_file;
+use crate::fs::remove_dir;
+use crate::fs::remove_xattr;
+use crate::fs::set_xattr;
+use crate::fs::unlink_file;
 use crate::fs::OpenFlags;
 use crate::fs::Stat;
+use crate::fs::StatFs;
+use crate::fs::stat_fs;
 use crate::mm::translated_byte_buffer;
+use crate::mm::translated_byte_buffer_mut;
 use crate::mm::translated_refmut;
 use crate::mm::translated_str;
 use crate::mm::UserBuffer;
 use crate::task::current_process;
 use crate::task::current_user_token;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
@@ -23,7 +35,16 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
         let file = file.clone();
         // release current process TCB manually to avoid multi-borrow
         drop(inner);
-        file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        let buffers = match translated_byte_buffer(token, buf, len) {
+            Some(buffers) => buffers,
+            None => return -1,
+        };
+        match file.write(UserBuffer::new(buffers)) {
+            // nothing can be written once the disk is full
+            Some(0) if len > 0 => -1,
+            Some(write_size) => write_size as isize,
+            None => -1,
+        }
     } else {
         -1
     }
@@ -40,7 +61,14 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
         let file = file.clone();
         // release current process TCB manually to avoid multi-borrow
         drop(inner);
-        file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        let buffers = match translated_byte_buffer_mut(token, buf as *mut u8, len) {
+            Some(buffers) => buffers,
+            None => return -1,
+        };
+        match file.read(UserBuffer::new(buffers)) {
+            Some(read_size) => read_size as isize,
+            None => -1,
+        }
     } else {
         -1
     }
@@ -49,7 +77,10 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
 pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let process = current_process();
     let token = current_user_token();
-    let path = translated_str(token, path);
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
     if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
         let mut inner = process.inner_exclusive_access();
         let fd = inner.alloc_fd();
@@ -76,14 +107,22 @@ pub fn sys_close(fd: usize) -> isize {
 pub fn sys_pipe(pipe: *mut usize) -> isize {
     let process = current_process();
     let token = current_user_token();
+    // the pages may have to be populated by the memory set, before any fd is allocated
+    let (read_fd_ref, write_fd_ref) = match (
+        translated_refmut(token, pipe),
+        translated_refmut(token, unsafe { pipe.add(1) }),
+    ) {
+        (Some(read_fd_ref), Some(write_fd_ref)) => (read_fd_ref, write_fd_ref),
+        _ => return -1,
+    };
     let mut inner = process.inner_exclusive_access();
     let (pipe_read, pipe_write) = make_pipe();
     let read_fd = inner.alloc_fd();
     inner.fd_table[read_fd] = Some(pipe_read);
     let write_fd = inner.alloc_fd();
     inner.fd_table[write_fd] = Some(pipe_write);
-    *translated_refmut(token, pipe) = read_fd;
-    *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
+    *read_fd_ref = read_fd;
+    *write_fd_ref = write_fd;
     0
 }
 
@@ -101,14 +140,168 @@ pub fn sys_dup(fd: usize) -> isize {
     new_fd as isize
 }
 
-pub fn sys_fstat(_fd: usize, _st: *mut Stat) -> isize {
-    -1
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        // release current process TCB manually to avoid multi-borrow
+        drop(inner);
+        match (file.stat(), translated_refmut(token, st)) {
+            (Some(stat), Some(st)) => {
+                *st = stat;
+                0
+            }
+            _ => -1,
+        }
+    } else {
+        -1
+    }
+}
+
+pub fn sys_linkat(old_name: *const u8, new_name: *const u8) -> isize {
+    let token = current_user_token();
+    let (old_name, new_name) = match (translated_str(token, old_name), translated_str(token, new_name)) {
+        (Some(old_name), Some(new_name)) => (old_name, new_name),
+        _ => return -1,
+    };
+    if link_file(old_name.as_str(), new_name.as_str()) {
+        0
+    } else {
+        -1
+    }
 }
 
-pub fn sys_linkat(_old_name: *const u8, _new_name: *const u8) -> isize {
-    -1
+/// Remove the directory instead of unlinking a file in `sys_unlinkat`
+const AT_REMOVEDIR: u32 = 0x200;
+
+pub fn sys_unlinkat(name: *const u8, flags: u32) -> isize {
+    let token = current_user_token();
+    let name = match translated_str(token, name) {
+        Some(name) => name,
+        None => return -1,
+    };
+    if flags & AT_REMOVEDIR != 0 {
+        return if remove_dir(name.as_str()) { 0 } else { -1 };
+    }
+    if unlink_file(name.as_str()) {
+        0
+    } else {
+        -1
+    }
 }
 
-pub fn sys_unlinkat(_name: *const u8) -> isize {
-    -1
+pub fn sys_mkdirat(path: *const u8) -> isize {
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    if make_dir(path.as_str()) {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_statfs(path: *const u8, st: *mut StatFs) -> isize {
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    match (stat_fs(path.as_str()), translated_refmut(token, st)) {
+        (Some(stat), Some(st)) => {
+            *st = stat;
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Copy `data` to a user buffer of `size` bytes and return its length,
+/// which is all that is returned if `size` is 0, or -1 if it does not fit
+fn copy_to_user(token: usize, buf: *mut u8, size: usize, data: &[u8]) -> isize {
+    if size == 0 {
+        return data.len() as isize;
+    }
+    if data.len() > size {
+        return -1;
+    }
+    let buffers = match translated_byte_buffer_mut(token, buf, data.len()) {
+        Some(buffers) => buffers,
+        None => return -1,
+    };
+    let mut start = 0;
+    for slice in buffers {
+        slice.copy_from_slice(&data[start..start + slice.len()]);
+        start += slice.len();
+    }
+    data.len() as isize
+}
+
+pub fn sys_setxattr(path: *const u8, name: *const u8, value: *const u8, size: usize) -> isize {
+    let token = current_user_token();
+    let (path, name, value) = match (
+        translated_str(token, path),
+        translated_str(token, name),
+        translated_byte_buffer(token, value, size),
+    ) {
+        (Some(path), Some(name), Some(value)) => (path, name, value.concat()),
+        _ => return -1,
+    };
+    if set_xattr(path.as_str(), name.as_str(), &value) {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_getxattr(path: *const u8, name: *const u8, value: *mut u8, size: usize) -> isize {
+    let token = current_user_token();
+    let (path, name) = match (translated_str(token, path), translated_str(token, name)) {
+        (Some(path), Some(name)) => (path, name),
+        _ => return -1,
+    };
+    match get_xattr(path.as_str(), name.as_str()) {
+        Some(data) => copy_to_user(token, value, size, &data),
+        None => -1,
+    }
+}
+
+/// The names are each terminated by 0
+pub fn sys_listxattr(path: *const u8, list: *mut u8, size: usize) -> isize {
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    match list_xattr(path.as_str()) {
+        Some(names) => {
+            let mut data = Vec::new();
+            for name in names {
+                data.extend_from_slice(name.as_bytes());
+                data.push(0);
+            }
+            copy_to_user(token, list, size, &data)
+        }
+        None => -1,
+    }
+}
+
+pub fn sys_removexattr(path: *const u8, name: *const u8) -> isize {
+    let token = current_user_token();
+    let (path, name) = match (translated_str(token, path), translated_str(token, name)) {
+        (Some(path), Some(name)) => (path, name),
+        _ => return -1,
+    };
+    if remove_xattr(path.as_str(), name.as_str()) {
+        0
+    } else {
+        -1
+    }
 }
@@ -1,8 +1,8 @@
 //! Process management syscalls
 
-use crate::config::MAX_SYSCALL_NUM;
+use crate::config::{MAX_SYSCALL_NUM, PAGE_SIZE, USER_SPACE_END, USER_STACK_SIZE};
 use crate::fs::{open_file, OpenFlags};
-use crate::mm::{translated_ref, translated_refmut, translated_str, PageTable, VirtAddr};
+use crate::mm::{translated_ref, translated_refmut, translated_str, MapPermission, MappedFile, PageTable, VirtAddr};
 use crate::task::{
     current_process, current_task, current_user_token, exit_current_and_run_next,
     suspend_current_and_run_next, TaskStatus,
@@ -57,36 +57,64 @@ pub fn sys_fork() -> isize {
     new_pid as isize
 }
 
-/// Syscall Exec which accepts the elf path
+/// Syscall Exec which accepts the elf path, returning -1 if the path or the arguments
+/// are not readable, or the arguments do not fit on the user stack
 pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
     let token = current_user_token();
-    let path = translated_str(token, path);
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
     let mut args_vec: Vec<String> = Vec::new();
     loop {
-        let arg_str_ptr = *translated_ref(token, args);
+        let arg_str_ptr = match translated_ref(token, args) {
+            Some(arg_str_ptr) => *arg_str_ptr,
+            None => return -1,
+        };
         if arg_str_ptr == 0 {
             break;
         }
-        args_vec.push(translated_str(token, arg_str_ptr as *const u8));
+        match translated_str(token, arg_str_ptr as *const u8) {
+            Some(arg) => args_vec.push(arg),
+            None => return -1,
+        }
         unsafe {
             args = args.add(1);
         }
     }
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        let all_data = app_inode.read_all();
-        let process = current_process();
-        let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
-        argc as isize
-    } else {
-        -1
+    // argv, the strings and the alignment of the stack pointer
+    let args_size = (args_vec.len() + 2) * core::mem::size_of::<usize>()
+        + args_vec.iter().map(|arg| arg.len() + 1).sum::<usize>();
+    if args_size > USER_STACK_SIZE {
+        return -1;
     }
+    let all_data = match open_file(path.as_str(), OpenFlags::RDONLY)
+        .and_then(|app_inode| app_inode.read_all()) {
+        Some(all_data) => all_data,
+        None => return -1,
+    };
+    let process = current_process();
+    let argc = args_vec.len();
+    let loaded = process.exec(all_data.as_slice(), args_vec);
+    // nothing is dropped once the process exits below
+    drop(all_data);
+    drop(process);
+    if !loaded {
+        // the original address space is gone, so there is nothing to return to
+        exit_current_and_run_next(-2);
+    }
+    argc as isize
 }
 
 /// If there is not a child process whose pid is same as given, return -1.
 /// Else if there is a child process but it is still running, return -2.
 pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     let process = current_process();
+    // before any child is reaped, the page may have to be populated by the memory set
+    let exit_code_ref = match translated_refmut(current_user_token(), exit_code_ptr) {
+        Some(exit_code_ref) => exit_code_ref,
+        None => return -1,
+    };
     // find a child process
 
     // ---- access current TCB exclusively
@@ -112,7 +140,7 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         // ++++ temporarily access child TCB exclusively
         let exit_code = child.inner_exclusive_access().exit_code;
         // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
+        *exit_code_ref = exit_code;
         found_pid as isize
     } else {
         -2
@@ -128,11 +156,16 @@ pub fn sys_get_time(_ts: *mut TimeVal, _tz: usize) -> isize {
     //         usec: us % 1_000_000,
     //     };
     // }
-    *translated_refmut(current_user_token(), _ts) = TimeVal {
-        sec: _us / 1_000_000,
-        usec: _us % 1_000_000,
-    };
-    0
+    match translated_refmut(current_user_token(), _ts) {
+        Some(ts) => {
+            *ts = TimeVal {
+                sec: _us / 1_000_000,
+                usec: _us % 1_000_000,
+            };
+            0
+        }
+        None => -1,
+    }
 }
 
 pub fn sys_task_info(_ti: *mut TaskInfo) -> isize {
@@ -143,12 +176,83 @@ pub fn sys_set_priority(_prio: isize) -> isize {
     -1
 }
 
-pub fn sys_mmap(_start: usize, _len: usize, _port: usize) -> isize {
-    -1
+/// Move the program break to `addr`, and return the new one, which stays the same
+/// if `addr` is 0 or invalid
+pub fn sys_brk(addr: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if addr != 0 {
+        inner.memory_set.set_brk(addr);
+    }
+    inner.memory_set.brk() as isize
 }
 
-pub fn sys_munmap(_start: usize, _len: usize) -> isize {
-    -1
+bitflags! {
+    /// Flags for mapping memory
+    pub struct MmapFlags: usize {
+        /// writes are shared with the processes forked, and go to the file on unmap
+        const SHARED = 1 << 0;
+        /// writes go to private copies
+        const PRIVATE = 1 << 1;
+        /// map zeroed pages rather than a file, ignoring `fd` and `offset`
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+/// Map `[start, start + len)` with `prot` (`R = 1`, `W = 2`, `X = 4`) to the file `fd`
+/// from `offset`, or to zeroed pages with [`MmapFlags::ANONYMOUS`].
+/// `W` without `R` is rejected, as RISC-V does not allow such pages.
+/// The pages are populated on their first access.
+pub fn sys_mmap(start: usize, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> isize {
+    let flags = match MmapFlags::from_bits(flags) {
+        Some(flags) if flags.contains(MmapFlags::SHARED) != flags.contains(MmapFlags::PRIVATE) => flags,
+        _ => return -1,
+    };
+    let end = match start.checked_add(len) {
+        Some(end) if end <= USER_SPACE_END => end,
+        _ => return -1,
+    };
+    if start % PAGE_SIZE != 0 || len == 0 || prot & !0x7 != 0 || prot & 0x7 == 0 || prot & 0x3 == 0x2 {
+        return -1;
+    }
+    let permission = MapPermission::from_bits((prot << 1) as u8).unwrap();
+    let shared = flags.contains(MmapFlags::SHARED);
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let file = if flags.contains(MmapFlags::ANONYMOUS) {
+        None
+    } else {
+        let file = match inner.fd_table.get(fd) {
+            Some(Some(file)) if offset % PAGE_SIZE == 0 => file.clone(),
+            _ => return -1,
+        };
+        // writes to a shared mapping end up in the file
+        let writable = file.writable() || !shared || !permission.contains(MapPermission::W);
+        match file.inode() {
+            Some(inode) if file.readable() && writable => Some(MappedFile { inode, offset }),
+            _ => return -1,
+        }
+    };
+    if inner.memory_set.mmap(start.into(), end.into(), permission, shared, file) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Unmap `[start, start + len)`, which has to be made of whole mappings
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    let end = match start.checked_add(len) {
+        Some(end) if start % PAGE_SIZE == 0 && len > 0 => end,
+        _ => return -1,
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner.memory_set.munmap(start.into(), end.into()) {
+        0
+    } else {
+        -1
+    }
 }
 
 //
@@ -146,7 +146,7 @@ lazy_static! {
     /// but we have user_shell, so we don't need to change it.
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
         let inode = open_file("ch8b_initproc", OpenFlags::RDONLY).unwrap();
-        let v = inode.read_all();
+        let v = inode.read_all().unwrap();
         ProcessControlBlock::new(v.as_slice())
     };
 }
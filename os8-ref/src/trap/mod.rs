@@ -15,12 +15,14 @@
 mod context;
 
 use crate::config::TRAMPOLINE;
+use crate::mm::{MapPermission, VirtAddr};
 use crate::syscall::syscall;
 use crate::task::{
-    current_trap_cx, current_trap_cx_user_va, current_user_token, exit_current_and_run_next,
-    suspend_current_and_run_next,
+    current_process, current_task, current_trap_cx, current_trap_cx_user_va, current_user_token,
+    exit_current_and_run_next, suspend_current_and_run_next,
 };
 use crate::timer::{check_timer, set_next_trigger};
+use alloc::sync::Arc;
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
@@ -62,11 +64,23 @@ pub fn trap_handler() -> ! {
             let mut cx = current_trap_cx();
             cx.sepc += 4;
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12], cx.x[13]]);
+            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]]);
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result as usize;
+            // the user pages accessed by the kernel can be swapped out again
+            let task = current_task().unwrap();
+            current_process()
+                .inner_exclusive_access()
+                .memory_set
+                .unpin(Arc::as_ptr(&task) as usize);
         }
+        // the first access to a page not populated yet, or the first write
+        // to a page shared copy-on-write since a fork
+        Trap::Exception(Exception::StorePageFault) if handle_page_fault(stval, MapPermission::W) => {}
+        Trap::Exception(Exception::LoadPageFault) if handle_page_fault(stval, MapPermission::R) => {}
+        Trap::Exception(Exception::InstructionPageFault)
+            if handle_page_fault(stval, MapPermission::X) => {}
         Trap::Exception(Exception::StoreFault)
         | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionFault)
@@ -103,6 +117,15 @@ pub fn trap_handler() -> ! {
     trap_return();
 }
 
+/// Let the memory set of the current process handle a page fault at `va`,
+/// return false if the access is invalid
+fn handle_page_fault(va: usize, access: MapPermission) -> bool {
+    current_process()
+        .inner_exclusive_access()
+        .memory_set
+        .handle_page_fault(VirtAddr::from(va).floor(), access)
+}
+
 #[no_mangle]
 pub fn trap_return() -> ! {
     set_user_trap_entry();
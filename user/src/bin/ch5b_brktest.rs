@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{brk, exit, fork, sbrk, waitpid};
+
+/// 测试 brk/sbrk：堆可以按需增长和收缩，收缩掉的部分不能再访问；
+/// 分配超过 16 KiB 初始堆的内存时，用户库的分配器通过 sbrk 扩展自己。
+/// fork 出的子进程有自己的堆。输出 brktest passed! 就算正确。
+
+const PAGE_SIZE: usize = 4096;
+
+fn wait_for(pid: isize) -> i32 {
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // 不合法的 brk 不改变程序断点
+    let start = sbrk(0);
+    assert!(start > 0);
+    assert_eq!(brk(0), start);
+    assert_eq!(brk(1), start);
+
+    // 增长
+    let old = sbrk((PAGE_SIZE * 4) as isize);
+    assert!(old > 0);
+    let new = sbrk(0);
+    assert_eq!(new, old + (PAGE_SIZE * 4) as isize);
+    let heap = unsafe { core::slice::from_raw_parts_mut(old as usize as *mut u8, PAGE_SIZE * 4) };
+    // 新的页面是 0
+    assert!(heap.iter().all(|b| *b == 0));
+    for (i, b) in heap.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+
+    // 收缩之后，剩下的部分不变，收缩掉的部分不能访问
+    assert_eq!(sbrk(-((PAGE_SIZE * 2) as isize)), new);
+    assert_eq!(sbrk(0), old + (PAGE_SIZE * 2) as isize);
+    assert!(heap[..PAGE_SIZE * 2].iter().enumerate().all(|(i, b)| *b == i as u8));
+    let pid = fork();
+    if pid == 0 {
+        let p = (old as usize + PAGE_SIZE * 3) as *const u8;
+        println!("Should cause error, Test brktest fail!");
+        exit(unsafe { p.read_volatile() } as i32);
+    }
+    assert_eq!(wait_for(pid), -2);
+
+    // 用户库的分配器通过 sbrk 扩展自己
+    let mut v: Vec<usize> = Vec::new();
+    for i in 0..256 * 1024 {
+        v.push(i);
+    }
+    let boxes: Vec<Vec<u8>> = (0..64).map(|i| alloc::vec![i as u8; PAGE_SIZE]).collect();
+    assert!(sbrk(0) > old + (PAGE_SIZE * 2) as isize);
+
+    // 子进程的堆是自己的一份
+    let pid = fork();
+    if pid == 0 {
+        assert!(v.iter().enumerate().all(|(i, x)| *x == i));
+        v.iter_mut().for_each(|x| *x = 0);
+        let more: Vec<u8> = alloc::vec![0xff; 1024 * 1024];
+        assert_eq!(more.len(), 1024 * 1024);
+        exit(0);
+    }
+    assert_eq!(wait_for(pid), 0);
+    assert!(v.iter().enumerate().all(|(i, x)| *x == i));
+    for (i, b) in boxes.iter().enumerate() {
+        assert!(b.iter().all(|x| *x == i as u8));
+    }
+    println!("brktest passed!");
+    0
+}
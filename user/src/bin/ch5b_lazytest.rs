@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, wait};
+
+/// 测试按需分配页面：128 MiB 的数组比物理内存还大，只有访问到的页面才会分配物理页帧。
+/// 真正非法的访问仍然会让进程以 -2 退出。输出 lazytest passed! 就算正确。
+
+const PAGE_SIZE: usize = 4096;
+const SIZE: usize = 128 * 1024 * 1024;
+const STRIDE: usize = 1024 * 1024;
+
+static mut DATA: [u8; SIZE] = [0; SIZE];
+static RODATA: [u8; PAGE_SIZE] = [7; PAGE_SIZE];
+
+/// 在子进程里执行 f，返回子进程的退出码
+fn exit_code_of(f: fn()) -> i32 {
+    if fork() == 0 {
+        f();
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert!(wait(&mut exit_code) > 0);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let data = unsafe { &mut DATA };
+    // 第一次读到的页面是全零的
+    for offset in (0..SIZE).step_by(STRIDE) {
+        assert_eq!(data[offset + 1], 0);
+    }
+    for offset in (0..SIZE).step_by(STRIDE) {
+        data[offset] = (offset / STRIDE) as u8;
+    }
+    for offset in (0..SIZE).step_by(STRIDE) {
+        assert_eq!(data[offset], (offset / STRIDE) as u8);
+    }
+    assert_eq!(RODATA[PAGE_SIZE - 1], 7);
+    // 写只读页面、访问没有映射的地址都是非法的
+    assert_eq!(
+        exit_code_of(|| unsafe {
+            (RODATA.as_ptr() as *mut u8).write_volatile(0);
+        }),
+        -2
+    );
+    assert_eq!(
+        exit_code_of(|| unsafe {
+            #[allow(clippy::zero_ptr)]
+            (0x0 as *mut u8).write_volatile(0);
+        }),
+        -2
+    );
+    assert_eq!(
+        exit_code_of(|| unsafe {
+            (0x0 as *const u8).read_volatile();
+        }),
+        -2
+    );
+    // 子进程按需分配的页面不影响父进程
+    assert_eq!(
+        exit_code_of(|| {
+            let data = unsafe { &mut DATA };
+            data[SIZE - 1] = 1;
+        }),
+        0
+    );
+    assert_eq!(data[SIZE - 1], 0);
+    println!("lazytest passed!");
+    0
+}
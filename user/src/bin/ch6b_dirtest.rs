@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, mkdir, open, read, rmdir, write, OpenFlags};
+
+/// 测试多级目录的创建、路径查找与删除，输出 dir_test passed! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mkdir("dir0\0"), 0);
+    assert_eq!(mkdir("dir0/dir1\0"), 0);
+    // 重复创建或父目录不存在都应失败
+    assert!(mkdir("dir0\0") < 0);
+    assert!(mkdir("nodir/dir1\0") < 0);
+
+    let test_str = "Hello, directory!";
+    let fd = open("dir0/dir1/filea\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, test_str.as_bytes());
+    close(fd);
+
+    // 通过 . 和 .. 访问同一个文件
+    let fd = open("dir0/./dir1/../dir1/filea\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut buffer = [0u8; 100];
+    let read_len = read(fd, &mut buffer) as usize;
+    close(fd);
+    assert_eq!(test_str, core::str::from_utf8(&buffer[..read_len]).unwrap());
+
+    // 非空目录不能删除，空目录可以
+    assert!(rmdir("dir0\0") < 0);
+    assert!(rmdir("dir0/dir1/filea\0") < 0);
+    assert_eq!(mkdir("dir0/dir2\0"), 0);
+    assert_eq!(rmdir("dir0/dir2\0"), 0);
+    assert!(open("dir0/dir2\0", OpenFlags::RDONLY) < 0);
+    println!("dir_test passed!");
+    0
+}
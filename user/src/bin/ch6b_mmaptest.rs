@@ -0,0 +1,153 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fork, mmap_file, munmap, open, pipe, read, unlink, waitpid, write};
+use user_lib::{MmapFlags, OpenFlags};
+
+/// 测试文件映射：MAP_PRIVATE 的写入只在自己的副本里，不影响文件；
+/// MAP_SHARED 的写入与 fork 出的子进程共享，munmap 或退出时写回文件，但不会让文件变长。
+/// 输出 mmaptest passed! 就算正确。
+
+const PAGE_SIZE: usize = 4096;
+const FILE_LEN: usize = PAGE_SIZE * 3 + 100;
+const MAP_LEN: usize = PAGE_SIZE * 4;
+const START: usize = 0x10000000;
+const FNAME: &str = "mmapfile\0";
+const PROT_RW: usize = 0b011;
+
+// 用户栈放不下两个文件大小的数组
+static mut EXPECTED: [u8; FILE_LEN] = [0; FILE_LEN];
+static mut BUF: [u8; FILE_LEN] = [0; FILE_LEN];
+
+fn pattern(i: usize) -> u8 {
+    (i % 251) as u8
+}
+
+fn open_rw() -> usize {
+    let fd = open(FNAME, OpenFlags::RDWR);
+    assert!(fd > 0);
+    fd as usize
+}
+
+/// 用 read 读出整个文件，检查长度没有变
+fn read_file(buf: &mut [u8; FILE_LEN]) {
+    let fd = open(FNAME, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut len = 0;
+    loop {
+        let mut chunk = [0u8; 512];
+        let n = read(fd as usize, &mut chunk);
+        assert!(n >= 0);
+        if n == 0 {
+            break;
+        }
+        let n = n as usize;
+        assert!(len + n <= FILE_LEN);
+        buf[len..len + n].copy_from_slice(&chunk[..n]);
+        len += n;
+    }
+    assert_eq!(len, FILE_LEN);
+    close(fd as usize);
+}
+
+fn check_mapped(map: &[u8], file: &[u8]) {
+    assert_eq!(&map[..FILE_LEN], file);
+    // 文件末尾之后的部分是 0
+    assert!(map[FILE_LEN..].iter().all(|b| *b == 0));
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let expected = unsafe { &mut EXPECTED };
+    let buf = unsafe { &mut BUF };
+    for (i, b) in expected.iter_mut().enumerate() {
+        *b = pattern(i);
+    }
+    let fd = open(FNAME, OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, expected), FILE_LEN as isize);
+    close(fd as usize);
+    let map = unsafe { core::slice::from_raw_parts_mut(START as *mut u8, MAP_LEN) };
+
+    // 不合法的映射
+    let fd = open_rw();
+    assert_eq!(mmap_file(START, MAP_LEN, PROT_RW, MmapFlags::SHARED | MmapFlags::PRIVATE, fd, 0), -1);
+    assert_eq!(mmap_file(START, MAP_LEN, PROT_RW, MmapFlags::empty(), fd, 0), -1);
+    let unknown = unsafe { MmapFlags::from_bits_unchecked(1 << 3) };
+    assert_eq!(mmap_file(START, MAP_LEN, PROT_RW, MmapFlags::SHARED | unknown, fd, 0), -1);
+    // 只写的页在 RISC-V 中不合法
+    assert_eq!(mmap_file(START, MAP_LEN, 0b010, MmapFlags::SHARED, fd, 0), -1);
+    assert_eq!(mmap_file(START, MAP_LEN, PROT_RW, MmapFlags::SHARED, fd, 1), -1);
+    assert_eq!(mmap_file(START, MAP_LEN, PROT_RW, MmapFlags::SHARED, fd + 10, 0), -1);
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(mmap_file(START, MAP_LEN, PROT_RW, MmapFlags::SHARED, pipe_fd[0], 0), -1);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    let ro_fd = open(FNAME, OpenFlags::RDONLY);
+    assert!(ro_fd > 0);
+    // 只读打开的文件不能共享写，但可以私有写
+    assert_eq!(mmap_file(START, MAP_LEN, PROT_RW, MmapFlags::SHARED, ro_fd as usize, 0), -1);
+    assert_eq!(mmap_file(START, MAP_LEN, PROT_RW, MmapFlags::PRIVATE, ro_fd as usize, 0), 0);
+    close(ro_fd as usize);
+
+    // MAP_PRIVATE：写入不影响文件
+    check_mapped(map, expected);
+    map[0] = !map[0];
+    map[PAGE_SIZE * 3 + 50] = 0xff;
+    assert_eq!(munmap(START, MAP_LEN), 0);
+    read_file(buf);
+    assert_eq!(buf, expected);
+
+    // 从第 1 页开始映射
+    assert_eq!(mmap_file(START, PAGE_SIZE, PROT_RW, MmapFlags::PRIVATE, fd, PAGE_SIZE), 0);
+    assert_eq!(&map[..PAGE_SIZE], &expected[PAGE_SIZE..PAGE_SIZE * 2]);
+    assert_eq!(munmap(START, PAGE_SIZE), 0);
+
+    // MAP_SHARED：子进程的写入父进程可见，munmap 时写回文件
+    assert_eq!(mmap_file(START, MAP_LEN, PROT_RW, MmapFlags::SHARED, fd, 0), 0);
+    close(fd);
+    check_mapped(map, expected);
+    map[1] = 0xaa;
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(map[1], 0xaa);
+        map[PAGE_SIZE * 2] = 0xbb;
+        map[PAGE_SIZE * 3 + 99] = 0xcc;
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(map[PAGE_SIZE * 2], 0xbb);
+    // 写在文件末尾之后的部分不会写回
+    map[FILE_LEN + 1] = 0xdd;
+    assert_eq!(munmap(START, MAP_LEN), 0);
+    expected[1] = 0xaa;
+    expected[PAGE_SIZE * 2] = 0xbb;
+    expected[PAGE_SIZE * 3 + 99] = 0xcc;
+    read_file(buf);
+    assert_eq!(buf, expected);
+
+    // MAP_SHARED：进程退出时写回文件
+    let pid = fork();
+    if pid == 0 {
+        let fd = open_rw();
+        assert_eq!(mmap_file(START, MAP_LEN, PROT_RW, MmapFlags::SHARED, fd, 0), 0);
+        close(fd);
+        map[PAGE_SIZE] = 0xee;
+        exit(0);
+    }
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    expected[PAGE_SIZE] = 0xee;
+    read_file(buf);
+    assert_eq!(buf, expected);
+
+    assert_eq!(unlink(FNAME), 0);
+    println!("mmaptest passed!");
+    0
+}
@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, statfs, unlink, write, OpenFlags, StatFs};
+
+/// 测试 statfs 返回的空闲块数与空闲 inode 数，输出 statfs_test passed! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let st = StatFs::new();
+    assert_eq!(statfs("/\0", &st), 0);
+    assert_eq!(st.bsize, 512);
+    assert!(st.bfree <= st.blocks);
+    assert!(st.ffree < st.files);
+    assert!(st.namelen >= 27);
+    assert_eq!(statfs("statfs_missing\0", &st), -1);
+
+    // 创建文件占用一个 inode，写入数据占用数据块
+    let fname = "statfs_file\0";
+    let fd = open(fname, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let created = StatFs::new();
+    assert_eq!(statfs(fname, &created), 0);
+    assert_eq!(created.ffree, st.ffree - 1);
+    let buf = [b'x'; 4 * 512];
+    assert_eq!(write(fd, &buf), buf.len() as isize);
+    close(fd);
+    let written = StatFs::new();
+    assert_eq!(statfs(fname, &written), 0);
+    assert_eq!(written.bfree, created.bfree - 4);
+
+    // 删除后 inode 与数据块全部归还
+    assert_eq!(unlink(fname), 0);
+    let freed = StatFs::new();
+    assert_eq!(statfs("/\0", &freed), 0);
+    assert_eq!(freed.ffree, st.ffree);
+    assert_eq!(freed.bfree, created.bfree);
+    println!("statfs_test passed!");
+    0
+}
@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fstat, open, sleep, unlink, write, OpenFlags, Stat, StatMode};
+
+/// 测试 fstat 返回的大小、权限与时间戳，输出 stat_test passed! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fname = "fstat_meta\0";
+    let fd = open(fname, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let stat = Stat::new();
+    assert_eq!(fstat(fd, &stat), 0);
+    assert_eq!(stat.mode, StatMode::FILE);
+    assert_eq!(stat.perm, 0o644);
+    assert_eq!(stat.size, 0);
+    let created = stat.ctime;
+    assert_eq!(stat.mtime, created);
+
+    // 写入后大小与修改时间随之更新
+    sleep(1500);
+    let test_str = "Hello, metadata!";
+    write(fd, test_str.as_bytes());
+    let stat = Stat::new();
+    assert_eq!(fstat(fd, &stat), 0);
+    assert_eq!(stat.size, test_str.len() as u64);
+    assert!(stat.mtime > created);
+    assert_eq!(stat.ctime, stat.mtime);
+    close(fd);
+    assert_eq!(unlink(fname), 0);
+    println!("stat_test passed!");
+    0
+}
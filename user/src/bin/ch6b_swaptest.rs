@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, wait};
+
+/// 测试页面换出：写满比物理内存更大的数组，放不下的页面被换出到 easy-fs 上的交换文件，
+/// 再次访问时换入，数据不变。fork 出的子进程共享换出的页面，写时复制。
+/// 输出 swaptest passed! 就算正确。
+
+const PAGE_SIZE: usize = 4096;
+/// 128 MiB 物理内存中内核约占 18 MiB，120 MiB 的数组约有 12 MiB 被换出，
+/// 加上子进程复制的 2 MiB，不超过内核预留的 32 MiB 交换文件
+const PAGES: usize = 120 * 1024 * 1024 / PAGE_SIZE;
+const WORDS: usize = PAGE_SIZE / core::mem::size_of::<usize>();
+
+static mut DATA: [[usize; WORDS]; PAGES] = [[0; WORDS]; PAGES];
+
+fn pattern(page: usize) -> usize {
+    page.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+}
+
+fn check(data: &[[usize; WORDS]], page: usize, value: usize) {
+    assert_eq!(data[page][0], value);
+    assert_eq!(data[page][WORDS / 2], !value);
+    assert_eq!(data[page][WORDS - 1], value);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let data = unsafe { &mut DATA };
+    for page in 0..PAGES {
+        data[page][0] = pattern(page);
+        data[page][WORDS / 2] = !pattern(page);
+        data[page][WORDS - 1] = pattern(page);
+    }
+    // 倒序检查，先访问还在内存中的页面
+    for page in (0..PAGES).rev() {
+        check(data, page, pattern(page));
+    }
+    if fork() == 0 {
+        for page in (0..PAGES).step_by(64) {
+            check(data, page, pattern(page));
+            data[page][0] = 0;
+            data[page][WORDS / 2] = !0;
+            data[page][WORDS - 1] = 0;
+        }
+        for page in (0..PAGES).step_by(64) {
+            check(data, page, 0);
+        }
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert!(wait(&mut exit_code) > 0);
+    assert_eq!(exit_code, 0);
+    // 子进程的写不影响父进程
+    for page in 0..PAGES {
+        check(data, page, pattern(page));
+    }
+    println!("swaptest passed!");
+    0
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, getxattr, listxattr, open, removexattr, setxattr, unlink, OpenFlags};
+
+/// 测试扩展属性的设置、读取、列举与删除，输出 xattr_test passed! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fname = "xattr_file\0";
+    let fd = open(fname, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+
+    // 设置后可以读回，长度为 0 的缓冲区只返回值的长度
+    assert_eq!(setxattr(fname, "exit_code\0", b"0"), 0);
+    assert_eq!(setxattr(fname, "category\0", b"fs"), 0);
+    let mut buf = [0u8; 16];
+    assert_eq!(getxattr(fname, "category\0", &mut []), 2);
+    assert_eq!(getxattr(fname, "category\0", &mut buf), 2);
+    assert_eq!(&buf[..2], b"fs");
+    assert_eq!(getxattr(fname, "missing\0", &mut buf), -1);
+    assert_eq!(getxattr(fname, "category\0", &mut buf[..1]), -1);
+
+    // 重新设置会替换原来的值
+    assert_eq!(setxattr(fname, "exit_code\0", b"-1"), 0);
+    assert_eq!(getxattr(fname, "exit_code\0", &mut buf), 2);
+    assert_eq!(&buf[..2], b"-1");
+
+    // 名字以 0 分隔
+    let mut list = [0u8; 64];
+    let len = listxattr(fname, &mut list);
+    assert_eq!(&list[..len as usize], b"exit_code\0category\0");
+    assert_eq!(removexattr(fname, "exit_code\0"), 0);
+    assert_eq!(removexattr(fname, "exit_code\0"), -1);
+    let len = listxattr(fname, &mut list);
+    assert_eq!(&list[..len as usize], b"category\0");
+
+    assert_eq!(setxattr("xattr_missing\0", "category\0", b"fs"), -1);
+    assert_eq!(unlink(fname), 0);
+    println!("xattr_test passed!");
+    0
+}
@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fork, pipe, read, wait, write};
+
+/// 测试写时复制的 fork：父进程有 4 MiB 的数据，fork 出的子进程若都复制一份，内存是不够的。
+/// 子进程自己写数据、内核通过 read 写数据，都不应影响父进程。输出 cowtest passed! 就算正确。
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 1024;
+const NUM: usize = 32;
+
+static mut DATA: [u8; PAGES * PAGE_SIZE] = [0; PAGES * PAGE_SIZE];
+
+fn pattern(page: usize) -> u8 {
+    page as u8 ^ 0x5a
+}
+
+/// 检查除了第 0 页（计数器）和 skip 页之外，每页的数据都没有变
+fn check(data: &[u8], skip: usize) {
+    for page in 1..PAGES {
+        if page == skip {
+            continue;
+        }
+        for offset in (0..PAGE_SIZE).step_by(512) {
+            assert_eq!(data[page * PAGE_SIZE + offset], pattern(page));
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let data = unsafe { &mut DATA };
+    for page in 0..PAGES {
+        data[page * PAGE_SIZE..(page + 1) * PAGE_SIZE].fill(pattern(page));
+    }
+    data[0] = 0;
+    for i in 0..NUM {
+        let mut pipe_fd = [0usize; 2];
+        assert_eq!(pipe(&mut pipe_fd), 0);
+        if fork() == 0 {
+            close(pipe_fd[1]);
+            // 父进程在 fork 之后修改的计数器对子进程不可见
+            assert_eq!(data[0] as usize, i);
+            // 子进程写自己的一页
+            let own = 1 + i * (PAGES - 1) / NUM;
+            data[own * PAGE_SIZE..(own + 1) * PAGE_SIZE].fill(0xff);
+            // 内核通过 read 写最后一页
+            let last = (PAGES - 1) * PAGE_SIZE;
+            let mut len = 0;
+            while len < 8 {
+                let n = read(pipe_fd[0], &mut data[last + len..last + 8]);
+                assert!(n > 0);
+                len += n as usize;
+            }
+            close(pipe_fd[0]);
+            assert_eq!(&data[last..last + 8], &(i as u64).to_le_bytes());
+            assert!(data[own * PAGE_SIZE..(own + 1) * PAGE_SIZE].iter().all(|b| *b == 0xff));
+            check(data, own);
+            exit(0);
+        }
+        data[0] = i as u8 + 1;
+        assert_eq!(write(pipe_fd[1], &(i as u64).to_le_bytes()), 8);
+        close(pipe_fd[0]);
+        close(pipe_fd[1]);
+    }
+    let mut exit_code: i32 = 0;
+    for _ in 0..NUM {
+        assert!(wait(&mut exit_code) > 0);
+        assert_eq!(exit_code, 0);
+    }
+    assert!(wait(&mut exit_code) < 0);
+    // 子进程和内核的写都没有影响父进程
+    assert_eq!(data[0] as usize, NUM);
+    check(data, 0);
+    println!("cowtest passed!");
+    0
+}
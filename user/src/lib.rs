@@ -15,18 +15,53 @@ extern crate bitflags;
 
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
 pub use console::{flush, STDIN, STDOUT};
 pub use syscall::*;
 
 const USER_HEAP_SIZE: usize = 16384;
+/// The heap grows by at least this much once `HEAP_SPACE` runs out
+const USER_HEAP_GROW_SIZE: usize = 0x10000;
 
 static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
 
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: UserHeap = UserHeap(LockedHeap::empty());
+
+/// The heap starting in `HEAP_SPACE`. With the `brk` feature, for kernels implementing
+/// `sys_brk`, it grows through [`sbrk`] once it runs out. Otherwise running out of it
+/// fails the allocation.
+struct UserHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for UserHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        if !cfg!(feature = "brk") {
+            return null_mut();
+        }
+        // twice the size of the block makes sure that an aligned one fits in
+        let size = match layout.size().max(layout.align()).checked_next_power_of_two() {
+            Some(size) if size <= isize::MAX as usize / 2 => (size * 2).max(USER_HEAP_GROW_SIZE),
+            _ => return null_mut(),
+        };
+        let start = sbrk(size as isize);
+        if start < 0 {
+            return null_mut();
+        }
+        heap.add_to_heap(start as usize, start as usize + size);
+        heap.alloc(layout).map_or(null_mut(), |ptr| ptr.as_ptr())
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout);
+    }
+}
 
 #[alloc_error_handler]
-pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
+pub fn handle_alloc_error(layout: Layout) -> ! {
     panic!("Heap allocation error, layout = {:?}", layout);
 }
 
@@ -49,7 +84,7 @@ fn clear_bss() {
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
     clear_bss();
     unsafe {
-        HEAP.lock()
+        HEAP.0.lock()
             .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
     }
     let mut v: Vec<&'static str> = Vec::new();
@@ -85,6 +120,17 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct MmapFlags: u32 {
+        /// writes are shared with the processes forked, and go to the file on munmap or exit
+        const SHARED = 1 << 0;
+        /// writes go to private copies
+        const PRIVATE = 1 << 1;
+        /// zeroed pages rather than a file
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct TimeVal {
@@ -142,8 +188,22 @@ pub struct Stat {
     pub mode: StatMode,
     /// number of hard links
     pub nlink: u32,
+    /// size in bytes
+    pub size: u64,
+    /// time of last access, in seconds
+    pub atime: u64,
+    /// time of last modification, in seconds
+    pub mtime: u64,
+    /// time of last status change, in seconds
+    pub ctime: u64,
+    /// permission bits
+    pub perm: u32,
+    /// user ID of owner
+    pub uid: u32,
+    /// group ID of owner
+    pub gid: u32,
     /// unused pad
-    pad: [u64; 7],
+    pad: [u32; 3],
 }
 
 impl Stat {
@@ -153,7 +213,14 @@ impl Stat {
             ino: 0,
             mode: StatMode::NULL,
             nlink: 0,
-            pad: [0; 7],
+            size: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            perm: 0,
+            uid: 0,
+            gid: 0,
+            pad: [0; 3],
         }
     }
 }
@@ -164,6 +231,42 @@ impl Default for Stat {
     }
 }
 
+#[repr(C)]
+#[derive(Debug)]
+pub struct StatFs {
+    /// size of a block in bytes
+    pub bsize: u64,
+    /// number of data blocks
+    pub blocks: u64,
+    /// number of free data blocks
+    pub bfree: u64,
+    /// number of inodes
+    pub files: u64,
+    /// number of free inodes
+    pub ffree: u64,
+    /// max length of file names
+    pub namelen: u64,
+}
+
+impl StatFs {
+    pub fn new() -> Self {
+        StatFs {
+            bsize: 0,
+            blocks: 0,
+            bfree: 0,
+            files: 0,
+            ffree: 0,
+            namelen: 0,
+        }
+    }
+}
+
+impl Default for StatFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 bitflags! {
     pub struct StatMode: u32 {
         const NULL  = 0;
@@ -171,10 +274,13 @@ bitflags! {
         const DIR   = 0o040000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// symbolic link
+        const LINK  = 0o120000;
     }
 }
 
 const AT_FDCWD: isize = -100;
+const AT_REMOVEDIR: usize = 0x200;
 
 pub fn open(path: &str, flags: OpenFlags) -> isize {
     sys_openat(AT_FDCWD as usize, path, flags.bits, OpenFlags::RDWR.bits)
@@ -203,10 +309,38 @@ pub fn unlink(path: &str) -> isize {
     sys_unlinkat(AT_FDCWD as usize, path, 0)
 }
 
+pub fn mkdir(path: &str) -> isize {
+    sys_mkdirat(AT_FDCWD as usize, path, 0)
+}
+
+pub fn rmdir(path: &str) -> isize {
+    sys_unlinkat(AT_FDCWD as usize, path, AT_REMOVEDIR)
+}
+
 pub fn fstat(fd: usize, st: &Stat) -> isize {
     sys_fstat(fd, st)
 }
 
+pub fn statfs(path: &str, st: &StatFs) -> isize {
+    sys_statfs(path, st)
+}
+
+pub fn setxattr(path: &str, name: &str, value: &[u8]) -> isize {
+    sys_setxattr(path, name, value)
+}
+
+pub fn getxattr(path: &str, name: &str, value: &mut [u8]) -> isize {
+    sys_getxattr(path, name, value)
+}
+
+pub fn listxattr(path: &str, list: &mut [u8]) -> isize {
+    sys_listxattr(path, list)
+}
+
+pub fn removexattr(path: &str, name: &str) -> isize {
+    sys_removexattr(path, name)
+}
+
 pub fn mail_read(buf: &mut [u8]) -> isize {
     sys_mail_read(buf)
 }
@@ -284,8 +418,37 @@ pub fn sleep(period_ms: usize) {
         sys_yield();
     }
 }
+/// Set the program break, return the new one. Without the `brk` feature the kernel
+/// does not implement `sys_brk`, so -1 is returned instead of calling it.
+pub fn brk(addr: usize) -> isize {
+    if cfg!(feature = "brk") {
+        sys_brk(addr)
+    } else {
+        -1
+    }
+}
+
+/// Move the program break by `increment`, return the old one, or -1 if it cannot move
+pub fn sbrk(increment: isize) -> isize {
+    let old_brk = brk(0);
+    if increment == 0 || old_brk < 0 {
+        return old_brk;
+    }
+    let new_brk = (old_brk as usize).wrapping_add(increment as usize);
+    if brk(new_brk) == new_brk as isize {
+        old_brk
+    } else {
+        -1
+    }
+}
+
 pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
-    sys_mmap(start, len, prot)
+    let flags = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS;
+    sys_mmap(start, len, prot, flags.bits, 0, 0)
+}
+
+pub fn mmap_file(start: usize, len: usize, prot: usize, flags: MmapFlags, fd: usize, offset: usize) -> isize {
+    sys_mmap(start, len, prot, flags.bits, fd, offset)
 }
 
 pub fn munmap(start: usize, len: usize) -> isize {
@@ -1,13 +1,19 @@
 use crate::TaskInfo;
 
-use super::{Stat, TimeVal};
+use super::{Stat, StatFs, TimeVal};
 
+pub const SYSCALL_SETXATTR: usize = 5;
+pub const SYSCALL_GETXATTR: usize = 8;
+pub const SYSCALL_LISTXATTR: usize = 11;
+pub const SYSCALL_REMOVEXATTR: usize = 14;
 pub const SYSCALL_OPENAT: usize = 56;
 pub const SYSCALL_CLOSE: usize = 57;
 pub const SYSCALL_READ: usize = 63;
 pub const SYSCALL_WRITE: usize = 64;
+pub const SYSCALL_MKDIRAT: usize = 34;
 pub const SYSCALL_UNLINKAT: usize = 35;
 pub const SYSCALL_LINKAT: usize = 37;
+pub const SYSCALL_STATFS: usize = 43;
 pub const SYSCALL_FSTAT: usize = 80;
 pub const SYSCALL_EXIT: usize = 93;
 pub const SYSCALL_SLEEP: usize = 101;
@@ -19,6 +25,7 @@ pub const SYSCALL_FORK: usize = 220;
 pub const SYSCALL_EXEC: usize = 221;
 pub const SYSCALL_WAITPID: usize = 260;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
+pub const SYSCALL_BRK: usize = 214;
 pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
 pub const SYSCALL_SPAWN: usize = 400;
@@ -123,10 +130,40 @@ pub fn sys_unlinkat(dirfd: usize, path: &str, flags: usize) -> isize {
     syscall(SYSCALL_UNLINKAT, [dirfd, path.as_ptr() as usize, flags])
 }
 
+pub fn sys_mkdirat(dirfd: usize, path: &str, mode: u32) -> isize {
+    syscall(SYSCALL_MKDIRAT, [dirfd, path.as_ptr() as usize, mode as usize])
+}
+
 pub fn sys_fstat(fd: usize, st: &Stat) -> isize {
     syscall(SYSCALL_FSTAT, [fd, st as *const _ as usize, 0])
 }
 
+pub fn sys_statfs(path: &str, st: &StatFs) -> isize {
+    syscall(SYSCALL_STATFS, [path.as_ptr() as usize, st as *const _ as usize, 0])
+}
+
+pub fn sys_setxattr(path: &str, name: &str, value: &[u8]) -> isize {
+    syscall6(
+        SYSCALL_SETXATTR,
+        [path.as_ptr() as usize, name.as_ptr() as usize, value.as_ptr() as usize, value.len(), 0, 0],
+    )
+}
+
+pub fn sys_getxattr(path: &str, name: &str, value: &mut [u8]) -> isize {
+    syscall6(
+        SYSCALL_GETXATTR,
+        [path.as_ptr() as usize, name.as_ptr() as usize, value.as_mut_ptr() as usize, value.len(), 0, 0],
+    )
+}
+
+pub fn sys_listxattr(path: &str, list: &mut [u8]) -> isize {
+    syscall(SYSCALL_LISTXATTR, [path.as_ptr() as usize, list.as_mut_ptr() as usize, list.len()])
+}
+
+pub fn sys_removexattr(path: &str, name: &str) -> isize {
+    syscall(SYSCALL_REMOVEXATTR, [path.as_ptr() as usize, name.as_ptr() as usize, 0])
+}
+
 pub fn sys_mail_read(buffer: &mut [u8]) -> isize {
     syscall(
         SYSCALL_MAIL_READ,
@@ -181,8 +218,12 @@ pub fn sys_set_priority(prio: isize) -> isize {
     syscall(SYSCALL_SET_PRIORITY, [prio as usize, 0, 0])
 }
 
-pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
-    syscall(SYSCALL_MMAP, [start, len, prot])
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
+pub fn sys_mmap(start: usize, len: usize, prot: usize, flags: u32, fd: usize, offset: usize) -> isize {
+    syscall6(SYSCALL_MMAP, [start, len, prot, flags as usize, fd, offset])
 }
 
 pub fn sys_munmap(start: usize, len: usize) -> isize {